@@ -1,22 +1,46 @@
+use std::collections::VecDeque;
+
 use axum::{
+    body::{
+        Bytes,
+        StreamBody,
+    },
     extract::{State, RawBody},
+    http::header,
     Json,
     response::IntoResponse,
 };
+use chrono::{
+    DateTime,
+    Utc,
+};
+use docker_protobuf::LogEntry;
 use serde::Deserialize;
 use serde_json::json;
+use tokio::sync::mpsc::Sender;
+use tokio_stream::wrappers::ReceiverStream;
 use tracing::{warn, info};
 
-use crate::{task::FifoProcessor, error::HttpError};
+use crate::{
+    container::ContainerInfo,
+    error::HttpError,
+    store::{
+        encode_frame,
+        LogStoreReader,
+        Position,
+    },
+    task::FifoProcessor,
+};
 
 use super::AppState;
 
 
-#[derive(Deserialize)]
-pub struct StartLoggingInfo {
-    #[serde(rename = "ContainerID")]
-    pub container_id: String,
-}
+// Number of encoded entries buffered between the store reader and the response body
+const READ_LOGS_BUFFER: usize = 64;
+
+// How often a following reader polls the store for new entries
+const FOLLOW_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
 
 #[derive(Deserialize)]
 pub struct StartLoggingPayload {
@@ -24,7 +48,7 @@ pub struct StartLoggingPayload {
     pub file: String,
 
     #[serde(rename = "Info")]
-    pub info: StartLoggingInfo,
+    pub info: ContainerInfo,
 }
 
 #[derive(Deserialize)]
@@ -33,6 +57,57 @@ pub struct StopLoggingPayload {
     pub file: String,
 }
 
+#[derive(Deserialize)]
+pub struct ReadConfig {
+    #[serde(rename = "Since", default)]
+    pub since: Option<DateTime<Utc>>,
+
+    #[serde(rename = "Until", default)]
+    pub until: Option<DateTime<Utc>>,
+
+    // Negative values return all entries
+    #[serde(rename = "Tail", default = "ReadConfig::default_tail")]
+    pub tail: i64,
+
+    #[serde(rename = "Follow", default)]
+    pub follow: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ReadLogsPayload {
+    #[serde(rename = "Config")]
+    pub config: ReadConfig,
+
+    #[serde(rename = "Info")]
+    pub info: ContainerInfo,
+}
+
+
+impl ReadConfig {
+    fn default_tail() -> i64 {
+        -1
+    }
+
+    // Docker sends the zero value of time.Time (0001-01-01T00:00:00Z) when unset
+    fn as_nanos(dt: &Option<DateTime<Utc>>) -> Option<i64> {
+        dt.filter(|dt| dt.timestamp() > 0)
+            .map(|dt| dt.timestamp_nanos())
+    }
+
+    fn is_before_since(&self, entry: &LogEntry) -> bool {
+        Self::as_nanos(&self.since)
+            .map(|since| entry.time_nano < since)
+            .unwrap_or(false)
+    }
+
+    fn is_after_until(&self, entry: &LogEntry) -> bool {
+        Self::as_nanos(&self.until)
+            .map(|until| entry.time_nano > until)
+            .unwrap_or(false)
+    }
+}
+
+
 pub struct LogDriver;
 
 
@@ -48,18 +123,51 @@ impl LogDriver {
                 .map_err(|_| HttpError::bad_request(None))?
         ).map_err(|_| HttpError::bad_request(None))?;
 
-        let task: T = T::new(
+        let config = state
+            .config
+            .with_log_opts(&payload.info.config)
+            .map_err(|e| {
+                warn!(
+                    container_id = payload.info.container_id.as_str(),
+                    "Rejecting log-opts for container {}: {}", payload.info.container_id, e,
+                );
+
+                HttpError::invalid_config(Some(e.to_string()))
+            })?;
+
+        let mut task: T = T::new(
+            config,
+            payload
+                .info
+                .clone(),
             state
-                .config
-                .clone()
+                .clients
+                .clone(),
         );
 
+        task.open(&payload.file)
+            .await
+            .map_err(|e| {
+                warn!(
+                    fpath = payload.file.as_str(),
+                    "Failed to open {}: {}", payload.file, e,
+                );
+
+                HttpError::internal(Some(format!("Failed to open {}: {}", payload.file, e)))
+            })?;
+
         state
             .add_task_flag(
                 &payload.file, 
                 tx,
         ).await;
 
+        state
+            .add_container(
+                &payload.file,
+                &payload.info.container_id,
+        ).await;
+
         // Spawn the task to process the fifo file
         tokio::spawn(task.process(payload.file, rx));
         Ok(Json(json!({"Err": ""})))
@@ -78,6 +186,10 @@ impl LogDriver {
         let flag = state
             .take_task_flag(&payload.file)
             .await;
+
+        state
+            .remove_container(&payload.file)
+            .await;
         
         match flag {
             Some(flag) => {
@@ -87,7 +199,7 @@ impl LogDriver {
                     payload.file,
                 );
 
-                if let Err(_) = flag.send(true) {
+                if flag.send(true).is_err() {
                     warn!(
                         fpath = payload.file.as_str(),
                         "Signal receiver dropped; task panic, deadlocked or complete for container logging to {}", 
@@ -108,6 +220,172 @@ impl LogDriver {
             },
         }
     }
+
+    pub async fn capabilities() -> impl IntoResponse {
+        Json(json!({"Cap": {"ReadLogs": true}}))
+    }
+
+    pub async fn read_logs(
+        State(state): State<AppState>,
+        RawBody(payload): RawBody,
+    ) -> Result<impl IntoResponse, HttpError> {
+        let payload: ReadLogsPayload = serde_json::from_slice(
+            &hyper::body::to_bytes(payload)
+                .await
+                .map_err(|_| HttpError::bad_request(None))?
+        ).map_err(|_| HttpError::bad_request(None))?;
+
+        // the store was written with the container's own max-frame-size, if it set one
+        let max_frame_size = state
+            .config
+            .with_log_opts(&payload.info.config)
+            .map(|config| config.max_frame_size)
+            .unwrap_or(state.config.max_frame_size);
+
+        let container_id = payload
+            .info
+            .container_id;
+
+        let reader = LogStoreReader::open(&state.config.state_dir, &container_id)
+            .await
+            .map_err(|_| HttpError::not_found(Some(format!("No logs found for container {}", container_id))))?
+            .with_max_frame_size(max_frame_size.0 as usize);
+
+        let (tx, rx) = tokio::sync::mpsc::channel(READ_LOGS_BUFFER);
+
+        tokio::spawn(stream_logs(state, container_id, payload.config, reader, tx));
+
+        Ok((
+            [(header::CONTENT_TYPE, "application/x-json-stream")],
+            StreamBody::new(ReceiverStream::new(rx)),
+        ))
+    }
+}
+
+
+async fn start_position(config: &ReadConfig, reader: &LogStoreReader) -> Result<Position, std::io::Error> {
+    let mut position = reader.position();
+
+    if let Some(since) = ReadConfig::as_nanos(&config.since) {
+        position = position.max(reader.position_since(since).await?);
+    }
+
+    // with `Until`, the tail is counted back from it while reading instead
+    if config.tail >= 0 && ReadConfig::as_nanos(&config.until).is_none() {
+        position = position.max(reader.position_tail(config.tail as usize).await?);
+    }
+
+    Ok(position)
+}
+
+
+/// Streams the entries in a container's store to `tx` as length-prefixed frames.
+/// Stops once the store is exhausted or, when following, once the container stops
+/// logging or the daemon hangs up.
+async fn stream_logs(
+    state: AppState,
+    container_id: String,
+    config: ReadConfig,
+    mut reader: LogStoreReader,
+    tx: Sender<Result<Bytes, std::io::Error>>,
+) {
+    // Only a bounded `Tail` needs buffering, to drop entries `Until` excludes at the end
+    let mut backlog: VecDeque<LogEntry> = VecDeque::new();
+
+    // Seek past entries excluded by `Since` and `Tail` rather than reading them
+    match start_position(&config, &reader).await {
+        Ok(position) => reader.seek(position),
+        Err(e) => {
+            warn!(
+                error = ?e,
+                "Error seeking local logs for container {}", container_id,
+            );
+
+            return;
+        },
+    }
+
+    loop {
+        match reader.next().await {
+            Ok(Some(entry)) => {
+                if config.is_before_since(&entry) || config.is_after_until(&entry) {
+                    continue;
+                }
+
+                if config.tail < 0 {
+                    if tx.send(Ok(Bytes::from(encode_frame(&entry)))).await.is_err() {
+                        return;
+                    }
+
+                    continue;
+                }
+
+                backlog.push_back(entry);
+
+                if backlog.len() > config.tail as usize {
+                    backlog.pop_front();
+                }
+            },
+            Ok(None) => break,
+            Err(e) => {
+                warn!(
+                    error = ?e,
+                    "Error reading local logs for container {}", container_id,
+                );
+
+                return;
+            },
+        }
+    }
+
+    for entry in backlog {
+        if tx.send(Ok(Bytes::from(encode_frame(&entry)))).await.is_err() {
+            return;
+        }
+    }
+
+    if !config.follow {
+        return;
+    }
+
+    loop {
+        // Check before reading so entries written just before the task stopped are still sent
+        let logging = state
+            .is_logging(&container_id)
+            .await;
+
+        match reader.next().await {
+            Ok(Some(entry)) => {
+                if config.is_after_until(&entry) {
+                    return;
+                }
+
+                if config.is_before_since(&entry) {
+                    continue;
+                }
+
+                if tx.send(Ok(Bytes::from(encode_frame(&entry)))).await.is_err() {
+                    return;
+                }
+            },
+            Ok(None) => {
+                if !logging || tx.is_closed() {
+                    return;
+                }
+
+                tokio::time::sleep(FOLLOW_INTERVAL)
+                    .await;
+            },
+            Err(e) => {
+                warn!(
+                    error = ?e,
+                    "Error following local logs for container {}", container_id,
+                );
+
+                return;
+            },
+        }
+    }
 }
 
 
@@ -131,18 +409,34 @@ mod tests {
 
     use crate::{
         api::{Api, AppState},
-        task::FifoProcessor, config::Config,
+        task::FifoProcessor, client::HttpClients, config::Config,
+        container::ContainerInfo,
+        store::{
+            LogStore,
+            StoreOptions,
+        },
     };
+    use docker_protobuf::LogEntry;
 
 
     struct TestProcessor;
 
     #[async_trait::async_trait]
     impl FifoProcessor for TestProcessor {
-        fn new(_: Config) -> Self {
+        fn new(_: Config, _: ContainerInfo, _: HttpClients) -> Self {
             Self
         }
 
+        async fn open<P: Into<PathBuf> + Send>(&mut self, path: P) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let path: PathBuf = path.into();
+
+            if path.to_str().unwrap().starts_with("/tmp/unopenable") {
+                return Err(format!("Unable to open {:?}", path).into());
+            }
+
+            Ok(())
+        }
+
         async fn process<P: Into<PathBuf> + Send>(self, path: P, recv: Receiver<bool>) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
             let p: String= path
                 .into()
@@ -202,6 +496,56 @@ mod tests {
             .expect("Failed to init config")
     }
 
+    fn config_with_state_dir(state_dir: &std::path::Path) -> Config {
+        let mut config = config();
+
+        config.state_dir = state_dir
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        config
+    }
+
+    fn decode_frames(mut bytes: &[u8]) -> Vec<LogEntry> {
+        let mut entries = Vec::new();
+
+        while !bytes.is_empty() {
+            let size = u32::from_be_bytes(bytes[..4].try_into().unwrap()) as usize;
+
+            entries.push(LogEntry::from_bytes(&bytes[4..4 + size]).unwrap());
+            bytes = &bytes[4 + size..];
+        }
+
+        entries
+    }
+
+    async fn populate_store(state_dir: &std::path::Path, container_id: &str, count: i64) {
+        let options = StoreOptions {
+            max_size: 1024 * 1024,
+            max_file: 5,
+        };
+
+        let mut store = LogStore::open(state_dir, container_id, options)
+            .await
+            .unwrap();
+
+        for i in 1..=count {
+            store
+                .append(&LogEntry {
+                    source: "stdout".to_string(),
+                    time_nano: i * 1_000_000_000,
+                    line: format!("line {}", i)
+                        .as_bytes()
+                        .to_vec(),
+                    partial: false,
+                    partial_log_metadata: None,
+                })
+                .await
+                .unwrap();
+        }
+    }
+
 
     #[tokio::test]
     async fn test_start_logging() {
@@ -249,13 +593,91 @@ mod tests {
             .lock()
             .unwrap()
             .get(fpath)
-            .unwrap()
-            .clone();
+            .copied()
+            .unwrap();
         
-        assert_eq!(value, true);
+        assert!(value);
             
     }
 
+    #[tokio::test]
+    async fn test_start_logging_invalid_log_opt() {
+        let fpath = "/tmp/test_invalid_log_opt_fifo";
+        let mut state = crate::api::AppState::new(config());
+
+        let body = serde_json::json!({
+            "File": fpath,
+            "Info": {
+                "ContainerID": "test_container_id",
+                "Config": {
+                    "max-file": "many",
+                },
+            }
+        });
+
+        let results = post(
+            "/LogDriver.StartLogging",
+            state.clone(),
+            body,
+        ).await;
+
+        assert_eq!(results.status(), http::StatusCode::BAD_REQUEST);
+
+        let body = hyper::body::to_bytes(results.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body)
+            .unwrap();
+
+        assert!(!body["Err"].as_str().unwrap().is_empty());
+
+        // no task should have been started
+        assert!(state.take_task_flag(fpath).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_start_logging_unknown_log_opt() {
+        let state = crate::api::AppState::new(config());
+        let body = serde_json::json!({
+            "File": "/tmp/test_unknown_log_opt_fifo",
+            "Info": {
+                "ContainerID": "test_container_id",
+                "Config": {
+                    "not-an-option": "true",
+                },
+            }
+        });
+
+        let results = post(
+            "/LogDriver.StartLogging",
+            state,
+            body,
+        ).await;
+
+        assert_eq!(results.status(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_start_logging_fifo_open_failure() {
+        let fpath = "/tmp/unopenable_fifo";
+        let mut state = crate::api::AppState::new(config());
+        let body = serde_json::json!({
+            "File": fpath,
+            "Info": {
+                "ContainerID": "test_container_id",
+            }
+        });
+
+        let results = post(
+            "/LogDriver.StartLogging",
+            state.clone(),
+            body,
+        ).await;
+
+        assert_eq!(results.status(), http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(state.take_task_flag(fpath).await.is_none());
+    }
+
     #[tokio::test]
     async fn test_stop_logging() {
         let fpath = "/tmp/stop_logging_fifle";
@@ -304,9 +726,197 @@ mod tests {
             .lock()
             .unwrap()
             .get(fpath)
-            .unwrap()
-            .clone();
+            .copied()
+            .unwrap();
         
-        assert_eq!(value, true);
+        assert!(value);
+    }
+
+    #[tokio::test]
+    async fn test_capabilities() {
+        let state = AppState::new(config());
+        let results = post(
+            "/LogDriver.Capabilities",
+            state,
+            serde_json::json!({}),
+        ).await;
+
+        assert_eq!(results.status(), http::StatusCode::OK);
+
+        let body = hyper::body::to_bytes(results.into_body())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({"Cap": {"ReadLogs": true}}),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_logs() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        populate_store(dir.path(), "read_logs_container", 5).await;
+
+        let state = AppState::new(config_with_state_dir(dir.path()));
+        let body = serde_json::json!({
+            "Config": {
+                "Since": "1970-01-01T00:00:02Z",
+                "Until": "0001-01-01T00:00:00Z",
+                "Tail": 2,
+                "Follow": false,
+            },
+            "Info": {
+                "ContainerID": "read_logs_container",
+            }
+        });
+
+        let results = post(
+            "/LogDriver.ReadLogs",
+            state,
+            body,
+        ).await;
+
+        assert_eq!(results.status(), http::StatusCode::OK);
+
+        let body = hyper::body::to_bytes(results.into_body())
+            .await
+            .unwrap();
+        let lines = decode_frames(&body)
+            .into_iter()
+            .map(|entry| String::from_utf8(entry.line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines, vec!["line 4", "line 5"]);
+    }
+
+    #[tokio::test]
+    async fn test_read_logs_since_until() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        populate_store(dir.path(), "since_until_container", 5).await;
+
+        let state = AppState::new(config_with_state_dir(dir.path()));
+        let body = serde_json::json!({
+            "Config": {
+                "Since": "1970-01-01T00:00:02Z",
+                "Until": "1970-01-01T00:00:03Z",
+                "Tail": -1,
+                "Follow": false,
+            },
+            "Info": {
+                "ContainerID": "since_until_container",
+            }
+        });
+
+        let results = post(
+            "/LogDriver.ReadLogs",
+            state,
+            body,
+        ).await;
+
+        let body = hyper::body::to_bytes(results.into_body())
+            .await
+            .unwrap();
+
+        assert_eq!(decode_frames(&body).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_read_logs_tail_until() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        populate_store(dir.path(), "tail_until_container", 5).await;
+
+        let state = AppState::new(config_with_state_dir(dir.path()));
+        let body = serde_json::json!({
+            "Config": {
+                "Until": "1970-01-01T00:00:03Z",
+                "Tail": 2,
+                "Follow": false,
+            },
+            "Info": {
+                "ContainerID": "tail_until_container",
+            }
+        });
+
+        let results = post(
+            "/LogDriver.ReadLogs",
+            state,
+            body,
+        ).await;
+
+        let body = hyper::body::to_bytes(results.into_body())
+            .await
+            .unwrap();
+        let lines = decode_frames(&body)
+            .into_iter()
+            .map(|entry| String::from_utf8(entry.line).unwrap())
+            .collect::<Vec<_>>();
+
+        // the last entries before `Until`, not the last entries overall
+        assert_eq!(lines, vec!["line 2", "line 3"]);
+    }
+
+    #[tokio::test]
+    async fn test_read_logs_container_max_frame_size() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        populate_store(dir.path(), "frame_size_container", 5).await;
+
+        let state = AppState::new(config_with_state_dir(dir.path()));
+        let body = serde_json::json!({
+            "Config": {
+                "Follow": false,
+            },
+            "Info": {
+                "ContainerID": "frame_size_container",
+                "Config": {
+                    "max-frame-size": "4",
+                },
+            }
+        });
+
+        let results = post(
+            "/LogDriver.ReadLogs",
+            state,
+            body,
+        ).await;
+
+        let body = hyper::body::to_bytes(results.into_body())
+            .await
+            .unwrap();
+
+        // every stored frame is larger than the container's limit
+        assert!(decode_frames(&body).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_logs_unknown_container() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let state = AppState::new(config_with_state_dir(dir.path()));
+        let body = serde_json::json!({
+            "Config": {
+                "Follow": false,
+            },
+            "Info": {
+                "ContainerID": "missing_container",
+            }
+        });
+
+        let results = post(
+            "/LogDriver.ReadLogs",
+            state,
+            body,
+        ).await;
+
+        assert_eq!(results.status(), http::StatusCode::NOT_FOUND);
     }
 }
@@ -0,0 +1,12 @@
+use crate::metrics;
+
+
+pub struct Metrics;
+
+
+impl Metrics {
+    /// Serves the plugin's metrics in the Prometheus text format
+    pub async fn metrics() -> String {
+        metrics::render()
+    }
+}
@@ -3,7 +3,10 @@ use std::{
     sync::Arc,
 };
 use axum::{
-    routing::post,
+    routing::{
+        get,
+        post,
+    },
     Router,
 };
 use tokio::sync::{
@@ -11,10 +14,11 @@ use tokio::sync::{
     oneshot::Sender,
 };
 
-use crate::{task::FifoProcessor, config::Config};
+use crate::{task::FifoProcessor, client::HttpClients, config::Config};
 
 
 mod log_driver;
+mod metrics;
 mod plugin;
 
 
@@ -22,6 +26,12 @@ mod plugin;
 pub struct AppState {
     // maintain a shared mapping of log file paths to signal flags
     flags: Arc<Mutex<HashMap<String, Sender<bool>>>>,
+
+    // maintain a shared mapping of log file paths to the container logging to them
+    containers: Arc<Mutex<HashMap<String, String>>>,
+
+    // HTTP clients shared by every task
+    clients: HttpClients,
     config: Config,
 }
 
@@ -29,11 +39,17 @@ impl AppState {
     pub fn new(config: Config) -> Self {
         Self {
             config,
+            clients: HttpClients::default(),
             flags: Arc::new(
                 Mutex::new(
                     HashMap::new()
                 )
             ),
+            containers: Arc::new(
+                Mutex::new(
+                    HashMap::new()
+                )
+            ),
         }
     }
 
@@ -52,6 +68,32 @@ impl AppState {
             .await
             .remove(fpath)
     }
+
+    pub async fn add_container<S: Into<String>>(&mut self, fpath: S, container_id: S) -> Option<String> {
+        self
+            .containers
+            .lock()
+            .await
+            .insert(fpath.into(), container_id.into())
+    }
+
+    pub async fn remove_container(&mut self, fpath: &str) -> Option<String> {
+        self
+            .containers
+            .lock()
+            .await
+            .remove(fpath)
+    }
+
+    /// Returns true if a task is currently logging for the given container
+    pub async fn is_logging(&self, container_id: &str) -> bool {
+        self
+            .containers
+            .lock()
+            .await
+            .values()
+            .any(|id| id == container_id)
+    }
 }
 
 
@@ -73,7 +115,7 @@ impl<T: FifoProcessor + Send + 'static> Api<T> {
     pub fn from_existing_state(state: AppState) -> Self {
         Self {
             _marker: std::marker::PhantomData,
-            state: state,
+            state,
         }
     }
 
@@ -82,11 +124,15 @@ impl<T: FifoProcessor + Send + 'static> Api<T> {
             .route("/Plugin.Activate", post(plugin::Plugin::activate))
             .route("/LogDriver.StartLogging", post(log_driver::LogDriver::start_logging::<T>))
             .route("/LogDriver.StopLogging", post(log_driver::LogDriver::stop_logging))
+            .route("/LogDriver.Capabilities", post(log_driver::LogDriver::capabilities))
+            .route("/LogDriver.ReadLogs", post(log_driver::LogDriver::read_logs))
+            .route("/metrics", get(metrics::Metrics::metrics))
             .with_state(self.state)
     }
 }
 
 
+#[allow(clippy::from_over_into)]
 impl<T: FifoProcessor + Send + 'static> Into<Router> for Api<T> {
     fn into(self) -> Router {
         self.into_router()
@@ -0,0 +1,248 @@
+use std::{
+    error::Error,
+    fmt,
+    str::FromStr,
+};
+
+use reqwest::header::{
+    HeaderMap,
+    HeaderName,
+    HeaderValue,
+    AUTHORIZATION,
+};
+
+use crate::config::Config;
+
+
+/// A configuration value which is redacted from Debug output
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Secret {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Secret(s.to_string()))
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+
+#[derive(Debug)]
+pub enum AuthError {
+    ReadFile {
+        path: String,
+        err: std::io::Error,
+    },
+    // e.g. both a bearer token and basic auth
+    Conflict(String),
+    InvalidHeader(String),
+}
+
+impl Error for AuthError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AuthError::ReadFile { err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::ReadFile { path, err } => write!(f, "Failed to read {}: {}", path, err),
+            AuthError::Conflict(reason) => write!(f, "Conflicting auth settings: {}", reason),
+            AuthError::InvalidHeader(header) => write!(f, "Invalid header: {}", header),
+        }
+    }
+}
+
+
+fn read_file(path: &str) -> Result<String, AuthError> {
+    std::fs::read_to_string(path)
+        .map_err(|err| AuthError::ReadFile {
+            path: path.to_string(),
+            err,
+        })
+}
+
+/// Returns the secret from `value`, or else the contents of `file` with surrounding
+/// whitespace (e.g. a trailing newline) removed
+pub fn resolve_secret(value: &Option<Secret>, file: &Option<String>, name: &str) -> Result<Option<String>, AuthError> {
+    match (value, file) {
+        (Some(_), Some(_)) => Err(AuthError::Conflict(format!("both {} and {}-file are set", name, name))),
+        (Some(value), None) => Ok(Some(value.expose().to_string())),
+        (None, Some(path)) => Ok(Some(read_file(path)?.trim().to_string())),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Adds a header, marked sensitive so its value isn't logged
+pub fn insert_header(headers: &mut HeaderMap, name: &str, value: &str) -> Result<(), AuthError> {
+    let invalid = || AuthError::InvalidHeader(name.to_string());
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| invalid())?;
+    let mut value = HeaderValue::from_str(value.trim())
+        .map_err(|_| invalid())?;
+
+    value.set_sensitive(true);
+    headers.insert(name, value);
+    Ok(())
+}
+
+
+/// Builds the headers sent with every request to the ingest API.
+///
+/// Static headers come from `headers` (`Name=Value` pairs separated by commas) and
+/// `headers-file` (one `Name: Value` per line).  A bearer token or basic auth adds an
+/// `Authorization` header; secrets can be read from files, so they don't appear in
+/// `docker inspect`.
+pub fn headers(config: &Config) -> Result<HeaderMap, AuthError> {
+    let mut headers = HeaderMap::new();
+
+    if let Some(pairs) = config.headers.as_ref() {
+        for pair in pairs.expose().split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (name, value) = pair
+                .split_once('=')
+                .ok_or_else(|| AuthError::InvalidHeader(pair.to_string()))?;
+
+            insert_header(&mut headers, name, value)?;
+        }
+    }
+
+    if let Some(path) = config.headers_file.as_ref() {
+        for line in read_file(path)?.lines().filter(|line| !line.trim().is_empty()) {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| AuthError::InvalidHeader(line.to_string()))?;
+
+            insert_header(&mut headers, name, value)?;
+        }
+    }
+
+    let token = resolve_secret(&config.auth_token, &config.auth_token_file, "auth-token")?;
+    let password = resolve_secret(&config.auth_password, &config.auth_password_file, "auth-password")?;
+
+    let authorization = match (token, config.auth_username.as_ref(), password) {
+        (Some(_), Some(_), _) => return Err(AuthError::Conflict("both auth-token and auth-username are set".to_string())),
+        (Some(token), None, _) => Some(format!("Bearer {}", token)),
+        (None, Some(username), password) => {
+            let credentials = format!("{}:{}", username, password.unwrap_or_default());
+
+            Some(format!("Basic {}", base64::encode(credentials)))
+        },
+        (None, None, Some(_)) => return Err(AuthError::Conflict("auth-password is set without auth-username".to_string())),
+        (None, None, None) => None,
+    };
+
+    if let Some(authorization) = authorization {
+        insert_header(&mut headers, AUTHORIZATION.as_str(), &authorization)?;
+    }
+
+    Ok(headers)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::Write,
+    };
+
+    use envconfig::Envconfig;
+
+    use super::*;
+
+    fn config(pairs: &[(&str, &str)]) -> Config {
+        let env = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+
+        Config::init_from_hashmap(&env)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_no_auth() {
+        assert!(headers(&config(&[])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_bearer_token_from_file() {
+        let mut file = tempfile::NamedTempFile::new()
+            .unwrap();
+
+        writeln!(file, "s3cret").unwrap();
+
+        let headers = headers(&config(&[("AUTH_TOKEN_FILE", file.path().to_str().unwrap())]))
+            .unwrap();
+
+        assert_eq!(headers[AUTHORIZATION], "Bearer s3cret");
+        assert!(headers[AUTHORIZATION].is_sensitive());
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        let headers = headers(&config(&[("AUTH_USERNAME", "user"), ("AUTH_PASSWORD", "pass")]))
+            .unwrap();
+
+        assert_eq!(headers[AUTHORIZATION], "Basic dXNlcjpwYXNz");
+    }
+
+    #[test]
+    fn test_static_headers() {
+        let mut file = tempfile::NamedTempFile::new()
+            .unwrap();
+
+        writeln!(file, "X-Tenant: logs").unwrap();
+
+        let headers = headers(&config(&[
+            ("HEADERS", "X-API-Key=abc, X-Env=prod"),
+            ("HEADERS_FILE", file.path().to_str().unwrap()),
+        ])).unwrap();
+
+        assert_eq!(headers["x-api-key"], "abc");
+        assert_eq!(headers["x-env"], "prod");
+        assert_eq!(headers["x-tenant"], "logs");
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let conflicts = [
+            config(&[("AUTH_TOKEN", "token"), ("AUTH_USERNAME", "user")]),
+            config(&[("AUTH_TOKEN", "token"), ("AUTH_TOKEN_FILE", "/run/secrets/token")]),
+            config(&[("AUTH_PASSWORD", "pass")]),
+        ];
+
+        for config in conflicts {
+            assert!(matches!(headers(&config), Err(AuthError::Conflict(_))));
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(matches!(headers(&config(&[("HEADERS", "X-API-Key")])), Err(AuthError::InvalidHeader(_))));
+        assert!(matches!(headers(&config(&[("AUTH_TOKEN_FILE", "/does/not/exist")])), Err(AuthError::ReadFile { .. })));
+    }
+
+    #[test]
+    fn test_secret_redacted() {
+        let config = config(&[("AUTH_TOKEN", "s3cret")]);
+
+        assert!(!format!("{:?}", config).contains("s3cret"));
+    }
+}
@@ -0,0 +1,183 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::{
+    config::Config,
+    log::LogMessage,
+};
+
+
+/// Limits at which a batch is sent
+#[derive(Debug, Clone, Copy)]
+pub struct BatchOptions {
+    pub max_count: usize,
+    pub max_bytes: usize,
+    pub linger: Duration,
+}
+
+impl From<&Config> for BatchOptions {
+    fn from(config: &Config) -> Self {
+        Self {
+            max_count: config.batch_max_count.max(1),
+            max_bytes: config.batch_max_size.0 as usize,
+            linger: config.batch_linger.0,
+        }
+    }
+}
+
+
+// Serialized size of a message, plus the separator which follows it in the array
+fn message_size(message: &LogMessage) -> usize {
+    serde_json::to_vec(message)
+        .map(|json| json.len())
+        .unwrap_or(0) + 1
+}
+
+
+/// Collects LogMessages into batches, sent as a single JSON array.
+///
+/// A batch is full once it holds `max_count` messages or adding another message would
+/// make the array larger than `max_bytes`; a message larger than `max_bytes` is sent on
+/// its own.  Batches which aren't full are sent once `linger` has passed since their
+/// first message was added.
+pub struct Batcher {
+    options: BatchOptions,
+    messages: Vec<LogMessage>,
+    size: usize,
+    deadline: Option<Instant>,
+}
+
+impl Batcher {
+    pub fn new(options: BatchOptions) -> Self {
+        Self {
+            options,
+            messages: Vec::new(),
+            size: 1, // opening bracket
+            deadline: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Adds a message, returning any batches which are now full
+    pub fn push(&mut self, message: LogMessage) -> Vec<Vec<LogMessage>> {
+        let size = message_size(&message);
+        let mut full = Vec::new();
+
+        if !self.is_empty() && self.size + size > self.options.max_bytes {
+            full.push(self.take());
+        }
+
+        if self.is_empty() {
+            self.deadline = Some(Instant::now() + self.options.linger);
+        }
+
+        self.messages.push(message);
+        self.size += size;
+
+        if self.messages.len() >= self.options.max_count || self.size >= self.options.max_bytes {
+            full.push(self.take());
+        }
+
+        full
+    }
+
+    /// Returns the time at which the pending batch should be sent
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Takes the pending batch, regardless of whether it is full
+    pub fn take(&mut self) -> Vec<LogMessage> {
+        self.size = 1;
+        self.deadline = None;
+
+        std::mem::take(&mut self.messages)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(text: &str) -> LogMessage {
+        LogMessage {
+            timestamp: chrono::Utc::now(),
+            message: text.to_string(),
+            level: 3,
+            context: None,
+        }
+    }
+
+    fn options() -> BatchOptions {
+        BatchOptions {
+            max_count: 3,
+            max_bytes: 1024 * 1024,
+            linger: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn test_flush_on_count() {
+        let mut batcher = Batcher::new(options());
+
+        assert!(batcher.push(message("1")).is_empty());
+        assert!(batcher.deadline().is_some());
+        assert!(batcher.push(message("2")).is_empty());
+
+        let batches = batcher.push(message("3"));
+
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].len(), 3);
+        assert!(batcher.is_empty());
+        assert!(batcher.deadline().is_none());
+    }
+
+    #[test]
+    fn test_flush_on_size() {
+        let size = message_size(&message("1"));
+        let mut batcher = Batcher::new(BatchOptions {
+            max_bytes: 1 + size * 2,
+            ..options()
+        });
+
+        assert!(batcher.push(message("1")).is_empty());
+
+        // the array would be exactly max_bytes
+        let batches = batcher.push(message("2"));
+
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].len(), 2);
+
+        // a message larger than max_bytes is sent on its own
+        assert!(batcher.push(message("3")).is_empty());
+
+        let batches = batcher.push(message(&"4".repeat(size * 2)));
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0][0].message, "3");
+        assert_eq!(batches[1].len(), 1);
+        assert!(batcher.is_empty());
+
+        // the serialized batch fits within max_bytes
+        let batch = vec![message("1"), message("2")];
+
+        assert_eq!(serde_json::to_vec(&batch).unwrap().len(), 1 + size * 2);
+    }
+
+    #[test]
+    fn test_take() {
+        let mut batcher = Batcher::new(options());
+
+        assert!(batcher.take().is_empty());
+
+        batcher.push(message("1"));
+
+        assert_eq!(batcher.take().len(), 1);
+        assert!(batcher.deadline().is_none());
+    }
+}
@@ -1,43 +1,727 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+
 use async_trait::async_trait;
-use reqwest;
-use serde_json::Value;
+use reqwest::{
+    header::{
+        HeaderMap,
+        CONTENT_ENCODING,
+        CONTENT_TYPE,
+        RETRY_AFTER,
+    },
+    StatusCode,
+};
+use serde::Deserialize;
+use tracing::{
+    debug,
+    warn,
+};
+
+use crate::{
+    auth,
+    compression::Compression,
+    config::Config,
+    container::ContainerInfo,
+    endpoint::{
+        EndpointHealth,
+        EndpointOptions,
+        Endpoints,
+    },
+    error::BoxedError,
+    log::LogMessage,
+    metrics,
+    tls::TlsOptions,
+};
+
+
+// Characters of an error response's body kept with the error
+const MAX_ERROR_BODY: usize = 4096;
+
+
+#[derive(Debug)]
+pub enum IngestError {
+    // the request body couldn't be serialized or compressed
+    Encode(std::io::Error),
+    // the request couldn't be sent, or no response was received
+    Request(reqwest::Error),
+    Status {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+        // the start of the response body, which usually says what was wrong
+        body: String,
+    },
+    // the response body couldn't be read, e.g. the connection dropped or timed out
+    Response(reqwest::Error),
+    // the response body wasn't valid JSON
+    Decode(serde_json::Error),
+    // the messages couldn't be written to a local sink or socket
+    Write(std::io::Error),
+    // the sink didn't confirm delivery of the batch with this ID in time
+    Unacknowledged(u64),
+    // logging stopped, and the drain timeout passed, before the batch was delivered
+    Stopped,
+}
+
+impl IngestError {
+    /// True if sending the same request again may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            IngestError::Encode(_) => false,
+            IngestError::Request(_) => true,
+            IngestError::Status { status, .. } => status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS,
+            IngestError::Response(_) => true,
+            IngestError::Decode(_) => false,
+            IngestError::Write(_) => true,
+            IngestError::Unacknowledged(_) => true,
+            IngestError::Stopped => true,
+        }
+    }
+
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            IngestError::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    pub fn body(&self) -> Option<&str> {
+        match self {
+            IngestError::Status { body, .. } => Some(body),
+            _ => None,
+        }
+    }
+
+    /// The delay requested by the endpoint's `Retry-After` header, if any
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            IngestError::Status { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl Error for IngestError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            IngestError::Encode(err) => Some(err),
+            IngestError::Request(err) => Some(err),
+            IngestError::Status { .. } => None,
+            IngestError::Response(err) => Some(err),
+            IngestError::Decode(err) => Some(err),
+            IngestError::Write(err) => Some(err),
+            IngestError::Unacknowledged(_) => None,
+            IngestError::Stopped => None,
+        }
+    }
+}
+
+impl fmt::Display for IngestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IngestError::Encode(err) => write!(f, "Encoding error: {}", err),
+            IngestError::Request(err) => write!(f, "Request error: {}", err),
+            IngestError::Status { status, .. } => write!(f, "Unexpected status: {}", status),
+            IngestError::Response(err) => write!(f, "Error reading response: {}", err),
+            IngestError::Decode(err) => write!(f, "Invalid response: {}", err),
+            IngestError::Write(err) => write!(f, "Write error: {}", err),
+            IngestError::Unacknowledged(id) => write!(f, "Batch {} was not acknowledged in time", id),
+            IngestError::Stopped => write!(f, "Logging stopped before the batch was delivered"),
+        }
+    }
+}
 
-use crate::log::LogMessage;
 
+/// Parses a `Retry-After` value, either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim())
+        .ok()?;
+
+    // a date in the past means retry now
+    Some(
+        (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO)
+    )
+}
+
+
+/// Returns a response if its status is 2xx, or the error for it otherwise, with the
+/// start of its body and any `Retry-After` delay
+pub async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, IngestError> {
+    let status = response.status();
+
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after);
+    let body = response
+        .text()
+        .await
+        .unwrap_or_default()
+        .chars()
+        .take(MAX_ERROR_BODY)
+        .collect();
+
+    Err(IngestError::Status {
+        status,
+        retry_after,
+        body,
+    })
+}
+
+
+/// A message the ingest API rejected, by its index in the batch
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Rejection {
+    pub index: usize,
+    #[serde(default, alias = "error")]
+    pub reason: Option<String>,
+}
+
+
+/// What the ingest API reported about a batch it accepted.  Fields it doesn't send are
+/// left empty, as is everything for an empty body or one which isn't a JSON object;
+/// a body which isn't JSON at all is logged and treated the same.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct IngestResponse {
+    // number of messages accepted
+    #[serde(default, alias = "accepted")]
+    pub count: Option<usize>,
+    #[serde(default)]
+    pub rejected: Vec<Rejection>,
+}
+
+impl IngestResponse {
+    fn parse(body: &[u8]) -> Result<Self, serde_json::Error> {
+        if body.iter().all(u8::is_ascii_whitespace) {
+            return Ok(Self::default());
+        }
+
+        let value = serde_json::from_slice::<serde_json::Value>(body)?;
+
+        Ok(serde_json::from_value(value).unwrap_or_default())
+    }
+}
 
 
 #[async_trait]
 pub trait Ingest {
-    fn new<S: AsRef<str>>(uri: S) -> Self where Self: Sized;
-    async fn ingest(&mut self, message: LogMessage) -> Result<serde_json::Value, reqwest::Error>;
+    fn new(config: &Config, info: &ContainerInfo, clients: &HttpClients) -> Result<Self, BoxedError> where Self: Sized;
+
+    /// Sends a batch of messages; for the ingest API, as a single JSON array.  Only a
+    /// 2xx response is a success; its body may still report messages as rejected.
+    async fn ingest(&mut self, messages: &[LogMessage]) -> Result<IngestResponse, IngestError>;
 }
 
-pub struct IngestClient {
-    uri: String,
+
+/// Settings for the HTTP client used to reach an endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClientOptions {
+    pub connect_timeout: Duration,
+    pub request_timeout: Duration,
+    pub idle_timeout: Duration,
+    pub keepalive: Option<Duration>,
+    pub pool_max_idle: usize,
+    pub http2: bool,
+    pub tls: TlsOptions,
 }
 
-#[async_trait]
-impl Ingest for IngestClient {
-    fn new<S: AsRef<str>>(uri: S) -> Self where Self: Sized {
+impl From<&Config> for ClientOptions {
+    fn from(config: &Config) -> Self {
         Self {
-            uri: uri
-                .as_ref()
-                .to_string(),
+            connect_timeout: config.http_connect_timeout.0,
+            request_timeout: config.http_request_timeout.0,
+            idle_timeout: config.http_idle_timeout.0,
+            keepalive: Some(config.http_keepalive.0)
+                .filter(|keepalive| !keepalive.is_zero()),
+            pool_max_idle: config.http_pool_max_idle,
+            http2: config.http2,
+            tls: TlsOptions::from(config),
         }
     }
+}
 
-    async fn ingest(&mut self, message: LogMessage) -> Result<serde_json::Value, reqwest::Error> {
-        let client = reqwest::Client::new();
-        let url = format!("{}/logs", self.uri);
+impl ClientOptions {
+    fn build(&self, endpoint: &str) -> Result<reqwest::Client, BoxedError> {
+        let builder = reqwest::Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.request_timeout)
+            .pool_idle_timeout(self.idle_timeout)
+            .pool_max_idle_per_host(self.pool_max_idle)
+            .tcp_keepalive(self.keepalive);
+
+        let builder = match self.http2 {
+            true => builder.http2_prior_knowledge(),
+            false => builder.http1_only(),
+        };
 
-        let response = client
+        let builder = self.tls.configure(builder, endpoint)?;
+
+        Ok(builder.build()?)
+    }
+}
+
+
+// Connections are pooled per origin, so paths don't need their own client
+fn origin(endpoint: &str) -> String {
+    reqwest::Url::parse(endpoint)
+        .map(|url| url.origin().ascii_serialization())
+        .unwrap_or_else(|_| endpoint.to_string())
+}
+
+
+/// HTTP clients shared by every container's task; one per endpoint and set of client
+/// options, so connections and TLS sessions are reused rather than rebuilt per request.
+/// Also holds the endpoints' health, which is shared the same way.
+#[derive(Clone, Default)]
+pub struct HttpClients {
+    clients: Arc<Mutex<HashMap<(String, ClientOptions), reqwest::Client>>>,
+    health: EndpointHealth,
+}
+
+impl HttpClients {
+    /// Returns the client for an endpoint, creating it if needed
+    pub fn get(&self, endpoint: &str, options: &ClientOptions) -> Result<reqwest::Client, BoxedError> {
+        let key = (origin(endpoint), options.clone());
+        let mut clients = self.clients
+            .lock()
+            .expect("HTTP client lock poisoned");
+
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+
+        let client = options.build(endpoint)?;
+
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+}
+
+
+/// Serializes messages as a JSON array, compressed if it is at least `min_size` bytes.
+/// Returns the body and its Content-Encoding.
+fn encode_body(messages: &[LogMessage], compression: Compression, min_size: usize) -> Result<(Vec<u8>, Option<&'static str>), std::io::Error> {
+    compression.encode(serde_json::to_vec(messages)?, min_size)
+}
+
+
+/// An ingest endpoint's request URL and HTTP client
+struct Target {
+    // as configured; used in logs and metrics
+    endpoint: String,
+    uri: String,
+    client: reqwest::Client,
+}
+
+impl Target {
+    async fn send(&self, headers: &HeaderMap, body: Vec<u8>, encoding: Option<&str>) -> Result<IngestResponse, IngestError> {
+        let url = format!("{}/logs", self.uri);
+        let mut request = self.client
             .post(url)
-            .json(&vec![&message])
+            .headers(headers.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+
+        if let Some(encoding) = encoding {
+            request = request.header(CONTENT_ENCODING, encoding);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(IngestError::Request)?;
+        let body = check_status(response)
+            .await?
+            .bytes()
+            .await
+            .map_err(IngestError::Response)?;
+
+        // the batch was accepted, so isn't failed just because what came back is unclear
+        match IngestResponse::parse(&body) {
+            Ok(response) => Ok(response),
+            Err(err) => {
+                warn!(
+                    error = %err,
+                    endpoint = %metrics::label(&self.endpoint),
+                    "Ingest API accepted log messages with an invalid response",
+                );
+
+                Ok(IngestResponse::default())
+            },
+        }
+    }
+
+    /// True if the endpoint responds without a server error
+    async fn probe(&self, headers: &HeaderMap) -> bool {
+        self.client
+            .get(&self.uri)
+            .headers(headers.clone())
             .send()
-            .await?;
-        
-        response
-            .json::<Value>()
             .await
+            .map(|response| !response.status().is_server_error())
+            .unwrap_or(false)
+    }
+}
+
+
+/// Sends batches to one of the container's ingest endpoints.  Retryable failures move
+/// on to the next endpoint; see `Endpoints` for how they are chosen.
+pub struct IngestClient {
+    targets: Vec<Target>,
+    endpoints: Endpoints,
+    headers: HeaderMap,
+    compression: Compression,
+    compression_min_size: usize,
+}
+
+#[async_trait]
+impl Ingest for IngestClient {
+    fn new(config: &Config, info: &ContainerInfo, clients: &HttpClients) -> Result<Self, BoxedError> where Self: Sized {
+        let options = ClientOptions::from(config);
+        let targets = config
+            .ingest_endpoints()
+            .into_iter()
+            .map(|endpoint| Ok(Target {
+                endpoint: endpoint.to_string(),
+                uri: options.tls.url(endpoint),
+                client: clients.get(endpoint, &options)?,
+            }))
+            .collect::<Result<Vec<_>, BoxedError>>()?;
+
+        if targets.is_empty() {
+            return Err("No ingest endpoints configured".into());
+        }
+
+        let endpoints = Endpoints::new(
+            targets
+                .iter()
+                .map(|target| target.endpoint.clone())
+                .collect(),
+            EndpointOptions::from(config),
+            &info.container_id,
+            &clients.health,
+        );
+
+        Ok(Self {
+            targets,
+            endpoints,
+            headers: auth::headers(config)?,
+            compression: config.compression,
+            compression_min_size: config.compression_min_size.0 as usize,
+        })
+    }
+
+    async fn ingest(&mut self, messages: &[LogMessage]) -> Result<IngestResponse, IngestError> {
+        let (body, encoding) = encode_body(messages, self.compression, self.compression_min_size)
+            .map_err(IngestError::Encode)?;
+
+        for index in self.endpoints.due_probes() {
+            let healthy = self.targets[index]
+                .probe(&self.headers)
+                .await;
+
+            self.endpoints.probed(index, healthy);
+        }
+
+        let order = self.endpoints.order();
+        let mut failure = None;
+
+        for (attempt, index) in order.iter().copied().enumerate() {
+            let target = &self.targets[index];
+
+            let err = match target.send(&self.headers, body.clone(), encoding).await {
+                Ok(response) => {
+                    self.endpoints.succeeded(index);
+                    metrics::record_batch(&target.endpoint, messages.len());
+
+                    debug!(
+                        endpoint = %metrics::label(&target.endpoint),
+                        count = messages.len(),
+                        "Sent log messages",
+                    );
+
+                    return Ok(response);
+                },
+                Err(err) => err,
+            };
+
+            metrics::record_failure(&target.endpoint);
+
+            // the endpoint is up, but won't accept this batch; neither will the others
+            if !err.is_retryable() {
+                self.endpoints.succeeded(index);
+                return Err(err);
+            }
+
+            self.endpoints.failed(index);
+
+            if attempt + 1 < order.len() {
+                warn!(
+                    error = %err,
+                    endpoint = %metrics::label(&target.endpoint),
+                    count = messages.len(),
+                    "Error ingesting log messages; trying the next endpoint",
+                );
+            }
+
+            failure = Some(err);
+        }
+
+        Err(failure.expect("There is at least one endpoint"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+    };
+
+    use envconfig::Envconfig;
+
+    use super::*;
+
+    // serves POST /logs with the given status, counting requests
+    fn serve(status: StatusCode) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let app = axum::Router::new()
+            .route("/logs", axum::routing::post(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                async move { (status, r#"{"count": 1}"#) }
+            }));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service())
+        );
+
+        (endpoint, requests)
+    }
+
+    // an endpoint nothing is listening on
+    fn closed() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap();
+
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    fn client(endpoints: &[&str]) -> IngestClient {
+        let config = Config::init_from_hashmap(&HashMap::from([
+            ("LOG_INGEST_API".to_string(), endpoints.join(",")),
+            ("ENDPOINT_UNHEALTHY_AFTER".to_string(), "1".to_string()),
+            ("ENDPOINT_PROBE_INTERVAL".to_string(), "1h".to_string()),
+        ])).unwrap();
+
+        IngestClient::new(&config, &ContainerInfo::default(), &HttpClients::default())
+            .unwrap()
+    }
+
+    fn options() -> ClientOptions {
+        let config = Config::init_from_hashmap(&HashMap::new())
+            .unwrap();
+
+        ClientOptions::from(&config)
+    }
+
+    #[test]
+    fn test_clients_shared_per_endpoint() {
+        let clients = HttpClients::default();
+        let options = options();
+
+        clients.get("http://ingest:8080", &options).unwrap();
+        clients.get("http://ingest:8080/v1", &options).unwrap();
+        assert_eq!(clients.clients.lock().unwrap().len(), 1);
+
+        clients.get("http://other:8080", &options).unwrap();
+        clients.get("http://ingest:8080", &ClientOptions { http2: true, ..options }).unwrap();
+        assert_eq!(clients.clients.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_encode_body() {
+        let messages = vec![LogMessage::dropped(1); 10];
+        let json = serde_json::to_vec(&messages)
+            .unwrap();
+
+        let (body, encoding) = encode_body(&messages, Compression::Gzip, json.len() + 1)
+            .unwrap();
+
+        assert_eq!(body, json);
+        assert_eq!(encoding, None);
+
+        let (body, encoding) = encode_body(&messages, Compression::Gzip, json.len())
+            .unwrap();
+
+        assert!(body.len() < json.len());
+        assert_eq!(encoding, Some("gzip"));
+
+        let (body, encoding) = encode_body(&messages, Compression::None, 0)
+            .unwrap();
+
+        assert_eq!(body, json);
+        assert_eq!(encoding, None);
+    }
+
+    #[test]
+    fn test_retryable_status() {
+        let status = |status: u16| IngestError::Status {
+            status: StatusCode::from_u16(status).unwrap(),
+            retry_after: None,
+            body: String::new(),
+        };
+
+        assert!(status(500).is_retryable());
+        assert!(status(503).is_retryable());
+        assert!(status(429).is_retryable());
+        assert!(!status(400).is_retryable());
+        assert!(!status(404).is_retryable());
+    }
+
+    // answers every request with `response`, then hangs up
+    async fn respond(response: &'static [u8]) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 4096];
+
+                let _ = tokio::io::AsyncReadExt::read(&mut stream, &mut request).await;
+                let _ = tokio::io::AsyncWriteExt::write_all(&mut stream, response).await;
+            }
+        });
+
+        endpoint
+    }
+
+    #[tokio::test]
+    async fn test_retryable_response() {
+        // promises a longer body than it sends
+        let endpoint = respond(b"HTTP/1.1 200 OK\r\ncontent-length: 100\r\n\r\n{").await;
+        let results = client(&[&endpoint])
+            .ingest(&[LogMessage::dropped(1)])
+            .await;
+
+        assert!(matches!(results, Err(IngestError::Response(_))));
+        assert!(results.unwrap_err().is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_response_accepted() {
+        let endpoint = respond(b"HTTP/1.1 200 OK\r\ncontent-length: 6\r\n\r\n<html>").await;
+        let response = client(&[&endpoint])
+            .ingest(&[LogMessage::dropped(1)])
+            .await
+            .unwrap();
+
+        assert_eq!(response, IngestResponse::default());
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon"), None);
+
+        let future = (chrono::Utc::now() + chrono::Duration::seconds(60))
+            .to_rfc2822();
+        let delay = parse_retry_after(&future)
+            .unwrap();
+
+        assert!(delay > Duration::from_secs(55) && delay <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response = IngestResponse::parse(br#"{"count": 2, "rejected": [{"index": 1, "error": "too large"}]}"#)
+            .unwrap();
+
+        assert_eq!(response.count, Some(2));
+        assert_eq!(response.rejected, vec![Rejection { index: 1, reason: Some("too large".to_string()) }]);
+
+        assert_eq!(IngestResponse::parse(b"").unwrap(), IngestResponse::default());
+        assert_eq!(IngestResponse::parse(br#"{"accepted": 3}"#).unwrap().count, Some(3));
+        assert_eq!(IngestResponse::parse(b"[1, 2]").unwrap(), IngestResponse::default());
+        assert!(IngestResponse::parse(b"<html>").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failover() {
+        let down = closed();
+        let (up, requests) = serve(StatusCode::OK);
+        let mut client = client(&[&down, &up]);
+
+        let response = client.ingest(&[LogMessage::dropped(1)])
+            .await
+            .unwrap();
+
+        assert_eq!(response.count, Some(1));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // the closed endpoint is out of rotation, so the batch goes straight to the other
+        client.ingest(&[LogMessage::dropped(1)]).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert!(!client.endpoints.is_healthy(0));
+
+        let output = metrics::render();
+
+        assert!(output.contains(&format!("docker_log_driver_endpoint_batches_total{{endpoint=\"{}\"}} 2", up)));
+        assert!(output.contains(&format!("docker_log_driver_endpoint_failures_total{{endpoint=\"{}\"}} 1", down)));
+    }
+
+    #[tokio::test]
+    async fn test_permanent_failure_not_failed_over() {
+        let (rejecting, rejected) = serve(StatusCode::BAD_REQUEST);
+        let (accepting, accepted) = serve(StatusCode::OK);
+        let mut client = client(&[&rejecting, &accepting]);
+
+        let results = client.ingest(&[LogMessage::dropped(1)]).await;
+
+        assert!(matches!(results, Err(IngestError::Status { status: StatusCode::BAD_REQUEST, .. })));
+        assert_eq!(rejected.load(Ordering::SeqCst), 1);
+        assert_eq!(accepted.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_zero_keepalive_disables() {
+        let config = Config::init_from_hashmap(&HashMap::from([
+            ("HTTP_KEEPALIVE".to_string(), "0".to_string()),
+        ])).unwrap();
+
+        assert_eq!(ClientOptions::from(&config).keepalive, None);
+        assert!(options().keepalive.is_some());
     }
 }
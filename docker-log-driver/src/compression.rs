@@ -0,0 +1,106 @@
+use std::{
+    fmt,
+    io::Write,
+    str::FromStr,
+};
+
+
+/// Content-Encoding applied to request bodies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!("Invalid compression: {}; expected none, gzip or zstd", s)),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+impl Compression {
+    /// Value of the `Content-Encoding` header for compressed bodies
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+        }
+    }
+
+    /// Compresses a body if it is at least `min_size` bytes.  Returns the body and its
+    /// Content-Encoding.
+    pub fn encode(&self, body: Vec<u8>, min_size: usize) -> Result<(Vec<u8>, Option<&'static str>), std::io::Error> {
+        if body.len() < min_size {
+            return Ok((body, None));
+        }
+
+        Ok((self.compress(&body)?, self.content_encoding()))
+    }
+
+    pub fn compress(&self, body: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+        match self {
+            Compression::None => Ok(body.to_vec()),
+            Compression::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+
+                encoder.write_all(body)?;
+                encoder.finish()
+            },
+            Compression::Zstd => zstd::encode_all(body, zstd::DEFAULT_COMPRESSION_LEVEL),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let body = br#"[{"message":"hello"},{"message":"hello"},{"message":"hello"}]"#;
+
+        let mut gzip = String::new();
+
+        flate2::read::GzDecoder::new(&Compression::Gzip.compress(body).unwrap()[..])
+            .read_to_string(&mut gzip)
+            .unwrap();
+
+        assert_eq!(gzip.as_bytes(), body);
+
+        let zstd = zstd::decode_all(&Compression::Zstd.compress(body).unwrap()[..])
+            .unwrap();
+
+        assert_eq!(zstd, body);
+        assert_eq!(Compression::None.compress(body).unwrap(), body);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("gzip".parse::<Compression>().unwrap(), Compression::Gzip);
+        assert_eq!("zstd".parse::<Compression>().unwrap(), Compression::Zstd);
+        assert_eq!("none".parse::<Compression>().unwrap(), Compression::None);
+        assert!("brotli".parse::<Compression>().is_err());
+    }
+}
@@ -1,12 +1,1242 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    path::{
+        Component,
+        Path,
+    },
+    str::FromStr,
+};
+
+use chrono::format::{
+    Item,
+    StrftimeItems,
+};
 use envconfig::Envconfig;
 use tracing::Level;
 
+use crate::{
+    auth::Secret,
+    compression::Compression,
+    endpoint::LoadBalancing,
+    queue::QueuePolicy,
+    sink::{
+        GelfAddress,
+        LokiEncoding,
+        SinkKind,
+        SyslogAddress,
+        SyslogFacility,
+        SyslogFormat,
+        SyslogFraming,
+        SyslogSeverities,
+    },
+    spool::SpoolEviction,
+};
+
+
+/// A size in bytes, parsed from either a plain number or a number with a `k`, `m` or
+/// `g` suffix (e.g. `10m`), matching the units accepted by Docker's `max-size`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s
+            .trim()
+            .to_lowercase();
+        let trimmed = lower
+            .strip_suffix('b')
+            .unwrap_or(&lower);
+
+        let (digits, multiplier) = match trimmed.chars().last() {
+            Some('k') => (&trimmed[..trimmed.len() - 1], 1024),
+            Some('m') => (&trimmed[..trimmed.len() - 1], 1024 * 1024),
+            Some('g') => (&trimmed[..trimmed.len() - 1], 1024 * 1024 * 1024),
+            _ => (trimmed, 1),
+        };
+
+        digits
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .and_then(|value| value.checked_mul(multiplier))
+            .map(ByteSize)
+            .ok_or(format!("Invalid size: {}", s))
+    }
+}
+
+
+/// A duration parsed from a number with a `ms`, `s`, `m` or `h` suffix (e.g. `250ms`,
+/// `5s`); plain numbers are seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HumanDuration(pub std::time::Duration);
+
+impl FromStr for HumanDuration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let split = trimmed
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(trimmed.len());
+        let (digits, unit) = trimmed.split_at(split);
+
+        let value = digits
+            .parse::<u64>()
+            .map_err(|_| format!("Invalid duration: {}", s))?;
+
+        let duration = match unit.trim() {
+            "ms" => std::time::Duration::from_millis(value),
+            "" | "s" => std::time::Duration::from_secs(value),
+            "m" => std::time::Duration::from_secs(value * 60),
+            "h" => std::time::Duration::from_secs(value * 60 * 60),
+            _ => return Err(format!("Invalid duration: {}", s)),
+        };
+
+        Ok(HumanDuration(duration))
+    }
+}
+
+
+#[derive(Debug)]
+pub enum ConfigError {
+    UnknownOption(String),
+    // only the plugin's own settings may set this option
+    PluginOnly(String),
+    InvalidValue {
+        key: String,
+        value: String,
+        reason: String,
+    },
+    InvalidUrl {
+        key: String,
+        value: String,
+        reason: String,
+    },
+}
+
+impl ConfigError {
+    /// Prefixes the option's key with the name of the sink it was set for
+    fn for_sink(self, name: &str) -> Self {
+        let prefix = |key: String| format!("{}.{}", name, key);
+
+        match self {
+            ConfigError::UnknownOption(key) => ConfigError::UnknownOption(prefix(key)),
+            ConfigError::PluginOnly(key) => ConfigError::PluginOnly(prefix(key)),
+            ConfigError::InvalidValue { key, value, reason } => ConfigError::InvalidValue { key: prefix(key), value, reason },
+            ConfigError::InvalidUrl { key, value, reason } => ConfigError::InvalidUrl { key: prefix(key), value, reason },
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::UnknownOption(key) => write!(f, "Unknown log-opt: {}", key),
+            ConfigError::PluginOnly(key) => write!(f, "{} can only be set in the plugin's settings, not with --log-opt", key),
+            ConfigError::InvalidValue { key, value, reason } => write!(f, "Invalid value {:?} for log-opt {}: {}", value, key, reason),
+            ConfigError::InvalidUrl { key, value, reason } => write!(f, "Invalid URL {:?} for {}: {}", value, key, reason),
+        }
+    }
+}
+
+
+// log-opts handled by the docker daemon itself, which are passed through to the plugin
+const DAEMON_LOG_OPTS: &[&str] = &["mode", "max-buffer-size"];
+
+// options which read or write files as the plugin, or weaken TLS, so that whoever can
+// run a container can't point them at the plugin's files or an endpoint of their choosing
+const PLUGIN_ONLY_OPTS: &[&str] = &[
+    "file-dir",
+    "auth-token-file",
+    "auth-password-file",
+    "headers-file",
+    "tls-ca-file",
+    "tls-cert-file",
+    "tls-key-file",
+    "tls-insecure",
+    "splunk-token-file",
+];
+
+
+/// Whether `path` is relative and stays below the directory it's joined to
+pub fn is_contained(path: &Path) -> bool {
+    path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
 
+
+fn validate_url(key: &str, value: &str) -> Result<(), ConfigError> {
+    let invalid = |reason: String| ConfigError::InvalidUrl {
+        key: key.to_string(),
+        value: value.to_string(),
+        reason,
+    };
+
+    let url = reqwest::Url::parse(value)
+        .map_err(|e| invalid(e.to_string()))?;
+
+    match url.scheme() {
+        "http" | "https" => (),
+        scheme => return Err(invalid(format!("unsupported scheme {}", scheme))),
+    }
+
+    if url.host_str().is_none() {
+        return Err(invalid("missing host".to_string()));
+    }
+
+    Ok(())
+}
+
+
+/// Normalizes a log-opt key so either the option name (`log-ingest-api`) or the
+/// environment variable name (`LOG_INGEST_API`) can be used.
+fn normalize_key(key: &str) -> String {
+    key
+        .trim()
+        .to_lowercase()
+        .replace('_', "-")
+}
+
+fn parse_value<T>(key: &str, value: &str) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .parse::<T>()
+        .map_err(|e| ConfigError::InvalidValue {
+            key: key.to_string(),
+            value: value.to_string(),
+            reason: e.to_string(),
+        })
+}
+
+
+/// Plugin-wide configuration, loaded from the environment.  Per container settings
+/// can be overridden with `--log-opt`; see `Config::with_log_opts`.
 #[derive(Envconfig, Debug, Clone)]
 pub struct Config {
+    // One or more comma separated ingest API URLs
     #[envconfig(from = "LOG_INGEST_API", default = "http://localhost:8080")]
     pub log_ingest_api: String,
 
+    // How batches are spread over multiple endpoints
+    #[envconfig(from = "LOAD_BALANCING", default = "failover")]
+    pub load_balancing: LoadBalancing,
+
+    // Consecutive failures after which an endpoint is taken out of rotation
+    #[envconfig(from = "ENDPOINT_UNHEALTHY_AFTER", default = "3")]
+    pub endpoint_unhealthy_after: u32,
+
+    // How often an unhealthy endpoint is probed
+    #[envconfig(from = "ENDPOINT_PROBE_INTERVAL", default = "10s")]
+    pub endpoint_probe_interval: HumanDuration,
+
     #[envconfig(from = "LOG_LEVEL", default = "info")]
     pub log_level: Level,
+
+    #[envconfig(from = "LOG_STATE_DIR", default = "/var/lib/docker-log-driver")]
+    pub state_dir: String,
+
+    // Maximum size of a single segment of the local log store
+    #[envconfig(from = "MAX_SIZE", default = "20m")]
+    pub max_size: ByteSize,
+
+    // Maximum number of segments kept in the local log store
+    #[envconfig(from = "MAX_FILE", default = "5")]
+    pub max_file: usize,
+
+    // Keep the local log store after a container stops logging
+    #[envconfig(from = "KEEP_LOCAL", default = "true")]
+    pub keep_local: bool,
+
+    // Maximum size of a line reassembled from partial entries before it is flushed
+    #[envconfig(from = "PARTIAL_MAX_SIZE", default = "1m")]
+    pub partial_max_size: ByteSize,
+
+    // How long to wait for the remaining partial entries of a line before flushing it
+    #[envconfig(from = "PARTIAL_TIMEOUT", default = "5s")]
+    pub partial_timeout: HumanDuration,
+
+    // Largest frame accepted from the FIFO
+    #[envconfig(from = "MAX_FRAME_SIZE", default = "1m")]
+    pub max_frame_size: ByteSize,
+
+    // Skip oversized or undecodable frames instead of stopping the container's logging
+    #[envconfig(from = "SKIP_INVALID_FRAMES", default = "true")]
+    pub skip_invalid_frames: bool,
+
+    // Number of messages buffered ahead of the sender
+    #[envconfig(from = "QUEUE_SIZE", default = "4096")]
+    pub queue_size: usize,
+
+    // What happens to messages once the queue is full; see `Config::queue_policy`
+    #[envconfig(from = "QUEUE_POLICY")]
+    pub queue_policy: Option<QueuePolicy>,
+
+    // How often a message reporting dropped messages is sent
+    #[envconfig(from = "DROP_REPORT_INTERVAL", default = "30s")]
+    pub drop_report_interval: HumanDuration,
+
+    // Maximum number of messages sent in a single request
+    #[envconfig(from = "BATCH_MAX_COUNT", default = "500")]
+    pub batch_max_count: usize,
+
+    // Maximum size of the JSON array sent in a single request
+    #[envconfig(from = "BATCH_MAX_SIZE", default = "1m")]
+    pub batch_max_size: ByteSize,
+
+    // How long a batch waits for more messages before it is sent
+    #[envconfig(from = "BATCH_LINGER", default = "1s")]
+    pub batch_linger: HumanDuration,
+
+    // Time allowed to establish a connection to an endpoint
+    #[envconfig(from = "HTTP_CONNECT_TIMEOUT", default = "5s")]
+    pub http_connect_timeout: HumanDuration,
+
+    // Time allowed for a request, from connecting until the response body is read
+    #[envconfig(from = "HTTP_REQUEST_TIMEOUT", default = "30s")]
+    pub http_request_timeout: HumanDuration,
+
+    // How long an unused pooled connection is kept open
+    #[envconfig(from = "HTTP_IDLE_TIMEOUT", default = "90s")]
+    pub http_idle_timeout: HumanDuration,
+
+    // TCP keep-alive interval; 0 disables keep-alive
+    #[envconfig(from = "HTTP_KEEPALIVE", default = "60s")]
+    pub http_keepalive: HumanDuration,
+
+    // Maximum number of idle connections kept per endpoint
+    #[envconfig(from = "HTTP_POOL_MAX_IDLE", default = "16")]
+    pub http_pool_max_idle: usize,
+
+    // Use HTTP/2 (with prior knowledge) instead of HTTP/1.1
+    #[envconfig(from = "HTTP2", default = "false")]
+    pub http2: bool,
+
+    // Content-Encoding of request bodies
+    #[envconfig(from = "COMPRESSION", default = "none")]
+    pub compression: Compression,
+
+    // Request bodies smaller than this are sent uncompressed
+    #[envconfig(from = "COMPRESSION_MIN_SIZE", default = "1k")]
+    pub compression_min_size: ByteSize,
+
+    // Bearer token sent with every request, or a file containing it
+    #[envconfig(from = "AUTH_TOKEN")]
+    pub auth_token: Option<Secret>,
+
+    #[envconfig(from = "AUTH_TOKEN_FILE")]
+    pub auth_token_file: Option<String>,
+
+    // HTTP basic auth credentials; the password may be read from a file
+    #[envconfig(from = "AUTH_USERNAME")]
+    pub auth_username: Option<String>,
+
+    #[envconfig(from = "AUTH_PASSWORD")]
+    pub auth_password: Option<Secret>,
+
+    #[envconfig(from = "AUTH_PASSWORD_FILE")]
+    pub auth_password_file: Option<String>,
+
+    // Static headers as comma separated Name=Value pairs, or a file of Name: Value lines
+    #[envconfig(from = "HEADERS")]
+    pub headers: Option<Secret>,
+
+    #[envconfig(from = "HEADERS_FILE")]
+    pub headers_file: Option<String>,
+
+    // PEM bundle of CA certificates trusted in addition to the system roots
+    #[envconfig(from = "TLS_CA_FILE")]
+    pub tls_ca_file: Option<String>,
+
+    // PEM client certificate and PKCS#8 key for mutual TLS
+    #[envconfig(from = "TLS_CERT_FILE")]
+    pub tls_cert_file: Option<String>,
+
+    #[envconfig(from = "TLS_KEY_FILE")]
+    pub tls_key_file: Option<String>,
+
+    // Name the endpoint's certificate is verified against, instead of its host
+    #[envconfig(from = "TLS_SERVER_NAME")]
+    pub tls_server_name: Option<String>,
+
+    // Skip certificate and hostname verification; for development only
+    #[envconfig(from = "TLS_INSECURE", default = "false")]
+    pub tls_insecure: bool,
+
+    // Maximum number of times a batch is sent, including the first attempt
+    #[envconfig(from = "RETRY_MAX_ATTEMPTS", default = "5")]
+    pub retry_max_attempts: u32,
+
+    // Delay before the first retry; doubled for each retry after it
+    #[envconfig(from = "RETRY_INITIAL_BACKOFF", default = "500ms")]
+    pub retry_initial_backoff: HumanDuration,
+
+    // Longest delay between retries
+    #[envconfig(from = "RETRY_MAX_BACKOFF", default = "30s")]
+    pub retry_max_backoff: HumanDuration,
+
+    // How long a batch is retried for before it is given up on
+    #[envconfig(from = "RETRY_MAX_ELAPSED", default = "2m")]
+    pub retry_max_elapsed: HumanDuration,
+
+    // Maximum size of the spool of undelivered batches; 0 disables spooling
+    #[envconfig(from = "SPOOL_MAX_SIZE", default = "100m")]
+    pub spool_max_size: ByteSize,
+
+    // Whether the oldest or newest batches are evicted once the spool is full
+    #[envconfig(from = "SPOOL_EVICTION", default = "drop-oldest")]
+    pub spool_eviction: SpoolEviction,
+
+    // How often delivery of spooled batches is attempted while the endpoint is failing
+    #[envconfig(from = "SPOOL_REPLAY_INTERVAL", default = "5s")]
+    pub spool_replay_interval: HumanDuration,
+
+    // Write messages the ingest API rejects, or which are given up on, to a file
+    #[envconfig(from = "DEAD_LETTER", default = "true")]
+    pub dead_letter: bool,
+
+    // Size at which the dead-letter file is rotated, and how many files are kept
+    #[envconfig(from = "DEAD_LETTER_MAX_SIZE", default = "10m")]
+    pub dead_letter_max_size: ByteSize,
+
+    #[envconfig(from = "DEAD_LETTER_MAX_FILE", default = "3")]
+    pub dead_letter_max_file: usize,
+
+    // Comma separated names of the sinks messages are sent to; see `Config::sinks`
+    #[envconfig(from = "SINKS", default = "")]
+    pub sinks: String,
+
+    // Kind of sink messages are sent to, unless set by the sink's name
+    #[envconfig(from = "SINK", default = "ingest")]
+    pub sink: SinkKind,
+
+    // Directory file sinks write under; the sink's state directory if unset
+    #[envconfig(from = "FILE_DIR")]
+    pub file_dir: Option<String>,
+
+    // File under `FILE_DIR` a file sink appends to; `{id}` and `{name}` are replaced by the container's
+    #[envconfig(from = "FILE_PATH")]
+    pub file_path: Option<String>,
+
+    // Base URL of the Loki a loki sink pushes to
+    #[envconfig(from = "LOKI_URL", default = "http://localhost:3100")]
+    pub loki_url: String,
+
+    // Body format of push requests: protobuf or json
+    #[envconfig(from = "LOKI_ENCODING", default = "protobuf")]
+    pub loki_encoding: LokiEncoding,
+
+    // Comma separated context fields and container labels which become stream labels
+    #[envconfig(from = "LOKI_LABELS", default = "container_name,source")]
+    pub loki_labels: String,
+
+    // Tenant sent as X-Scope-OrgID
+    #[envconfig(from = "LOKI_TENANT")]
+    pub loki_tenant: Option<String>,
+
+    // Base URL of the cluster an elasticsearch sink indexes into
+    #[envconfig(from = "ELASTICSEARCH_URL", default = "http://localhost:9200")]
+    pub elasticsearch_url: String,
+
+    // Index name template; strftime escapes use the message's timestamp, `{field}` its metadata
+    #[envconfig(from = "ELASTICSEARCH_INDEX", default = "logs-{container_name}-%Y.%m.%d")]
+    pub elasticsearch_index: String,
+
+    // Base URL of the HTTP Event Collector a splunk sink sends to
+    #[envconfig(from = "SPLUNK_URL", default = "https://localhost:8088")]
+    pub splunk_url: String,
+
+    // HEC token, or a file containing it
+    #[envconfig(from = "SPLUNK_TOKEN")]
+    pub splunk_token: Option<Secret>,
+
+    #[envconfig(from = "SPLUNK_TOKEN_FILE")]
+    pub splunk_token_file: Option<String>,
+
+    // Index, sourcetype and host of events; the host defaults to the container's name
+    #[envconfig(from = "SPLUNK_INDEX")]
+    pub splunk_index: Option<String>,
+
+    #[envconfig(from = "SPLUNK_SOURCETYPE")]
+    pub splunk_sourcetype: Option<String>,
+
+    #[envconfig(from = "SPLUNK_HOST")]
+    pub splunk_host: Option<String>,
+
+    // Wait for indexer acknowledgement of each batch
+    #[envconfig(from = "SPLUNK_ACK", default = "false")]
+    pub splunk_ack: bool,
+
+    // How often acknowledgement is checked, and how long it is waited for
+    #[envconfig(from = "SPLUNK_ACK_INTERVAL", default = "1s")]
+    pub splunk_ack_interval: HumanDuration,
+
+    #[envconfig(from = "SPLUNK_ACK_TIMEOUT", default = "30s")]
+    pub splunk_ack_timeout: HumanDuration,
+
+    // Where a gelf sink sends to: udp://host:port or tcp://host:port
+    #[envconfig(from = "GELF_ADDRESS", default = "udp://localhost:12201")]
+    pub gelf_address: GelfAddress,
+
+    // Host of messages; the container's name if unset
+    #[envconfig(from = "GELF_HOST")]
+    pub gelf_host: Option<String>,
+
+    // Compression of UDP datagrams: gzip or none
+    #[envconfig(from = "GELF_COMPRESSION", default = "gzip")]
+    pub gelf_compression: Compression,
+
+    // Largest UDP datagram; bigger messages are chunked
+    #[envconfig(from = "GELF_CHUNK_SIZE", default = "1420")]
+    pub gelf_chunk_size: usize,
+
+    // Where a syslog sink sends to: udp://, tcp:// or tls://host:port, or unix:///path
+    #[envconfig(from = "SYSLOG_ADDRESS", default = "udp://localhost:514")]
+    pub syslog_address: SyslogAddress,
+
+    // Message format: rfc5424 or rfc3164
+    #[envconfig(from = "SYSLOG_FORMAT", default = "rfc5424")]
+    pub syslog_format: SyslogFormat,
+
+    // Framing over TCP and TLS: octet-counting or newline
+    #[envconfig(from = "SYSLOG_FRAMING", default = "octet-counting")]
+    pub syslog_framing: SyslogFraming,
+
+    #[envconfig(from = "SYSLOG_FACILITY", default = "user")]
+    pub syslog_facility: SyslogFacility,
+
+    // APP-NAME, or the tag of rfc3164; `{name}` and `{id}` are replaced by the container's
+    #[envconfig(from = "SYSLOG_APP_NAME", default = "{name}")]
+    pub syslog_app_name: String,
+
+    // HOSTNAME; the host's if unset
+    #[envconfig(from = "SYSLOG_HOSTNAME")]
+    pub syslog_hostname: Option<String>,
+
+    // Comma separated level=severity pairs overriding the default mapping
+    #[envconfig(from = "SYSLOG_SEVERITIES", default = "")]
+    pub syslog_severities: SyslogSeverities,
+}
+
+
+/// One of a container's sinks, with its own settings
+#[derive(Debug, Clone)]
+pub struct SinkConfig {
+    pub name: String,
+    pub config: Config,
+}
+
+
+impl Config {
+    /// Returns a copy of the configuration with a container's log-opts applied.  The
+    /// resulting configuration is validated; unknown options are rejected.
+    pub fn with_log_opts(&self, opts: &HashMap<String, String>) -> Result<Self, ConfigError> {
+        let mut config = self.clone();
+
+        let own = opts
+            .iter()
+            .map(|(key, value)| (normalize_key(key), value.as_str()))
+            // options for a single sink are applied by `sinks`
+            .filter(|(key, _)| !DAEMON_LOG_OPTS.contains(&key.as_str()) && !key.contains('.'))
+            .collect::<Vec<_>>();
+
+        config.set_log_opts(&own)?;
+
+        config.validate()?;
+        config.sinks(opts)?;
+
+        Ok(config)
+    }
+
+    /// What happens to messages once the queue is full: `queue-policy` if set, otherwise
+    /// `block`, or `drop-oldest` for a container with several sinks
+    pub fn queue_policy(&self) -> QueuePolicy {
+        self.queue_policy
+            .unwrap_or(QueuePolicy::Block)
+    }
+
+    /// The sink names in `sinks`, in the order they were given
+    pub fn sink_names(&self) -> Vec<String> {
+        self.sinks
+            .split(',')
+            .map(normalize_key)
+            .filter(|name| !name.is_empty())
+            .collect()
+    }
+
+    /// Returns the configuration of each sink.  Without `sinks` there is a single sink,
+    /// of the kind set by `sink`.
+    ///
+    /// Otherwise each named sink starts from this configuration, with its state kept
+    /// under `<state dir>/sinks/<name>`, and has the log-opts prefixed with its name
+    /// (e.g. `archive.queue-size`) applied.  A sink named after a kind (e.g. `file`) is
+    /// of that kind unless `<name>.sink` says otherwise.  With several sinks, none may
+    /// use `queue-policy=block`, as a full queue would hold up the others; those which
+    /// don't set a policy use `drop-oldest`.
+    pub fn sinks(&self, opts: &HashMap<String, String>) -> Result<Vec<SinkConfig>, ConfigError> {
+        let names = self.sink_names();
+
+        for key in opts.keys().map(|key| normalize_key(key)) {
+            let known = key
+                .split_once('.')
+                .map(|(name, option)| names.iter().any(|n| n == name) && option != "sinks")
+                .unwrap_or(true);
+
+            if !known {
+                return Err(ConfigError::UnknownOption(key));
+            }
+        }
+
+        if names.is_empty() {
+            return Ok(vec![SinkConfig {
+                name: self.sink.to_string(),
+                config: self.clone(),
+            }]);
+        }
+
+        for (index, name) in names.iter().enumerate() {
+            let invalid = name.contains(|c: char| !c.is_ascii_alphanumeric() && c != '-');
+
+            if invalid || names[..index].contains(name) {
+                return Err(ConfigError::InvalidValue {
+                    key: "sinks".to_string(),
+                    value: self.sinks.clone(),
+                    reason: format!("invalid or repeated sink name {:?}", name),
+                });
+            }
+        }
+
+        let several = names.len() > 1;
+
+        names
+            .into_iter()
+            .map(|name| {
+                let mut config = Config {
+                    state_dir: Path::new(&self.state_dir)
+                        .join("sinks")
+                        .join(&name)
+                        .display()
+                        .to_string(),
+                    ..self.clone()
+                };
+
+                if let Ok(kind) = name.parse() {
+                    config.sink = kind;
+                }
+
+                let prefix = format!("{}.", name);
+                let opts = opts
+                    .iter()
+                    .filter_map(|(key, value)| {
+                        normalize_key(key)
+                            .strip_prefix(&prefix)
+                            .map(|key| (key.to_string(), value.as_str()))
+                    })
+                    .collect::<Vec<_>>();
+
+                config
+                    .set_log_opts(&opts)
+                    .map_err(|e| e.for_sink(&name))?;
+
+                config
+                    .validate()
+                    .map_err(|e| e.for_sink(&name))?;
+
+                // so that one full queue doesn't hold up the other sinks
+                if several && config.queue_policy.is_none() {
+                    config.queue_policy = Some(QueuePolicy::DropOldest);
+                }
+
+                if several && config.queue_policy() == QueuePolicy::Block {
+                    return Err(ConfigError::InvalidValue {
+                        key: format!("{}.queue-policy", name),
+                        value: config.queue_policy().to_string(),
+                        reason: "a full queue would hold up the other sinks; use drop-oldest, drop-newest or spill".to_string(),
+                    });
+                }
+
+                Ok(SinkConfig {
+                    name,
+                    config,
+                })
+            })
+            .collect()
+    }
+
+    /// The ingest API URLs in `log-ingest-api`, in the order they were given
+    pub fn ingest_endpoints(&self) -> Vec<&str> {
+        self.log_ingest_api
+            .split(',')
+            .map(str::trim)
+            .filter(|endpoint| !endpoint.is_empty())
+            .collect()
+    }
+
+    /// Checks settings which parse successfully but are unusable
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let endpoints = self.ingest_endpoints();
+
+        if endpoints.is_empty() {
+            return Err(ConfigError::InvalidUrl {
+                key: "log-ingest-api".to_string(),
+                value: self.log_ingest_api.clone(),
+                reason: "no endpoints".to_string(),
+            });
+        }
+
+        for endpoint in endpoints {
+            validate_url("log-ingest-api", endpoint)?;
+        }
+
+        validate_url("loki-url", &self.loki_url)?;
+        validate_url("elasticsearch-url", &self.elasticsearch_url)?;
+        validate_url("splunk-url", &self.splunk_url)?;
+
+        if StrftimeItems::new(&self.elasticsearch_index).any(|item| item == Item::Error) {
+            return Err(ConfigError::InvalidValue {
+                key: "elasticsearch-index".to_string(),
+                value: self.elasticsearch_index.clone(),
+                reason: "invalid date format".to_string(),
+            });
+        }
+
+        if self.gelf_compression == Compression::Zstd {
+            return Err(ConfigError::InvalidValue {
+                key: "gelf-compression".to_string(),
+                value: self.gelf_compression.to_string(),
+                reason: "expected gzip or none".to_string(),
+            });
+        }
+
+        // UDP's limit, and enough for a chunk's header and some payload
+        if !(128..=65507).contains(&self.gelf_chunk_size) {
+            return Err(ConfigError::InvalidValue {
+                key: "gelf-chunk-size".to_string(),
+                value: self.gelf_chunk_size.to_string(),
+                reason: "must be between 128 and 65507".to_string(),
+            });
+        }
+
+        let at_least_one = [
+            ("max-file", self.max_file),
+            ("queue-size", self.queue_size),
+            ("batch-max-count", self.batch_max_count),
+            ("retry-max-attempts", self.retry_max_attempts as usize),
+            ("endpoint-unhealthy-after", self.endpoint_unhealthy_after as usize),
+            ("dead-letter-max-file", self.dead_letter_max_file),
+        ];
+
+        for (key, value) in at_least_one {
+            if value == 0 {
+                return Err(ConfigError::InvalidValue {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                    reason: "must be at least 1".to_string(),
+                });
+            }
+        }
+
+        if let Some(file_path) = &self.file_path {
+            if !is_contained(Path::new(file_path)) {
+                return Err(ConfigError::InvalidValue {
+                    key: "file-path".to_string(),
+                    value: file_path.clone(),
+                    reason: "must be a relative path without ..".to_string(),
+                });
+            }
+        }
+
+        if self.tls_cert_file.is_some() != self.tls_key_file.is_some() {
+            return Err(ConfigError::InvalidValue {
+                key: "tls-cert-file".to_string(),
+                value: self.tls_cert_file.clone().unwrap_or_default(),
+                reason: "tls-cert-file and tls-key-file must be set together".to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Applies log-opts.  If they point an endpoint elsewhere, the plugin's credentials
+    /// are cleared, other than those the log-opts set themselves, so they aren't sent
+    /// to an endpoint of the container's choosing.
+    fn set_log_opts(&mut self, opts: &[(String, &str)]) -> Result<(), ConfigError> {
+        let endpoints = self.endpoints();
+
+        for (key, value) in opts {
+            self.set_log_opt(key, value)?;
+        }
+
+        if self.endpoints() == endpoints {
+            return Ok(());
+        }
+
+        let set = |key: &str| opts.iter().any(|(k, _)| k == key);
+
+        if !set("auth-token") {
+            self.auth_token = None;
+        }
+        if !set("auth-username") {
+            self.auth_username = None;
+        }
+        if !set("auth-password") {
+            self.auth_password = None;
+        }
+        if !set("headers") {
+            self.headers = None;
+        }
+        if !set("splunk-token") {
+            self.splunk_token = None;
+        }
+
+        // files can't be set with log-opts, so are always the plugin's
+        self.auth_token_file = None;
+        self.auth_password_file = None;
+        self.headers_file = None;
+        self.splunk_token_file = None;
+        self.tls_cert_file = None;
+        self.tls_key_file = None;
+
+        Ok(())
+    }
+
+    fn endpoints(&self) -> [String; 4] {
+        [
+            self.log_ingest_api.clone(),
+            self.loki_url.clone(),
+            self.elasticsearch_url.clone(),
+            self.splunk_url.clone(),
+        ]
+    }
+
+    fn set_log_opt(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        if PLUGIN_ONLY_OPTS.contains(&key) {
+            return Err(ConfigError::PluginOnly(key.to_string()));
+        }
+
+        match key {
+            "log-ingest-api" => self.log_ingest_api = parse_value(key, value)?,
+            "load-balancing" => self.load_balancing = parse_value(key, value)?,
+            "endpoint-unhealthy-after" => self.endpoint_unhealthy_after = parse_value(key, value)?,
+            "endpoint-probe-interval" => self.endpoint_probe_interval = parse_value(key, value)?,
+            "max-size" => self.max_size = parse_value(key, value)?,
+            "max-file" => self.max_file = parse_value(key, value)?,
+            "keep-local" => self.keep_local = parse_value(key, value)?,
+            "partial-max-size" => self.partial_max_size = parse_value(key, value)?,
+            "partial-timeout" => self.partial_timeout = parse_value(key, value)?,
+            "max-frame-size" => self.max_frame_size = parse_value(key, value)?,
+            "skip-invalid-frames" => self.skip_invalid_frames = parse_value(key, value)?,
+            "queue-size" => self.queue_size = parse_value(key, value)?,
+            "queue-policy" => self.queue_policy = Some(parse_value(key, value)?),
+            "drop-report-interval" => self.drop_report_interval = parse_value(key, value)?,
+            "batch-max-count" => self.batch_max_count = parse_value(key, value)?,
+            "batch-max-size" => self.batch_max_size = parse_value(key, value)?,
+            "batch-linger" => self.batch_linger = parse_value(key, value)?,
+            "http-connect-timeout" => self.http_connect_timeout = parse_value(key, value)?,
+            "http-request-timeout" => self.http_request_timeout = parse_value(key, value)?,
+            "http-idle-timeout" => self.http_idle_timeout = parse_value(key, value)?,
+            "http-keepalive" => self.http_keepalive = parse_value(key, value)?,
+            "http-pool-max-idle" => self.http_pool_max_idle = parse_value(key, value)?,
+            "http2" => self.http2 = parse_value(key, value)?,
+            "compression" => self.compression = parse_value(key, value)?,
+            "compression-min-size" => self.compression_min_size = parse_value(key, value)?,
+            "auth-token" => self.auth_token = Some(parse_value(key, value)?),
+            "auth-username" => self.auth_username = Some(parse_value(key, value)?),
+            "auth-password" => self.auth_password = Some(parse_value(key, value)?),
+            "headers" => self.headers = Some(parse_value(key, value)?),
+            "tls-server-name" => self.tls_server_name = Some(parse_value(key, value)?),
+            "retry-max-attempts" => self.retry_max_attempts = parse_value(key, value)?,
+            "retry-initial-backoff" => self.retry_initial_backoff = parse_value(key, value)?,
+            "retry-max-backoff" => self.retry_max_backoff = parse_value(key, value)?,
+            "retry-max-elapsed" => self.retry_max_elapsed = parse_value(key, value)?,
+            "spool-max-size" => self.spool_max_size = parse_value(key, value)?,
+            "spool-eviction" => self.spool_eviction = parse_value(key, value)?,
+            "spool-replay-interval" => self.spool_replay_interval = parse_value(key, value)?,
+            "dead-letter" => self.dead_letter = parse_value(key, value)?,
+            "dead-letter-max-size" => self.dead_letter_max_size = parse_value(key, value)?,
+            "dead-letter-max-file" => self.dead_letter_max_file = parse_value(key, value)?,
+            "sinks" => self.sinks = parse_value(key, value)?,
+            "sink" => self.sink = parse_value(key, value)?,
+            "file-path" => self.file_path = Some(parse_value(key, value)?),
+            "loki-url" => self.loki_url = parse_value(key, value)?,
+            "loki-encoding" => self.loki_encoding = parse_value(key, value)?,
+            "loki-labels" => self.loki_labels = parse_value(key, value)?,
+            "loki-tenant" => self.loki_tenant = Some(parse_value(key, value)?),
+            "elasticsearch-url" => self.elasticsearch_url = parse_value(key, value)?,
+            "elasticsearch-index" => self.elasticsearch_index = parse_value(key, value)?,
+            "splunk-url" => self.splunk_url = parse_value(key, value)?,
+            "splunk-token" => self.splunk_token = Some(parse_value(key, value)?),
+            "splunk-index" => self.splunk_index = Some(parse_value(key, value)?),
+            "splunk-sourcetype" => self.splunk_sourcetype = Some(parse_value(key, value)?),
+            "splunk-host" => self.splunk_host = Some(parse_value(key, value)?),
+            "splunk-ack" => self.splunk_ack = parse_value(key, value)?,
+            "splunk-ack-interval" => self.splunk_ack_interval = parse_value(key, value)?,
+            "splunk-ack-timeout" => self.splunk_ack_timeout = parse_value(key, value)?,
+            "gelf-address" => self.gelf_address = parse_value(key, value)?,
+            "gelf-host" => self.gelf_host = Some(parse_value(key, value)?),
+            "gelf-compression" => self.gelf_compression = parse_value(key, value)?,
+            "gelf-chunk-size" => self.gelf_chunk_size = parse_value(key, value)?,
+            "syslog-address" => self.syslog_address = parse_value(key, value)?,
+            "syslog-format" => self.syslog_format = parse_value(key, value)?,
+            "syslog-framing" => self.syslog_framing = parse_value(key, value)?,
+            "syslog-facility" => self.syslog_facility = parse_value(key, value)?,
+            "syslog-app-name" => self.syslog_app_name = parse_value(key, value)?,
+            "syslog-hostname" => self.syslog_hostname = Some(parse_value(key, value)?),
+            "syslog-severities" => self.syslog_severities = parse_value(key, value)?,
+            _ => return Err(ConfigError::UnknownOption(key.to_string())),
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!("1024".parse::<ByteSize>().unwrap(), ByteSize(1024));
+        assert_eq!("10k".parse::<ByteSize>().unwrap(), ByteSize(10 * 1024));
+        assert_eq!("20m".parse::<ByteSize>().unwrap(), ByteSize(20 * 1024 * 1024));
+        assert_eq!("1GB".parse::<ByteSize>().unwrap(), ByteSize(1024 * 1024 * 1024));
+
+        assert!("".parse::<ByteSize>().is_err());
+        assert!("ten".parse::<ByteSize>().is_err());
+        assert!("-1m".parse::<ByteSize>().is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        use std::time::Duration;
+
+        assert_eq!("250ms".parse::<HumanDuration>().unwrap(), HumanDuration(Duration::from_millis(250)));
+        assert_eq!("5".parse::<HumanDuration>().unwrap(), HumanDuration(Duration::from_secs(5)));
+        assert_eq!("5s".parse::<HumanDuration>().unwrap(), HumanDuration(Duration::from_secs(5)));
+        assert_eq!("2m".parse::<HumanDuration>().unwrap(), HumanDuration(Duration::from_secs(120)));
+        assert_eq!("1h".parse::<HumanDuration>().unwrap(), HumanDuration(Duration::from_secs(3600)));
+
+        assert!("".parse::<HumanDuration>().is_err());
+        assert!("5d".parse::<HumanDuration>().is_err());
+        assert!("s".parse::<HumanDuration>().is_err());
+    }
+
+    fn config() -> Config {
+        Config::init_from_hashmap(&HashMap::new())
+            .unwrap()
+    }
+
+    fn opts(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_log_opts_override_env() {
+        let config = config()
+            .with_log_opts(&opts(&[
+                ("LOG_INGEST_API", "http://ingest:9080"),
+                ("max-size", "1m"),
+                ("max_file", "2"),
+                ("keep-local", "false"),
+                ("mode", "non-blocking"),
+            ]))
+            .unwrap();
+
+        assert_eq!(config.log_ingest_api, "http://ingest:9080");
+        assert_eq!(config.max_size, ByteSize(1024 * 1024));
+        assert_eq!(config.max_file, 2);
+        assert!(!config.keep_local);
+    }
+
+    #[test]
+    fn test_log_opts_defaults_to_env() {
+        let config = config()
+            .with_log_opts(&HashMap::new())
+            .unwrap();
+
+        assert_eq!(config.log_ingest_api, "http://localhost:8080");
+        assert_eq!(config.max_file, 5);
+    }
+
+    #[test]
+    fn test_invalid_log_opt() {
+        let results = config()
+            .with_log_opts(&opts(&[("max-file", "many")]));
+
+        assert!(matches!(results, Err(ConfigError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn test_zero_log_opt() {
+        for key in ["max-file", "queue-size", "batch-max-count"] {
+            let results = config()
+                .with_log_opts(&opts(&[(key, "0")]));
+
+            assert!(matches!(results, Err(ConfigError::InvalidValue { .. })), "{} should be invalid", key);
+        }
+    }
+
+    #[test]
+    fn test_unknown_log_opt() {
+        let results = config()
+            .with_log_opts(&opts(&[("not-an-option", "true")]));
+
+        assert!(matches!(results, Err(ConfigError::UnknownOption(key)) if key == "not-an-option"));
+    }
+
+    #[test]
+    fn test_invalid_url_log_opt() {
+        for url in ["not a url", "ftp://ingest:9080", "http://"] {
+            let results = config()
+                .with_log_opts(&opts(&[("log-ingest-api", url)]));
+
+            assert!(matches!(results, Err(ConfigError::InvalidUrl { .. })), "{} should be invalid", url);
+        }
+    }
+
+    #[test]
+    fn test_tls_cert_requires_key() {
+        let env = |vars: &[(&str, &str)]| Config::init_from_hashmap(
+            &vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        ).unwrap();
+
+        let results = env(&[("TLS_CERT_FILE", "/etc/ssl/client.pem")])
+            .validate();
+
+        assert!(matches!(results, Err(ConfigError::InvalidValue { .. })));
+
+        let config = env(&[("TLS_CERT_FILE", "/etc/ssl/client.pem"), ("TLS_KEY_FILE", "/etc/ssl/client.key")]);
+
+        assert!(config.validate().is_ok());
+        assert_eq!(config.tls_key_file.as_deref(), Some("/etc/ssl/client.key"));
+    }
+
+
+    #[test]
+    fn test_plugin_only_log_opts() {
+        for key in PLUGIN_ONLY_OPTS {
+            let results = config()
+                .with_log_opts(&opts(&[(key, "/etc/shadow")]));
+
+            assert!(matches!(results, Err(ConfigError::PluginOnly(_))), "{} should be rejected", key);
+        }
+    }
+
+
+    #[test]
+    fn test_endpoint_override_drops_credentials() {
+        let plugin = Config {
+            auth_token: Some("plugin-token".parse().unwrap()),
+            headers: Some("X-Api-Key=plugin-key".parse().unwrap()),
+            splunk_token_file: Some("/etc/splunk-token".to_string()),
+            tls_cert_file: Some("/etc/ssl/client.crt".to_string()),
+            tls_key_file: Some("/etc/ssl/client.key".to_string()),
+            ..config()
+        };
+
+        // the plugin's endpoint keeps the plugin's credentials
+        let config = plugin
+            .with_log_opts(&opts(&[("log-ingest-api", "http://localhost:8080"), ("queue-size", "10")]))
+            .unwrap();
+        let headers = crate::auth::headers(&config)
+            .unwrap();
+
+        assert_eq!(headers["authorization"], "Bearer plugin-token");
+
+        let config = plugin
+            .with_log_opts(&opts(&[("log-ingest-api", "http://attacker:8080")]))
+            .unwrap();
+        let headers = crate::auth::headers(&config)
+            .unwrap();
+
+        assert!(headers.get("authorization").is_none());
+        assert!(headers.get("x-api-key").is_none());
+        assert!(config.splunk_token_file.is_none());
+        assert!(config.tls_cert_file.is_none() && config.tls_key_file.is_none());
+
+        // unless the container brings its own
+        let config = plugin
+            .with_log_opts(&opts(&[("loki-url", "http://loki:3100"), ("auth-token", "own-token")]))
+            .unwrap();
+        let headers = crate::auth::headers(&config)
+            .unwrap();
+
+        assert_eq!(headers["authorization"], "Bearer own-token");
+        assert!(headers.get("x-api-key").is_none());
+
+        // a single sink's endpoint only drops that sink's credentials
+        let opts = opts(&[
+            ("sinks", "ingest,loki"),
+            ("queue-policy", "drop-oldest"),
+            ("loki.loki-url", "http://attacker:3100"),
+        ]);
+        let sinks = plugin
+            .with_log_opts(&opts)
+            .unwrap()
+            .sinks(&opts)
+            .unwrap();
+
+        assert_eq!(crate::auth::headers(&sinks[0].config).unwrap()["authorization"], "Bearer plugin-token");
+        assert!(crate::auth::headers(&sinks[1].config).unwrap().get("authorization").is_none());
+    }
+
+
+    #[test]
+    fn test_file_path_contained() {
+        for value in ["/var/log/{id}.ndjson", "../{id}.ndjson", "logs/../../{id}.ndjson"] {
+            let results = config()
+                .with_log_opts(&opts(&[("file-path", value)]));
+
+            assert!(matches!(results, Err(ConfigError::InvalidValue { .. })), "{} should be invalid", value);
+        }
+
+        let config = config()
+            .with_log_opts(&opts(&[("file-path", "./{name}/{id}.ndjson")]))
+            .unwrap();
+
+        assert_eq!(config.file_path.as_deref(), Some("./{name}/{id}.ndjson"));
+    }
+
+
+    #[test]
+    fn test_multiple_endpoints() {
+        let config = config()
+            .with_log_opts(&opts(&[("log-ingest-api", "http://zone-a:8080, http://zone-b:8080")]))
+            .unwrap();
+
+        assert_eq!(config.ingest_endpoints(), vec!["http://zone-a:8080", "http://zone-b:8080"]);
+
+        for value in [",", "http://zone-a:8080,not a url"] {
+            let results = config
+                .with_log_opts(&opts(&[("log-ingest-api", value)]));
+
+            assert!(matches!(results, Err(ConfigError::InvalidUrl { .. })), "{} should be invalid", value);
+        }
+    }
+
+    #[test]
+    fn test_elasticsearch_index() {
+        let results = config()
+            .with_log_opts(&opts(&[("elasticsearch-index", "logs-%Q")]));
+
+        assert!(matches!(results, Err(ConfigError::InvalidValue { key, .. }) if key == "elasticsearch-index"));
+    }
+
+    #[test]
+    fn test_single_sink() {
+        let config = config()
+            .with_log_opts(&HashMap::new())
+            .unwrap();
+        let sinks = config
+            .sinks(&HashMap::new())
+            .unwrap();
+
+        assert_eq!(sinks.len(), 1);
+        assert_eq!(sinks[0].name, "ingest");
+        assert_eq!(sinks[0].config.state_dir, config.state_dir);
+
+        // sink-prefixed options need `sinks`
+        let results = config
+            .with_log_opts(&opts(&[("ingest.queue-size", "10")]));
+
+        assert!(matches!(results, Err(ConfigError::UnknownOption(key)) if key == "ingest.queue-size"));
+    }
+
+    #[test]
+    fn test_sinks() {
+        let opts = opts(&[
+            ("sinks", "ingest, file,Archive"),
+            ("queue-size", "100"),
+            ("queue-policy", "spill"),
+            ("ingest.log-ingest-api", "http://new-ingest:8080"),
+            ("file.queue_size", "10"),
+            ("ARCHIVE.SINK", "file"),
+            ("archive.file-path", "archive/{id}.ndjson"),
+        ]);
+        let config = config()
+            .with_log_opts(&opts)
+            .unwrap();
+        let sinks = config
+            .sinks(&opts)
+            .unwrap();
+
+        let names = sinks
+            .iter()
+            .map(|sink| sink.name.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, vec!["ingest", "file", "archive"]);
+        assert_eq!(sinks[0].config.sink, SinkKind::Ingest);
+        assert_eq!(sinks[0].config.log_ingest_api, "http://new-ingest:8080");
+        assert_eq!(sinks[0].config.queue_size, 100);
+        assert_eq!(sinks[0].config.state_dir, "/var/lib/docker-log-driver/sinks/ingest");
+        assert_eq!(sinks[1].config.sink, SinkKind::File);
+        assert_eq!(sinks[1].config.queue_size, 10);
+        assert_eq!(sinks[2].config.sink, SinkKind::File);
+        assert_eq!(sinks[2].config.file_path.as_deref(), Some("archive/{id}.ndjson"));
+
+        // the container's own settings aren't changed by a sink's
+        assert_eq!(config.log_ingest_api, "http://localhost:8080");
+    }
+
+    #[test]
+    fn test_sinks_queue_policy() {
+        let opts = opts(&[("sinks", "ingest,file"), ("file.queue-policy", "spill")]);
+        let sinks = config()
+            .with_log_opts(&opts)
+            .unwrap()
+            .sinks(&opts)
+            .unwrap();
+
+        // unless set, several sinks drop messages rather than block each other
+        assert_eq!(sinks[0].config.queue_policy(), QueuePolicy::DropOldest);
+        assert_eq!(sinks[1].config.queue_policy(), QueuePolicy::Spill);
+
+        let sinks = config()
+            .sinks(&HashMap::from([("sinks".to_string(), "ingest".to_string())]))
+            .unwrap();
+
+        assert_eq!(sinks[0].config.queue_policy(), QueuePolicy::Block);
+    }
+
+    #[test]
+    fn test_invalid_sinks() {
+        let invalid = [
+            vec![("sinks", "ingest,ingest")],
+            vec![("sinks", "in.gest")],
+            vec![("sinks", "ingest"), ("file.queue-size", "10")],
+            vec![("sinks", "ingest"), ("ingest.queue-size", "0")],
+            vec![("sinks", "ingest"), ("ingest.sinks", "file")],
+        ];
+
+        for pairs in invalid {
+            assert!(config().with_log_opts(&opts(&pairs)).is_err(), "{:?} should be invalid", pairs);
+        }
+
+        let results = config()
+            .with_log_opts(&opts(&[("sinks", "ingest"), ("ingest.max-file", "many")]));
+
+        assert!(matches!(results, Err(ConfigError::InvalidValue { key, .. }) if key == "ingest.max-file"));
+
+        // one sink's full queue would block the others
+        let results = config()
+            .with_log_opts(&opts(&[("sinks", "ingest,file"), ("queue-policy", "drop-newest"), ("file.queue-policy", "block")]));
+
+        assert!(matches!(results, Err(ConfigError::InvalidValue { key, .. }) if key == "file.queue-policy"));
+        assert!(config().with_log_opts(&opts(&[("sinks", "ingest,file"), ("queue-policy", "block")])).is_err());
+        assert!(config().with_log_opts(&opts(&[("sinks", "ingest"), ("queue-policy", "block")])).is_ok());
+    }
 }
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use chrono::{
+    DateTime,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Deserializer,
+    Serialize,
+};
+use serde_json::{
+    Map,
+    Value,
+};
+
+
+// Go encodes nil maps and slices as null
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer)
+        .map(Option::unwrap_or_default)
+}
+
+
+/// Metadata Docker sends about a container in `LogDriver.StartLogging` and
+/// `LogDriver.ReadLogs`; see `logger.Info` in moby.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContainerInfo {
+    // --log-opt values for the container
+    #[serde(rename = "Config", deserialize_with = "null_as_default")]
+    pub config: HashMap<String, String>,
+
+    #[serde(rename = "ContainerID")]
+    pub container_id: String,
+
+    #[serde(rename = "ContainerName")]
+    pub container_name: String,
+
+    #[serde(rename = "ContainerEntrypoint")]
+    pub container_entrypoint: String,
+
+    #[serde(rename = "ContainerArgs", deserialize_with = "null_as_default")]
+    pub container_args: Vec<String>,
+
+    #[serde(rename = "ContainerImageID")]
+    pub container_image_id: String,
+
+    #[serde(rename = "ContainerImageName")]
+    pub container_image_name: String,
+
+    #[serde(rename = "ContainerCreated")]
+    pub container_created: Option<DateTime<Utc>>,
+
+    // not written out with dead letters, as it may hold secrets
+    #[serde(rename = "ContainerEnv", deserialize_with = "null_as_default", skip_serializing)]
+    pub container_env: Vec<String>,
+
+    #[serde(rename = "ContainerLabels", deserialize_with = "null_as_default")]
+    pub container_labels: HashMap<String, String>,
+
+    #[serde(rename = "LogPath")]
+    pub log_path: String,
+
+    #[serde(rename = "DaemonName")]
+    pub daemon_name: String,
+}
+
+
+impl ContainerInfo {
+    /// Container name without the leading `/` Docker includes
+    pub fn name(&self) -> &str {
+        self.container_name
+            .trim_start_matches('/')
+    }
+
+    /// First 12 characters of the container ID, as shown by `docker ps`
+    pub fn short_id(&self) -> &str {
+        self.container_id
+            .get(..12)
+            .unwrap_or(&self.container_id)
+    }
+
+    /// Container metadata attached to the context of every LogMessage
+    pub fn context(&self) -> Map<String, Value> {
+        let mut context = Map::new();
+
+        context.insert("container_id".to_string(), Value::String(self.container_id.clone()));
+        context.insert("container_name".to_string(), Value::String(self.name().to_string()));
+        context.insert("image_id".to_string(), Value::String(self.container_image_id.clone()));
+        context.insert("image_name".to_string(), Value::String(self.container_image_name.clone()));
+
+        context
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_info() {
+        let info: ContainerInfo = serde_json::from_value(serde_json::json!({
+            "Config": {
+                "max-size": "10m",
+            },
+            "ContainerID": "0123456789abcdef",
+            "ContainerName": "/web",
+            "ContainerEntrypoint": "/bin/sh",
+            "ContainerArgs": ["-c", "echo hello"],
+            "ContainerImageID": "sha256:abc",
+            "ContainerImageName": "alpine:latest",
+            "ContainerCreated": "2023-01-01T00:00:00.123456789Z",
+            "ContainerEnv": ["PATH=/bin"],
+            "ContainerLabels": {
+                "com.docker.compose.service": "web",
+            },
+            "LogPath": "",
+            "DaemonName": "docker",
+        })).unwrap();
+
+        assert_eq!(info.name(), "web");
+        assert_eq!(info.short_id(), "0123456789ab");
+        assert_eq!(info.config.get("max-size").unwrap(), "10m");
+        assert_eq!(info.container_args, vec!["-c", "echo hello"]);
+        assert_eq!(info.container_labels.get("com.docker.compose.service").unwrap(), "web");
+        assert!(info.container_created.is_some());
+
+        assert_eq!(Value::Object(info.context()), serde_json::json!({
+            "container_id": "0123456789abcdef",
+            "container_name": "web",
+            "image_id": "sha256:abc",
+            "image_name": "alpine:latest",
+        }));
+    }
+
+    #[test]
+    fn test_deserialize_minimal_info() {
+        let info: ContainerInfo = serde_json::from_value(serde_json::json!({
+            "Config": null,
+            "ContainerID": "abc",
+            "ContainerArgs": null,
+            "ContainerLabels": null,
+        })).unwrap();
+
+        assert_eq!(info.short_id(), "abc");
+        assert!(info.config.is_empty());
+        assert!(info.container_args.is_empty());
+        assert!(info.container_labels.is_empty());
+    }
+}
@@ -0,0 +1,410 @@
+use std::{
+    fs::TryLockError,
+    os::unix::fs::MetadataExt,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use chrono::{
+    DateTime,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tokio::{
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::AsyncWriteExt,
+};
+
+use crate::{
+    client::IngestError,
+    config::Config,
+    container::ContainerInfo,
+    log::LogMessage,
+};
+
+
+const FILE_NAME: &str = "dead-letter.ndjson";
+const SOURCE_FILE_NAME: &str = "source.json";
+
+
+/// A message the ingest API wouldn't accept, and why
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetterRecord {
+    pub rejected_at: DateTime<Utc>,
+    // status of the response which rejected the whole batch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    pub reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    pub message: LogMessage,
+}
+
+impl DeadLetterRecord {
+    pub fn new<S: Into<String>>(message: LogMessage, reason: S) -> Self {
+        Self {
+            rejected_at: Utc::now(),
+            status: None,
+            reason: reason.into(),
+            body: None,
+            message,
+        }
+    }
+
+    /// A message from a batch which failed, with the response's status and body
+    pub fn from_error(message: LogMessage, err: &IngestError) -> Self {
+        Self {
+            status: err
+                .status()
+                .map(|status| status.as_u16()),
+            body: err
+                .body()
+                .filter(|body| !body.is_empty())
+                .map(|body| body.to_string()),
+            ..Self::new(message, err.to_string())
+        }
+    }
+}
+
+
+/// The sink and container a dead-letter directory's messages were sent from, so they
+/// can be resubmitted the same way
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetterSource {
+    pub sink: String,
+    pub container: ContainerInfo,
+}
+
+/// Reads the source recorded in a dead-letter directory, if any
+pub async fn read_source(dir: &Path) -> Result<Option<DeadLetterSource>, std::io::Error> {
+    match tokio::fs::read(dir.join(SOURCE_FILE_NAME)).await {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+
+/// Rotation limits for a container's dead-letter file
+#[derive(Debug, Clone, Copy)]
+pub struct DeadLetterOptions {
+    pub max_size: u64,
+    pub max_file: usize,
+}
+
+impl From<&Config> for DeadLetterOptions {
+    fn from(config: &Config) -> Self {
+        Self {
+            max_size: config.dead_letter_max_size.0,
+            max_file: config.dead_letter_max_file.max(1),
+        }
+    }
+}
+
+
+/// Returns the path of a dead-letter file; 0 is the file being written, and higher
+/// numbers are older, rotated files
+pub fn file_path(dir: &Path, generation: usize) -> PathBuf {
+    match generation {
+        0 => dir.join(FILE_NAME),
+        n => dir.join(format!("{}.{}", FILE_NAME, n)),
+    }
+}
+
+/// Lists the dead-letter files in a directory, oldest first
+pub fn list_files(dir: &Path) -> Vec<PathBuf> {
+    let mut generations = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry
+                .file_name()
+                .into_string()
+                .ok()?;
+
+            match name.strip_prefix(FILE_NAME)? {
+                "" => Some(0),
+                suffix => suffix
+                    .strip_prefix('.')?
+                    .parse::<usize>()
+                    .ok(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    generations.sort_unstable_by(|a, b| b.cmp(a));
+    generations
+        .into_iter()
+        .map(|generation| file_path(dir, generation))
+        .collect()
+}
+
+fn encode_records(records: &[DeadLetterRecord]) -> Result<Vec<u8>, std::io::Error> {
+    let mut lines = Vec::new();
+
+    for record in records {
+        serde_json::to_writer(&mut lines, record)?;
+        lines.push(b'\n');
+    }
+
+    Ok(lines)
+}
+
+pub async fn read_records(path: &Path) -> Result<Vec<DeadLetterRecord>, std::io::Error> {
+    tokio::fs::read_to_string(path)
+        .await?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(std::io::Error::from))
+        .collect()
+}
+
+/// Locks a dead-letter file so it can be rewritten, or returns `None` if the plugin
+/// has it open for writing.  The file is unlocked when the returned handle is dropped.
+pub async fn try_lock(path: &Path) -> Result<Option<std::fs::File>, std::io::Error> {
+    let file = File::open(path)
+        .await?
+        .into_std()
+        .await;
+
+    match file.try_lock() {
+        Ok(()) => Ok(Some(file)),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(err)) => Err(err),
+    }
+}
+
+/// Replaces the records in a dead-letter file, removing it if there are none left
+pub async fn rewrite_records(path: &Path, records: &[DeadLetterRecord]) -> Result<(), std::io::Error> {
+    if records.is_empty() {
+        return tokio::fs::remove_file(path)
+            .await;
+    }
+
+    let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+
+    tokio::fs::write(&tmp, encode_records(records)?)
+        .await?;
+    tokio::fs::rename(tmp, path)
+        .await
+}
+
+
+/// Newline delimited JSON file of a container's rejected messages, so they can be
+/// inspected or resubmitted rather than lost.
+///
+/// Once the file would exceed `max_size` it is rotated like a log file, to
+/// `dead-letter.ndjson.1` and so on, keeping at most `max_file` files.
+pub struct DeadLetter {
+    dir: PathBuf,
+    options: DeadLetterOptions,
+    file: File,
+    size: u64,
+}
+
+impl DeadLetter {
+    pub fn dir<P: AsRef<Path>>(root: P, container_id: &str) -> PathBuf {
+        root
+            .as_ref()
+            .join("dead-letter")
+            .join(container_id)
+    }
+
+    pub async fn open<P: AsRef<Path>>(root: P, container_id: &str, options: DeadLetterOptions) -> Result<Self, std::io::Error> {
+        let dir = Self::dir(root, container_id);
+
+        tokio::fs::create_dir_all(&dir)
+            .await?;
+
+        let file = Self::open_file(&dir)
+            .await?;
+        let size = file
+            .metadata()
+            .await?
+            .len();
+
+        Ok(Self {
+            dir,
+            options,
+            file,
+            size,
+        })
+    }
+
+    /// Opens the file being written, holding a shared lock on it for as long as it's
+    /// open so that `resubmit` leaves it alone
+    async fn open_file(dir: &Path) -> Result<File, std::io::Error> {
+        let path = file_path(dir, 0);
+
+        loop {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?
+                .into_std()
+                .await;
+
+            // only waits while the file is being resubmitted
+            let file = tokio::task::spawn_blocking(move || file.lock_shared().map(|_| file))
+                .await
+                .map_err(std::io::Error::other)??;
+            let opened = file.metadata()?;
+
+            // a resubmission may have replaced or removed the file in the meantime
+            match tokio::fs::metadata(&path).await {
+                Ok(current) if (current.dev(), current.ino()) == (opened.dev(), opened.ino()) => {
+                    return Ok(File::from_std(file));
+                },
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+                _ => continue,
+            }
+        }
+    }
+
+    pub fn path(&self) -> PathBuf {
+        file_path(&self.dir, 0)
+    }
+
+    /// Records where the messages written come from; see `read_source`
+    pub async fn write_source(&self, source: &DeadLetterSource) -> Result<(), std::io::Error> {
+        let tmp = self.dir.join(format!("{}.tmp", SOURCE_FILE_NAME));
+
+        tokio::fs::write(&tmp, serde_json::to_vec(source)?)
+            .await?;
+        tokio::fs::rename(tmp, self.dir.join(SOURCE_FILE_NAME))
+            .await
+    }
+
+    pub async fn write(&mut self, records: &[DeadLetterRecord]) -> Result<(), std::io::Error> {
+        let lines = encode_records(records)?;
+
+        if self.size > 0 && self.size + lines.len() as u64 > self.options.max_size {
+            self.rotate()
+                .await?;
+        }
+
+        self.file
+            .write_all(&lines)
+            .await?;
+        self.file
+            .flush()
+            .await?;
+
+        self.size += lines.len() as u64;
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> Result<(), std::io::Error> {
+        let oldest = self.options.max_file - 1;
+
+        match oldest {
+            0 => tokio::fs::remove_file(file_path(&self.dir, 0)).await?,
+            _ => {
+                for generation in (0..oldest).rev() {
+                    match tokio::fs::rename(file_path(&self.dir, generation), file_path(&self.dir, generation + 1)).await {
+                        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+                        _ => (),
+                    }
+                }
+            },
+        }
+
+        self.file = Self::open_file(&self.dir)
+            .await?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> DeadLetterOptions {
+        DeadLetterOptions {
+            max_size: 1024 * 1024,
+            max_file: 3,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let mut dead_letter = DeadLetter::open(dir.path(), "container", options())
+            .await
+            .unwrap();
+
+        let err = IngestError::Status {
+            status: reqwest::StatusCode::BAD_REQUEST,
+            retry_after: None,
+            body: r#"{"error": "invalid level"}"#.to_string(),
+        };
+
+        dead_letter.write(&[
+            DeadLetterRecord::from_error(LogMessage::dropped(1), &err),
+            DeadLetterRecord::from_error(LogMessage::dropped(2), &err),
+        ]).await.unwrap();
+
+        // appended to after reopening
+        let mut dead_letter = DeadLetter::open(dir.path(), "container", options())
+            .await
+            .unwrap();
+
+        dead_letter.write(&[DeadLetterRecord::new(LogMessage::dropped(3), "rejected")]).await.unwrap();
+
+        let records = read_records(&dead_letter.path())
+            .await
+            .unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].status, Some(400));
+        assert_eq!(records[0].body.as_deref(), Some(r#"{"error": "invalid level"}"#));
+        assert_eq!(records[2].status, None);
+        assert_eq!(records[2].message.message, "3 messages dropped");
+    }
+
+    #[tokio::test]
+    async fn test_rotation() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let options = DeadLetterOptions {
+            max_size: 1,
+            max_file: 3,
+        };
+        let mut dead_letter = DeadLetter::open(dir.path(), "container", options)
+            .await
+            .unwrap();
+
+        for count in 1..=4 {
+            dead_letter.write(&[DeadLetterRecord::new(LogMessage::dropped(count), "rejected")]).await.unwrap();
+        }
+
+        let files = list_files(&DeadLetter::dir(dir.path(), "container"));
+        let mut messages = Vec::new();
+
+        for file in files.iter() {
+            for record in read_records(file).await.unwrap() {
+                messages.push(record.message.message);
+            }
+        }
+
+        // the oldest file was removed
+        assert_eq!(files.len(), 3);
+        assert_eq!(messages, vec!["2 messages dropped", "3 messages dropped", "4 messages dropped"]);
+    }
+}
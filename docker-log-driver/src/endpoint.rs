@@ -0,0 +1,346 @@
+use std::{
+    collections::{
+        hash_map::DefaultHasher,
+        HashMap,
+    },
+    fmt,
+    hash::{
+        Hash,
+        Hasher,
+    },
+    str::FromStr,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::time::Instant;
+use tracing::{
+    info,
+    warn,
+};
+
+use crate::{
+    config::Config,
+    metrics,
+};
+
+
+/// How batches are spread over a container's ingest endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadBalancing {
+    // the first healthy endpoint, in the order they are configured
+    Failover,
+    RoundRobin,
+    // a healthy endpoint chosen by container ID, so a container sticks to one endpoint
+    Hash,
+}
+
+impl FromStr for LoadBalancing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "failover" => Ok(LoadBalancing::Failover),
+            "round-robin" => Ok(LoadBalancing::RoundRobin),
+            "hash" => Ok(LoadBalancing::Hash),
+            _ => Err(format!("Invalid load balancing: {}; expected failover, round-robin or hash", s)),
+        }
+    }
+}
+
+impl fmt::Display for LoadBalancing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadBalancing::Failover => write!(f, "failover"),
+            LoadBalancing::RoundRobin => write!(f, "round-robin"),
+            LoadBalancing::Hash => write!(f, "hash"),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct EndpointOptions {
+    pub balancing: LoadBalancing,
+    // consecutive failures after which an endpoint is marked unhealthy
+    pub unhealthy_after: u32,
+    pub probe_interval: Duration,
+}
+
+impl From<&Config> for EndpointOptions {
+    fn from(config: &Config) -> Self {
+        Self {
+            balancing: config.load_balancing,
+            unhealthy_after: config.endpoint_unhealthy_after.max(1),
+            probe_interval: config.endpoint_probe_interval.0,
+        }
+    }
+}
+
+
+#[derive(Debug, Default)]
+struct Health {
+    failures: u32,
+    // set while the endpoint is unhealthy
+    probe_at: Option<Instant>,
+}
+
+
+/// Health of ingest endpoints by URL, shared through `HttpClients` so that once one
+/// container finds an endpoint down, the others take it out of rotation too
+#[derive(Clone, Default)]
+pub struct EndpointHealth {
+    endpoints: Arc<Mutex<HashMap<String, Arc<Mutex<Health>>>>>,
+}
+
+impl EndpointHealth {
+    fn get(&self, url: &str) -> Arc<Mutex<Health>> {
+        self.endpoints
+            .lock()
+            .expect("Endpoint health lock poisoned")
+            .entry(url.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+
+struct Endpoint {
+    url: String,
+    health: Arc<Mutex<Health>>,
+}
+
+impl Endpoint {
+    fn health(&self) -> std::sync::MutexGuard<'_, Health> {
+        self.health
+            .lock()
+            .expect("Endpoint health lock poisoned")
+    }
+}
+
+
+/// Selection of a container's ingest endpoints, and their health.
+///
+/// Endpoints are marked unhealthy after `unhealthy_after` consecutive failures and
+/// taken out of rotation.  Once `probe_interval` has passed they are due a probe, and
+/// are put back once one succeeds.  Health is shared with every other container
+/// sending to the same endpoints.
+pub struct Endpoints {
+    endpoints: Vec<Endpoint>,
+    options: EndpointOptions,
+    // where the rotation starts; advanced per batch for round-robin
+    start: usize,
+}
+
+impl Endpoints {
+    /// `key` picks the endpoint when hashing, e.g. the container ID
+    pub fn new(urls: Vec<String>, options: EndpointOptions, key: &str, health: &EndpointHealth) -> Self {
+        let start = match options.balancing {
+            LoadBalancing::Hash if !urls.is_empty() => {
+                let mut hasher = DefaultHasher::new();
+
+                key.hash(&mut hasher);
+                (hasher.finish() % urls.len() as u64) as usize
+            },
+            _ => 0,
+        };
+
+        Self {
+            endpoints: urls
+                .into_iter()
+                .map(|url| Endpoint {
+                    health: health.get(&url),
+                    url,
+                })
+                .collect(),
+            options,
+            start,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    pub fn is_healthy(&self, index: usize) -> bool {
+        self.endpoints[index].health().probe_at.is_none()
+    }
+
+    /// Unhealthy endpoints whose probe interval has passed
+    pub fn due_probes(&self) -> Vec<usize> {
+        let now = Instant::now();
+
+        (0..self.len())
+            .filter(|index| self.endpoints[*index].health().probe_at.is_some_and(|probe_at| probe_at <= now))
+            .collect()
+    }
+
+    /// Returns the endpoints to try for the next batch, in order.  If every endpoint is
+    /// unhealthy they are all tried, rather than giving up on the batch.
+    pub fn order(&mut self) -> Vec<usize> {
+        let count = self.len();
+        let rotation = (0..count)
+            .map(|offset| (self.start + offset) % count)
+            .collect::<Vec<_>>();
+
+        if self.options.balancing == LoadBalancing::RoundRobin && count > 0 {
+            self.start = (self.start + 1) % count;
+        }
+
+        let healthy = rotation
+            .iter()
+            .copied()
+            .filter(|index| self.is_healthy(*index))
+            .collect::<Vec<_>>();
+
+        match healthy.is_empty() {
+            true => rotation,
+            false => healthy,
+        }
+    }
+
+    pub fn succeeded(&mut self, index: usize) {
+        let endpoint = &self.endpoints[index];
+        let mut health = endpoint.health();
+
+        if health.probe_at.is_some() {
+            info!(endpoint = %metrics::label(&endpoint.url), "Ingest endpoint is healthy again");
+        }
+
+        health.failures = 0;
+        health.probe_at = None;
+    }
+
+    pub fn failed(&mut self, index: usize) {
+        let options = self.options;
+        let endpoint = &self.endpoints[index];
+        let mut health = endpoint.health();
+
+        health.failures += 1;
+
+        if health.failures >= options.unhealthy_after {
+            if health.probe_at.is_none() {
+                warn!(
+                    endpoint = %metrics::label(&endpoint.url),
+                    failures = health.failures,
+                    "Marking ingest endpoint unhealthy; probing it again in {:?}", options.probe_interval,
+                );
+            }
+
+            health.probe_at = Some(Instant::now() + options.probe_interval);
+        }
+    }
+
+    pub fn probed(&mut self, index: usize, healthy: bool) {
+        match healthy {
+            true => self.succeeded(index),
+            false => self.endpoints[index].health().probe_at = Some(Instant::now() + self.options.probe_interval),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared(balancing: LoadBalancing, key: &str, health: &EndpointHealth) -> Endpoints {
+        let urls = ["http://a:8080", "http://b:8080", "http://c:8080"]
+            .iter()
+            .map(|url| url.to_string())
+            .collect();
+
+        Endpoints::new(urls, EndpointOptions {
+            balancing,
+            unhealthy_after: 2,
+            probe_interval: Duration::ZERO,
+        }, key, health)
+    }
+
+    fn endpoints(balancing: LoadBalancing, key: &str) -> Endpoints {
+        shared(balancing, key, &EndpointHealth::default())
+    }
+
+    #[test]
+    fn test_failover() {
+        let mut endpoints = endpoints(LoadBalancing::Failover, "container");
+
+        assert_eq!(endpoints.order(), vec![0, 1, 2]);
+        assert_eq!(endpoints.order(), vec![0, 1, 2]);
+
+        // a single failure doesn't take an endpoint out of rotation
+        endpoints.failed(0);
+        assert_eq!(endpoints.order(), vec![0, 1, 2]);
+
+        endpoints.failed(0);
+        assert!(!endpoints.is_healthy(0));
+        assert_eq!(endpoints.order(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let mut endpoints = endpoints(LoadBalancing::RoundRobin, "container");
+
+        assert_eq!(endpoints.order(), vec![0, 1, 2]);
+        assert_eq!(endpoints.order(), vec![1, 2, 0]);
+        assert_eq!(endpoints.order(), vec![2, 0, 1]);
+        assert_eq!(endpoints.order(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_hash() {
+        let first = endpoints(LoadBalancing::Hash, "container").order();
+        let second = endpoints(LoadBalancing::Hash, "container").order();
+
+        assert_eq!(first, second);
+        assert_eq!(first.len(), 3);
+
+        // spread across endpoints by key
+        let starts = (0..32)
+            .map(|i| endpoints(LoadBalancing::Hash, &format!("container-{}", i)).order()[0])
+            .collect::<std::collections::HashSet<_>>();
+
+        assert!(starts.len() > 1);
+    }
+
+    #[test]
+    fn test_probe() {
+        let mut endpoints = endpoints(LoadBalancing::Failover, "container");
+
+        for _ in 0..2 {
+            endpoints.failed(0);
+            endpoints.failed(1);
+            endpoints.failed(2);
+        }
+
+        // every endpoint is unhealthy, so they are all tried
+        assert_eq!(endpoints.order(), vec![0, 1, 2]);
+        assert_eq!(endpoints.due_probes(), vec![0, 1, 2]);
+
+        endpoints.probed(0, false);
+        endpoints.probed(1, true);
+
+        assert!(!endpoints.is_healthy(0));
+        assert_eq!(endpoints.order(), vec![1]);
+    }
+
+    #[test]
+    fn test_shared_health() {
+        let health = EndpointHealth::default();
+        let mut first = shared(LoadBalancing::Failover, "first", &health);
+        let second = shared(LoadBalancing::Failover, "second", &health);
+
+        first.failed(0);
+        first.failed(0);
+
+        // the other container doesn't have to find the endpoint down for itself
+        assert!(!second.is_healthy(0));
+        assert!(second.is_healthy(1));
+
+        first.succeeded(0);
+        assert!(second.is_healthy(0));
+    }
+}
@@ -14,6 +14,9 @@ use serde_json::json;
 #[derive(Debug, Clone, Serialize)]
 pub enum HttpError {
     BadRequest(String),
+    InvalidConfig(String),
+    NotFound(String),
+    Internal(String),
 }
 
 
@@ -45,6 +48,27 @@ impl HttpError {
 
         Self::BadRequest(message)
     }
+
+    pub fn invalid_config(message: Option<String>) -> Self {
+        let message: String = message
+            .unwrap_or("Invalid Config".to_string());
+
+        Self::InvalidConfig(message)
+    }
+
+    pub fn not_found(message: Option<String>) -> Self {
+        let message: String = message
+            .unwrap_or("Not Found".to_string());
+
+        Self::NotFound(message)
+    }
+
+    pub fn internal(message: Option<String>) -> Self {
+        let message: String = message
+            .unwrap_or("Internal Server Error".to_string());
+
+        Self::Internal(message)
+    }
 }
 
 
@@ -52,6 +76,9 @@ impl IntoResponse for HttpError {
     fn into_response(self) -> Response {
         let (status_code, message) = match self {
             HttpError::BadRequest(s) => (StatusCode::BAD_REQUEST, s),
+            HttpError::InvalidConfig(s) => (StatusCode::BAD_REQUEST, s),
+            HttpError::NotFound(s) => (StatusCode::NOT_FOUND, s),
+            HttpError::Internal(s) => (StatusCode::INTERNAL_SERVER_ERROR, s),
         };
 
         (status_code, Json(json!({"Err": message}))).into_response()
@@ -1,70 +1,149 @@
 use chrono::{
     NaiveDateTime, 
     DateTime, 
+    SecondsFormat,
     Utc,
 };
 use docker_protobuf::LogEntry;
-use serde::Serialize;
+use serde::{
+    Deserialize,
+    Serialize,
+    Serializer,
+};
 use serde_json::{
+    Map,
     Value,
     Number,
 };
 
 
+// 2000-01-01T00:00:00Z; anything earlier is assumed to be a bogus timestamp
+const MIN_TIMESTAMP_SECS: i64 = 946_684_800;
+
+// how far ahead of the receive time a timestamp may be
+const MAX_CLOCK_SKEW_SECS: i64 = 24 * 60 * 60;
+
+// level of lines which don't specify one
+const DEFAULT_LEVEL: i32 = 3;
+
+
+/// Converts Docker's `time_nano` into a timestamp, keeping nanosecond precision.
+/// Returns None if it is unset or far outside of `received`.
+fn timestamp_from_nanos(time_nano: i64, received: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let secs = time_nano.div_euclid(1_000_000_000);
+    let nanos = time_nano.rem_euclid(1_000_000_000) as u32;
+
+    if secs < MIN_TIMESTAMP_SECS || secs > received.timestamp() + MAX_CLOCK_SKEW_SECS {
+        return None;
+    }
+
+    NaiveDateTime::from_timestamp_opt(secs, nanos)
+        .map(|naive_dt| DateTime::<Utc>::from_utc(naive_dt, Utc))
+}
+
+// always write every digit, rather than trimming to millis or micros
+fn serialize_nanos<S: Serializer>(timestamp: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&timestamp.to_rfc3339_opts(SecondsFormat::Nanos, true))
+}
+
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogMessage {
+    #[serde(serialize_with = "serialize_nanos")]
     pub timestamp: chrono::DateTime<chrono::Utc>,
     pub message: String,
     pub level: i32,
     pub context: Option<Value>,
 }
 
-impl TryFrom<LogEntry> for LogMessage {
-    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
-
-    fn try_from(log: LogEntry) -> Result<Self, Self::Error> {
-        let naive_dt = NaiveDateTime::from_timestamp_millis(log.time_nano / 1000)
-            .ok_or(format!("Invalid timestamp: {}", log.time_nano))?; // TODO: Unsure if this is correct; is it actually using nano timestamps?
-        let dt = DateTime::<Utc>::from_utc(naive_dt, Utc);
-
-        // TODO: add support for partial log entries
-
-        // attempt to parse the log line as JSON
-        let log = match serde_json::from_slice::<Value>(&log.line) {
-            Ok(json) => {
-                let json = json.as_object()
-                    .ok_or("Invalid or unexpected format")?;
-
-                let empty = Value::String(String::from(""));
-                let message = json.get("message")
-                    .unwrap_or(&empty)
-                    .as_str()
-                    .ok_or("Invalid message")?;
-
-                let level = json.get("level")
-                    .unwrap_or(&Value::Number(Number::from(3)))
-                    .as_i64()
-                    .ok_or("Invalid level")?;
-                
-                let context = json.iter()
-                    .filter(|(k, _)| *k != "message" && *k != "level")
-                    .map(|(k, v)| (k.clone(), v.clone()))
-                    // insert the source into the context
-                    .chain(vec![("source".to_string(), Value::String(log.source))].into_iter())
-                    .collect::<Value>();
+impl LogMessage {
+    /// A synthetic message reporting that `count` messages were dropped
+    pub fn dropped(count: u64) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            message: format!("{} messages dropped", count),
+            level: DEFAULT_LEVEL,
+            context: Some(serde_json::json!({
+                "dropped": count,
+                "synthetic": true,
+            })),
+        }
+    }
+
+    /// Adds every key in `extra` to the message's context, replacing existing keys
+    pub fn with_context(mut self, extra: &Map<String, Value>) -> Self {
+        let mut context = match self.context.take() {
+            Some(Value::Object(context)) => context,
+            _ => Map::new(),
+        };
+
+        context.extend(extra.clone());
+        self.context = Some(Value::Object(context));
+        self
+    }
+}
+
+/// Splits a line which is a JSON object into its message, level and the rest of its
+/// fields, or returns None if it isn't one, or its message or level is the wrong type
+fn parse_json(line: &[u8]) -> Option<(String, i32, Map<String, Value>)> {
+    let json = match serde_json::from_slice::<Value>(line).ok()? {
+        Value::Object(json) => json,
+        _ => return None,
+    };
+
+    let message = match json.get("message") {
+        Some(message) => message.as_str()?.to_string(),
+        None => String::new(),
+    };
+
+    let level = match json.get("level") {
+        Some(level) => level.as_i64()? as i32,
+        None => DEFAULT_LEVEL,
+    };
+
+    let fields = json
+        .into_iter()
+        .filter(|(k, _)| k != "message" && k != "level")
+        .collect();
+
+    Some((message, level, fields))
+}
+
+
+impl From<LogEntry> for LogMessage {
+    /// A line which is a JSON object becomes a structured message; anything else,
+    /// including JSON of another shape or invalid UTF-8, is kept as plain text rather
+    /// than ending the container's logging
+    fn from(log: LogEntry) -> Self {
+        let received = Utc::now();
+
+        // fall back to the time the entry was received if Docker's is unusable
+        let (dt, fallback) = match timestamp_from_nanos(log.time_nano, received) {
+            Some(dt) => (dt, false),
+            None => (received, true),
+        };
+
+        // partial entries have already been reassembled; any still marked partial were
+        // flushed before the rest of the line arrived
+        let partial = log.partial;
+        let log_time_nano = log.time_nano;
+
+        let log = match parse_json(&log.line) {
+            Some((message, level, mut fields)) => {
+                // insert the source into the context
+                fields.insert("source".to_string(), Value::String(log.source));
 
                 Self {
                     timestamp: dt,
-                    message: message.to_string(),
-                    level: level as i32,
-                    context: Some(context),
+                    message,
+                    level,
+                    context: Some(Value::Object(fields)),
                 }
             },
-            Err(_) => { // if it fails to parse as json, treat as string
-                let message = String::from_utf8(log.line)
-                    .map_err(|_| "Invalid UTF-8")?;
-                
+            None => { // if it isn't a JSON object, treat as string
+                let message = String::from_utf8_lossy(&log.line)
+                    .into_owned();
+
                 let context = serde_json::json!({
                     "source": log.source,
                 });
@@ -72,13 +151,28 @@ impl TryFrom<LogEntry> for LogMessage {
                 Self {
                     timestamp: dt,
                     message,
-                    level: 3,
+                    level: DEFAULT_LEVEL,
                     context: Some(context),
                 }
             }
         };
 
-        Ok(log)
+        let mut context = Map::new();
+
+        if partial {
+            context.insert("partial".to_string(), Value::Bool(true));
+        }
+
+        if fallback {
+            context.insert("timestamp_fallback".to_string(), Value::Bool(true));
+            context.insert("time_nano".to_string(), Value::Number(Number::from(log_time_nano)));
+        }
+
+        if context.is_empty() {
+            return log;
+        }
+
+        log.with_context(&context)
     }
 }
 
@@ -88,13 +182,12 @@ mod tests {
     use docker_protobuf::LogEntry;
 
     use super::*;
-    use std::convert::TryInto;
 
     #[test]
     fn test_into_log_message() {
-        let expected_time_nano = 1620000000000;
+        let expected_time_nano = 1620000000000000000;
         let log = LogEntry {
-            time_nano: expected_time_nano * 1000,
+            time_nano: expected_time_nano,
             line: r#"{"message":"test","level":2,"another_field": 4}"#
                 .as_bytes()
                 .to_vec(),
@@ -104,10 +197,9 @@ mod tests {
         };
 
         let log: LogMessage = log
-            .try_into()
-            .unwrap();
+            .into();
 
-        let naive_dt = NaiveDateTime::from_timestamp_millis(expected_time_nano)
+        let naive_dt = NaiveDateTime::from_timestamp_millis(expected_time_nano / 1_000_000)
             .unwrap();
         assert_eq!(log.timestamp, chrono::DateTime::<chrono::Utc>::from_utc(naive_dt, Utc));
         assert_eq!(log.message, "test");
@@ -121,9 +213,9 @@ mod tests {
 
     #[test]
     fn test_string_log_message() {
-        let expected_time_nano = 1620000000000;
+        let expected_time_nano = 1620000000000000000;
         let log = LogEntry {
-            time_nano: expected_time_nano * 1000,
+            time_nano: expected_time_nano,
             line: r#"test"#.as_bytes().to_vec(),
             partial: false,
             partial_log_metadata: None,
@@ -131,10 +223,9 @@ mod tests {
         };
 
         let log: LogMessage = log
-            .try_into()
-            .unwrap();
+            .into();
 
-        let naive_dt = NaiveDateTime::from_timestamp_millis(expected_time_nano)
+        let naive_dt = NaiveDateTime::from_timestamp_millis(expected_time_nano / 1_000_000)
             .unwrap();
         assert_eq!(log.timestamp, chrono::DateTime::<chrono::Utc>::from_utc(naive_dt, Utc));
         assert_eq!(log.message, "test");
@@ -144,4 +235,87 @@ mod tests {
             "source": "container-id",
         })));
     }
+
+    #[test]
+    fn test_unstructured_log_message() {
+        let lines: [&[u8]; 4] = [b"[1, 2]", b"42", br#"{"message": 5}"#, b"caf\xe9"];
+
+        for line in lines {
+            let log = LogMessage::from(LogEntry {
+                time_nano: 1620000000000000000,
+                line: line.to_vec(),
+                partial: false,
+                partial_log_metadata: None,
+                source: "container-id".to_string(),
+            });
+
+            assert_eq!(log.message, String::from_utf8_lossy(line));
+            assert_eq!(log.level, 3);
+        }
+    }
+
+    #[test]
+    fn test_partial_log_message() {
+        let log = LogEntry {
+            time_nano: 1620000000000000000,
+            line: r#"{"message":"trunc"#.as_bytes().to_vec(),
+            partial: true,
+            partial_log_metadata: None,
+            source: "container-id".to_string(),
+        };
+
+        let log: LogMessage = log
+            .into();
+
+        assert_eq!(log.message, r#"{"message":"trunc"#);
+        assert_eq!(log.context, Some(serde_json::json!({
+            "partial": true,
+            "source": "container-id",
+        })));
+    }
+
+    #[test]
+    fn test_nanosecond_timestamp() {
+        let log = LogEntry {
+            time_nano: 1620000000123456789,
+            line: r#"test"#.as_bytes().to_vec(),
+            partial: false,
+            partial_log_metadata: None,
+            source: "container-id".to_string(),
+        };
+
+        let log: LogMessage = log
+            .into();
+
+        assert_eq!(log.timestamp.timestamp_nanos(), 1620000000123456789);
+
+        let json = serde_json::to_value(&log)
+            .unwrap();
+
+        assert_eq!(json["timestamp"], "2021-05-03T00:00:00.123456789Z");
+    }
+
+    #[test]
+    fn test_timestamp_fallback() {
+        for time_nano in [0, -1, 1620000000, i64::MAX] {
+            let log = LogEntry {
+                time_nano,
+                line: r#"test"#.as_bytes().to_vec(),
+                partial: false,
+                partial_log_metadata: None,
+                source: "container-id".to_string(),
+            };
+
+            let before = Utc::now();
+            let log: LogMessage = log
+                .into();
+
+            assert!(log.timestamp >= before && log.timestamp <= Utc::now(), "{} should fall back", time_nano);
+            assert_eq!(log.context, Some(serde_json::json!({
+                "source": "container-id",
+                "time_nano": time_nano,
+                "timestamp_fallback": true,
+            })));
+        }
+    }
 }
@@ -1,17 +1,45 @@
+use std::path::PathBuf;
+
 use axum::Router;
 use envconfig::Envconfig;
+use tracing::{
+    error,
+    info,
+};
 
+use client::{
+    HttpClients,
+    Ingest,
+};
 use config::Config;
+use sink::Sink;
 use task::ApiTask;
 
 mod api;
+mod auth;
+mod batch;
 mod client;
+mod compression;
 mod config;
+mod container;
+mod dead_letter;
+mod endpoint;
 mod error;
 mod log;
+mod metrics;
+mod partial;
+mod queue;
 mod reader;
+mod resubmit;
+mod retry;
+mod sender;
 mod server;
+mod sink;
+mod snappy;
+mod spool;
+mod store;
 mod task;
+mod tls;
 
 
 #[tokio::main]
@@ -19,6 +47,9 @@ async fn main() {
     let config = Config::init_from_env()
         .expect("Failed to load configuration!");
 
+    let args = std::env::args()
+        .collect::<Vec<_>>();
+
     tracing_subscriber::fmt()
         .json()
         .with_env_filter(
@@ -28,6 +59,10 @@ async fn main() {
         )
         .with_current_span(false)
         .init();
+
+    if args.get(1).map(String::as_str) == Some("resubmit") {
+        std::process::exit(resubmit(config, &args[2..]).await);
+    }
     
     let server = server::UnixServer::from_filename("ingest.sock")
         .into_server()
@@ -42,3 +77,64 @@ async fn main() {
         .await
         .unwrap();
 }
+
+
+/// `docker-log-driver resubmit [--sink <kind>] <file or directory>...` sends dead-lettered
+/// messages to the sink, and with the container, they were first sent with, or per the
+/// environment if that wasn't recorded; `--sink` overrides the kind of sink.  Returns
+/// the exit code
+async fn resubmit(config: Config, args: &[String]) -> i32 {
+    let (kind, paths) = match args {
+        [flag, kind, paths @ ..] if flag == "--sink" => match kind.parse() {
+            Ok(kind) => (Some(kind), paths),
+            Err(err) => {
+                eprintln!("{}", err);
+                return 2;
+            },
+        },
+        paths => (None, paths),
+    };
+
+    if paths.is_empty() {
+        eprintln!("Usage: docker-log-driver resubmit [--sink <kind>] <dead-letter file or directory>...");
+        return 2;
+    }
+
+    let clients = HttpClients::default();
+    let mut stats = resubmit::ResubmitStats::default();
+
+    for path in paths.iter().map(PathBuf::from) {
+        let resubmitted = async {
+            let (mut config, info) = resubmit::source(&config, &path)
+                .await?;
+
+            if let Some(kind) = kind {
+                config.sink = kind;
+            }
+
+            let client = Sink::new(&config, &info, &clients)?;
+
+            resubmit::resubmit(&config, client, std::slice::from_ref(&path))
+                .await
+        };
+
+        match resubmitted.await {
+            Ok(resubmitted) => {
+                stats.accepted += resubmitted.accepted;
+                stats.rejected += resubmitted.rejected;
+            },
+            Err(err) => {
+                error!(error = %err, path = ?path, "Failed to resubmit dead-lettered log messages");
+                return 1;
+            },
+        }
+    }
+
+    info!(
+        accepted = stats.accepted,
+        rejected = stats.rejected,
+        "Resubmitted {} dead-lettered log messages", stats.accepted,
+    );
+
+    0
+}
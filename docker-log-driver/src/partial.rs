@@ -0,0 +1,354 @@
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    time::Duration,
+};
+
+use docker_protobuf::LogEntry;
+use tokio::time::Instant;
+
+
+/// Fragments of a single line which Docker split into several LogEntries
+struct Group {
+    source: String,
+    time_nano: i64,
+    fragments: BTreeMap<i32, Vec<u8>>,
+    last: Option<i32>,
+    size: usize,
+    started: Instant,
+}
+
+impl Group {
+    fn new(entry: &LogEntry) -> Self {
+        Self {
+            source: entry
+                .source
+                .clone(),
+            time_nano: entry.time_nano,
+            fragments: BTreeMap::new(),
+            last: None,
+            size: 0,
+            started: Instant::now(),
+        }
+    }
+
+    fn add(&mut self, ordinal: i32, line: Vec<u8>) {
+        self.size += line.len();
+        self.fragments
+            .entry(ordinal)
+            .or_default()
+            .extend(line);
+    }
+
+    /// True once the last fragment and every fragment before it have been seen
+    fn is_complete(&self) -> bool {
+        let (first, last) = match (self.fragments.keys().next(), self.last) {
+            (Some(first), Some(last)) => (*first, last),
+            _ => return false,
+        };
+
+        self.fragments.len() as i64 == last as i64 - first as i64 + 1
+    }
+
+    /// Joins the fragments in ordinal order.  `partial` marks lines that were flushed
+    /// before their last fragment was seen.
+    fn assemble(self, partial: bool) -> LogEntry {
+        let mut line = Vec::with_capacity(self.size);
+
+        for fragment in self.fragments.into_values() {
+            line.extend(fragment);
+        }
+
+        LogEntry {
+            source: self.source,
+            time_nano: self.time_nano,
+            line,
+            partial,
+            partial_log_metadata: None,
+        }
+    }
+}
+
+
+enum Kind {
+    Complete,
+    Fragment {
+        key: String,
+        ordinal: Option<i32>,
+        last: bool,
+    },
+}
+
+
+/// Reassembles lines Docker split into several LogEntries (lines over 16KB).
+///
+/// Fragments are grouped by `PartialLogEntryMetadata.id` and joined in ordinal order
+/// once the last fragment, and every fragment before it, has been seen.  Older daemons
+/// don't send metadata; they mark every fragment but the last as partial, so those are
+/// grouped by source instead.  Groups larger than `max_size`, or older than `timeout`,
+/// are flushed incomplete and marked partial.
+pub struct Reassembler {
+    groups: HashMap<String, Group>,
+    max_size: usize,
+    timeout: Duration,
+}
+
+impl Reassembler {
+    pub fn new(max_size: usize, timeout: Duration) -> Self {
+        Self {
+            groups: HashMap::new(),
+            max_size,
+            timeout,
+        }
+    }
+
+    fn kind(&self, entry: &LogEntry) -> Kind {
+        match (&entry.partial_log_metadata, entry.partial) {
+            (Some(metadata), _) => Kind::Fragment {
+                key: format!("id:{}", metadata.id),
+                ordinal: Some(metadata.ordinal),
+                last: metadata.last,
+            },
+            (None, true) => Kind::Fragment {
+                key: format!("source:{}", entry.source),
+                ordinal: None,
+                last: false,
+            },
+            (None, false) => {
+                let key = format!("source:{}", entry.source);
+
+                // the final fragment of a line from a daemon without metadata
+                if self.groups.contains_key(&key) {
+                    Kind::Fragment {
+                        key,
+                        ordinal: None,
+                        last: true,
+                    }
+                } else {
+                    Kind::Complete
+                }
+            },
+        }
+    }
+
+    /// Adds an entry, returning any entries which are now complete
+    pub fn push(&mut self, entry: LogEntry) -> Vec<LogEntry> {
+        let (key, ordinal, last) = match self.kind(&entry) {
+            Kind::Complete => return vec![entry],
+            Kind::Fragment { key, ordinal, last } => (key, ordinal, last),
+        };
+
+        let mut complete = Vec::new();
+        let group = self.groups
+            .entry(key.clone())
+            .or_insert_with(|| Group::new(&entry));
+
+        // cap the memory held for a single line
+        if !group.fragments.is_empty() && group.size + entry.line.len() > self.max_size {
+            let flushed = std::mem::replace(group, Group::new(&entry));
+
+            complete.push(flushed.assemble(true));
+        }
+
+        let ordinal = ordinal
+            .unwrap_or(group.fragments.len() as i32 + 1);
+
+        group.add(ordinal, entry.line);
+
+        if last {
+            group.last = Some(ordinal);
+        }
+
+        if group.is_complete() {
+            let group = self.groups
+                .remove(&key)
+                .expect("Group was inserted above");
+
+            complete.push(group.assemble(false));
+        }
+
+        complete
+    }
+
+    /// Returns the time at which the oldest incomplete group expires
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.groups
+            .values()
+            .map(|group| group.started + self.timeout)
+            .min()
+    }
+
+    /// Flushes every group which has been incomplete for longer than the timeout
+    pub fn flush_expired(&mut self, now: Instant) -> Vec<LogEntry> {
+        let expired = self.groups
+            .iter()
+            .filter(|(_, group)| group.started + self.timeout <= now)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        self.flush(expired)
+    }
+
+    /// Flushes every incomplete group, e.g. once the FIFO is closed
+    pub fn flush_all(&mut self) -> Vec<LogEntry> {
+        let keys = self.groups
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        self.flush(keys)
+    }
+
+    fn flush(&mut self, keys: Vec<String>) -> Vec<LogEntry> {
+        let mut groups = keys
+            .into_iter()
+            .filter_map(|key| self.groups.remove(&key))
+            .collect::<Vec<_>>();
+
+        groups.sort_by_key(|group| group.started);
+        groups
+            .into_iter()
+            .map(|group| group.assemble(true))
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use docker_protobuf::PartialLogEntryMetadata;
+
+    use super::*;
+
+    fn fragment(line: &str, id: &str, ordinal: i32, last: bool) -> LogEntry {
+        LogEntry {
+            source: "stdout".to_string(),
+            time_nano: ordinal as i64,
+            line: line
+                .as_bytes()
+                .to_vec(),
+            partial: true,
+            partial_log_metadata: Some(PartialLogEntryMetadata {
+                last,
+                id: id.to_string(),
+                ordinal,
+            }),
+        }
+    }
+
+    fn legacy(line: &str, partial: bool) -> LogEntry {
+        LogEntry {
+            source: "stdout".to_string(),
+            time_nano: 0,
+            line: line
+                .as_bytes()
+                .to_vec(),
+            partial,
+            partial_log_metadata: None,
+        }
+    }
+
+    fn reassembler() -> Reassembler {
+        Reassembler::new(1024, Duration::from_secs(5))
+    }
+
+    #[test]
+    fn test_complete_entries_pass_through() {
+        let mut reassembler = reassembler();
+        let complete = reassembler.push(legacy("line", false));
+
+        assert_eq!(complete.len(), 1);
+        assert_eq!(complete[0].line, b"line");
+        assert!(reassembler.next_deadline().is_none());
+    }
+
+    #[test]
+    fn test_reassemble_by_id() {
+        let mut reassembler = reassembler();
+
+        assert!(reassembler.push(fragment(r#"{"message":"#, "a", 1, false)).is_empty());
+        assert!(reassembler.push(fragment("other", "b", 1, false)).is_empty());
+        assert!(reassembler.push(fragment(r#""hello"}"#, "a", 3, true)).is_empty());
+        assert!(reassembler.next_deadline().is_some());
+
+        // out of order fragments are joined by ordinal
+        let complete = reassembler.push(fragment(r#" "#, "a", 2, false));
+
+        assert_eq!(complete.len(), 1);
+        assert_eq!(complete[0].line, br#"{"message": "hello"}"#);
+
+        let complete = reassembler.push(fragment(" line", "b", 2, true));
+
+        assert_eq!(complete.len(), 1);
+        assert_eq!(complete[0].line, b"other line");
+        assert!(!complete[0].partial);
+        assert_eq!(complete[0].time_nano, 1);
+    }
+
+    #[test]
+    fn test_reassemble_in_order() {
+        let mut reassembler = reassembler();
+
+        assert!(reassembler.push(fragment(r#"{"message":"#, "a", 1, false)).is_empty());
+        assert!(reassembler.push(fragment(r#" "#, "a", 2, false)).is_empty());
+
+        let complete = reassembler.push(fragment(r#""hello"}"#, "a", 3, true));
+
+        assert_eq!(complete.len(), 1);
+        assert_eq!(complete[0].line, br#"{"message": "hello"}"#);
+        assert!(reassembler.next_deadline().is_none());
+    }
+
+    #[test]
+    fn test_reassemble_legacy() {
+        let mut reassembler = reassembler();
+
+        assert!(reassembler.push(legacy("first ", true)).is_empty());
+        assert!(reassembler.push(legacy("second ", true)).is_empty());
+
+        let complete = reassembler.push(legacy("third", false));
+
+        assert_eq!(complete.len(), 1);
+        assert_eq!(complete[0].line, b"first second third");
+    }
+
+    #[test]
+    fn test_max_size() {
+        let mut reassembler = Reassembler::new(8, Duration::from_secs(5));
+
+        assert!(reassembler.push(fragment("12345", "a", 1, false)).is_empty());
+
+        let complete = reassembler.push(fragment("67890", "a", 2, false));
+
+        assert_eq!(complete.len(), 1);
+        assert_eq!(complete[0].line, b"12345");
+        assert!(complete[0].partial);
+
+        let complete = reassembler.push(fragment("!", "a", 3, true));
+
+        assert_eq!(complete.len(), 1);
+        assert_eq!(complete[0].line, b"67890!");
+    }
+
+    #[tokio::test]
+    async fn test_flush_expired() {
+        let mut reassembler = Reassembler::new(1024, Duration::from_millis(10));
+
+        reassembler.push(fragment("incomplete", "a", 1, false));
+
+        let deadline = reassembler
+            .next_deadline()
+            .unwrap();
+
+        assert!(reassembler.flush_expired(Instant::now()).is_empty());
+
+        let flushed = reassembler.flush_expired(deadline);
+
+        assert_eq!(flushed.len(), 1);
+        assert_eq!(flushed[0].line, b"incomplete");
+        assert!(flushed[0].partial);
+        assert!(reassembler.flush_all().is_empty());
+    }
+}
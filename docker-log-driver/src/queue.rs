@@ -0,0 +1,496 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
+    sync::{
+        Arc,
+        Mutex,
+        MutexGuard,
+    },
+};
+
+use tokio::sync::Notify;
+use tracing::error;
+
+use crate::{
+    log::LogMessage,
+    spool::Spool,
+};
+
+
+/// What happens to a message sent while the queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueuePolicy {
+    // wait for space, which in turn stops reading the container's FIFO
+    Block,
+    DropOldest,
+    DropNewest,
+    // write messages to disk until the queue has caught up
+    Spill,
+}
+
+impl FromStr for QueuePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "block" => Ok(QueuePolicy::Block),
+            "drop-oldest" => Ok(QueuePolicy::DropOldest),
+            "drop-newest" => Ok(QueuePolicy::DropNewest),
+            "spill" => Ok(QueuePolicy::Spill),
+            _ => Err(format!("Invalid queue policy: {}; expected block, drop-oldest, drop-newest or spill", s)),
+        }
+    }
+}
+
+impl fmt::Display for QueuePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueuePolicy::Block => write!(f, "block"),
+            QueuePolicy::DropOldest => write!(f, "drop-oldest"),
+            QueuePolicy::DropNewest => write!(f, "drop-newest"),
+            QueuePolicy::Spill => write!(f, "spill"),
+        }
+    }
+}
+
+
+/// Returned when sending to a queue whose receiver has been dropped
+#[derive(Debug)]
+pub struct QueueClosed;
+
+impl std::error::Error for QueueClosed {}
+
+impl fmt::Display for QueueClosed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Queue receiver has stopped")
+    }
+}
+
+
+pub fn spill_dir<P: AsRef<Path>>(root: P, container_id: &str) -> PathBuf {
+    root
+        .as_ref()
+        .join("spill")
+        .join(container_id)
+}
+
+
+#[derive(Default)]
+struct State {
+    messages: VecDeque<LogMessage>,
+    dropped: u64,
+    // dropped since the last call to `take_dropped`
+    unreported: u64,
+    sender_closed: bool,
+    receiver_closed: bool,
+}
+
+impl State {
+    fn drop_messages(&mut self, count: u64) {
+        self.dropped += count;
+        self.unreported += count;
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    capacity: usize,
+    policy: QueuePolicy,
+    spill: Option<tokio::sync::Mutex<Spool>>,
+    // notified when a message is removed
+    space: Notify,
+    // notified when a message is added or the sender is dropped
+    items: Notify,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .expect("Queue lock poisoned")
+    }
+}
+
+
+/// A bounded queue of LogMessages between processing a container's entries and sending
+/// them, with a QueuePolicy for when it is full.  Dropped messages are counted.
+///
+/// With `QueuePolicy::Spill`, messages which don't fit are appended to a spool on disk
+/// and received once the in-memory queue is empty.  Messages are spilled for as long as
+/// anything is spilled, so they are received in order.  Without a spool, spilling falls
+/// back to blocking.
+pub fn channel(capacity: usize, policy: QueuePolicy, spill: Option<Spool>) -> (QueueSender, QueueReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State::default()),
+        capacity: capacity.max(1),
+        policy,
+        spill: spill.map(tokio::sync::Mutex::new),
+        space: Notify::new(),
+        items: Notify::new(),
+    });
+
+    (QueueSender { shared: shared.clone() }, QueueReceiver { shared })
+}
+
+
+pub struct QueueSender {
+    shared: Arc<Shared>,
+}
+
+impl QueueSender {
+    pub async fn send(&self, message: LogMessage) -> Result<(), QueueClosed> {
+        let shared = &self.shared;
+
+        if shared.policy == QueuePolicy::Spill {
+            if let Some(spill) = shared.spill.as_ref() {
+                let mut spill = spill
+                    .lock()
+                    .await;
+
+                let full = {
+                    let state = shared.state();
+
+                    if state.receiver_closed {
+                        return Err(QueueClosed);
+                    }
+
+                    state.messages.len() >= shared.capacity
+                };
+
+                if full || !spill.is_empty() {
+                    self.spill(&mut spill, message)
+                        .await;
+
+                    return Ok(());
+                }
+            }
+        }
+
+        loop {
+            {
+                let mut state = shared.state();
+
+                if state.receiver_closed {
+                    return Err(QueueClosed);
+                }
+
+                if state.messages.len() >= shared.capacity {
+                    match shared.policy {
+                        QueuePolicy::DropOldest => {
+                            state.messages.pop_front();
+                            state.drop_messages(1);
+                        },
+                        QueuePolicy::DropNewest => {
+                            state.drop_messages(1);
+                            return Ok(());
+                        },
+                        QueuePolicy::Block | QueuePolicy::Spill => (),
+                    }
+                }
+
+                if state.messages.len() < shared.capacity {
+                    state.messages.push_back(message);
+                    shared.items.notify_one();
+
+                    return Ok(());
+                }
+            }
+
+            shared.space
+                .notified()
+                .await;
+        }
+    }
+
+    async fn spill(&self, spill: &mut Spool, message: LogMessage) {
+        let evicted = spill.stats().evicted;
+        let results = spill.append(&[message]).await;
+        let mut state = self.shared.state();
+
+        match results {
+            Ok(_) => state.drop_messages(spill.stats().evicted - evicted),
+            Err(err) => {
+                error!(error = %err, "Error spilling log message");
+                state.drop_messages(1);
+            },
+        }
+
+        drop(state);
+        self.shared.items.notify_one();
+    }
+}
+
+impl Drop for QueueSender {
+    fn drop(&mut self) {
+        self.shared.state().sender_closed = true;
+        self.shared.items.notify_one();
+    }
+}
+
+
+pub struct QueueReceiver {
+    shared: Arc<Shared>,
+}
+
+impl QueueReceiver {
+    /// Receives the next message, or None once the sender has been dropped and the
+    /// queue is empty
+    pub async fn recv(&mut self) -> Option<LogMessage> {
+        let shared = &self.shared;
+
+        loop {
+            {
+                let mut state = shared.state();
+
+                if let Some(message) = state.messages.pop_front() {
+                    shared.space.notify_one();
+                    return Some(message);
+                }
+            }
+
+            if let Some(message) = self.recv_spilled().await {
+                return Some(message);
+            }
+
+            if shared.state().sender_closed {
+                return None;
+            }
+
+            shared.items
+                .notified()
+                .await;
+        }
+    }
+
+    // the spill is locked for one frame at a time, so senders aren't held up while
+    // unreadable frames are skipped
+    async fn recv_spilled(&self) -> Option<LogMessage> {
+        let spill = self.shared.spill
+            .as_ref()?;
+
+        loop {
+            let mut spill = spill
+                .lock()
+                .await;
+
+            let results = match spill.peek().await {
+                Ok(Some(messages)) => spill
+                    .ack()
+                    .await
+                    .map(|_| messages),
+                Ok(None) => return None,
+                Err(err) => Err(err),
+            };
+
+            match results {
+                Ok(messages) => match messages.into_iter().next() {
+                    Some(message) => return Some(message),
+                    None => continue,
+                },
+                Err(err) => {
+                    error!(error = %err, "Error reading spilled log message");
+
+                    let size = spill.size();
+                    let evicted = spill.stats().evicted;
+
+                    if let Err(err) = spill.discard().await {
+                        error!(error = %err, "Error discarding spilled log message");
+                        return None;
+                    }
+
+                    // nothing was skipped, so reading again would fail the same way
+                    if spill.size() == size {
+                        return None;
+                    }
+
+                    self.shared
+                        .state()
+                        .drop_messages(spill.stats().evicted - evicted);
+                },
+            }
+        }
+    }
+
+    /// Total number of messages dropped
+    pub fn dropped(&self) -> u64 {
+        self.shared
+            .state()
+            .dropped
+    }
+
+    /// Returns the number of messages dropped since the last call
+    pub fn take_dropped(&self) -> u64 {
+        std::mem::take(&mut self.shared.state().unreported)
+    }
+}
+
+impl Drop for QueueReceiver {
+    fn drop(&mut self) {
+        self.shared.state().receiver_closed = true;
+        self.shared.space.notify_one();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::spool::{
+        SpoolEviction,
+        SpoolOptions,
+    };
+
+    use super::*;
+
+    fn message(text: &str) -> LogMessage {
+        LogMessage {
+            timestamp: chrono::Utc::now(),
+            message: text.to_string(),
+            level: 3,
+            context: None,
+        }
+    }
+
+    async fn fill(tx: &QueueSender, count: usize) {
+        for i in 0..count {
+            tx.send(message(&i.to_string())).await.unwrap();
+        }
+    }
+
+    async fn drain(mut rx: QueueReceiver) -> Vec<String> {
+        let mut received = Vec::new();
+
+        while let Some(message) = rx.recv().await {
+            received.push(message.message);
+        }
+
+        received
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest() {
+        let (tx, rx) = channel(2, QueuePolicy::DropOldest, None);
+
+        fill(&tx, 5).await;
+        drop(tx);
+
+        assert_eq!(rx.dropped(), 3);
+        assert_eq!(drain(rx).await, vec!["3", "4"]);
+    }
+
+    #[tokio::test]
+    async fn test_drop_newest() {
+        let (tx, rx) = channel(2, QueuePolicy::DropNewest, None);
+
+        fill(&tx, 5).await;
+        drop(tx);
+
+        assert_eq!(rx.take_dropped(), 3);
+        assert_eq!(rx.take_dropped(), 0);
+        assert_eq!(rx.dropped(), 3);
+        assert_eq!(drain(rx).await, vec!["0", "1"]);
+    }
+
+    #[tokio::test]
+    async fn test_block() {
+        let (tx, mut rx) = channel(2, QueuePolicy::Block, None);
+
+        fill(&tx, 2).await;
+
+        // the third message waits for space
+        let blocked = tokio::time::timeout(Duration::from_millis(50), tx.send(message("2"))).await;
+
+        assert!(blocked.is_err());
+
+        let sending = tokio::spawn(async move {
+            tx.send(message("2")).await.unwrap();
+        });
+
+        assert_eq!(rx.recv().await.unwrap().message, "0");
+        sending.await.unwrap();
+
+        assert_eq!(rx.dropped(), 0);
+        assert_eq!(drain(rx).await, vec!["1", "2"]);
+    }
+
+    #[tokio::test]
+    async fn test_spill() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let spill = Spool::open_dir(spill_dir(dir.path(), "container"), SpoolOptions {
+            max_size: 1024 * 1024,
+            eviction: SpoolEviction::DropOldest,
+        }).await.unwrap();
+
+        let (tx, mut rx) = channel(2, QueuePolicy::Spill, Some(spill));
+
+        fill(&tx, 4).await;
+
+        // once anything is spilled, later messages are spilled behind it
+        assert_eq!(rx.recv().await.unwrap().message, "0");
+        tx.send(message("4")).await.unwrap();
+        drop(tx);
+
+        assert_eq!(rx.dropped(), 0);
+        assert_eq!(drain(rx).await, vec!["1", "2", "3", "4"]);
+    }
+
+    #[tokio::test]
+    async fn test_spill_corrupt_frame() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let spill = Spool::open_dir(spill_dir(dir.path(), "container"), SpoolOptions {
+            max_size: 1024 * 1024,
+            eviction: SpoolEviction::DropOldest,
+        }).await.unwrap().with_sync(false);
+
+        let (tx, rx) = channel(2, QueuePolicy::Spill, Some(spill));
+
+        fill(&tx, 4).await;
+        drop(tx);
+
+        // cut the spilled messages short, as a failing disk might
+        for entry in std::fs::read_dir(spill_dir(dir.path(), "container")).unwrap() {
+            let path = entry.unwrap().path();
+
+            if path.extension().is_some_and(|extension| extension == "spool") {
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .unwrap()
+                    .set_len(4)
+                    .unwrap();
+            }
+        }
+
+        // the unreadable messages are dropped, rather than read again forever
+        let received = tokio::time::timeout(Duration::from_secs(5), async {
+            let mut rx = rx;
+            let mut received = Vec::new();
+
+            while let Some(message) = rx.recv().await {
+                received.push(message.message);
+            }
+
+            (received, rx.dropped())
+        }).await.unwrap();
+
+        assert_eq!(received, (vec!["0".to_string(), "1".to_string()], 2));
+    }
+
+    #[tokio::test]
+    async fn test_send_after_receiver_dropped() {
+        let (tx, rx) = channel(2, QueuePolicy::Block, None);
+
+        drop(rx);
+        assert!(tx.send(message("0")).await.is_err());
+    }
+}
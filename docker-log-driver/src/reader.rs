@@ -2,12 +2,25 @@ use std::{error::Error, fmt, pin::Pin};
 
 use docker_protobuf::LogEntry;
 use tokio::io::AsyncReadExt;
+use tracing::warn;
 
 
 #[derive(Debug)]
 pub enum ReaderError {
     Io(std::io::Error),
     Protobuf(prost::DecodeError),
+    FrameTooLarge {
+        size: usize,
+        max: usize,
+    },
+}
+
+impl ReaderError {
+    /// True if the frame boundaries are still known, so reading can continue with the
+    /// next frame.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, ReaderError::Protobuf(_) | ReaderError::FrameTooLarge { .. })
+    }
 }
 
 impl Error for ReaderError {
@@ -15,6 +28,7 @@ impl Error for ReaderError {
         match self {
             ReaderError::Io(err) => Some(err),
             ReaderError::Protobuf(err) => Some(err),
+            ReaderError::FrameTooLarge { .. } => None,
         }
     }
 }
@@ -24,6 +38,7 @@ impl fmt::Display for ReaderError {
         match self {
             ReaderError::Io(err) => write!(f, "IO error: {}", err),
             ReaderError::Protobuf(err) => write!(f, "Protobuf error: {}", err),
+            ReaderError::FrameTooLarge { size, max } => write!(f, "Frame of {} bytes exceeds maximum of {} bytes", size, max),
         }
     }
 }
@@ -43,19 +58,70 @@ impl From<prost::DecodeError> for ReaderError {
 
 pub struct Reader<T> {
     reader: Pin<Box<T>>,
+    max_frame_size: usize,
+    skip_invalid: bool,
+    skipped: u64,
 }
 
 
 impl<T: AsyncReadExt> Reader<T> {
+    pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
     pub fn new(reader: T) -> Self {
         Self {
             reader: Box::pin(reader),
+            max_frame_size: Self::DEFAULT_MAX_FRAME_SIZE,
+            skip_invalid: false,
+            skipped: 0,
         }
     }
 
+    /// Frames with a length prefix larger than `max_frame_size` are rejected rather
+    /// than allocated.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// When enabled, oversized frames and frames which fail to decode are skipped
+    /// instead of returned as errors.
+    pub fn with_skip_invalid(mut self, skip_invalid: bool) -> Self {
+        self.skip_invalid = skip_invalid;
+        self
+    }
+
+    /// Number of frames skipped so far
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
     /// Reads the next LogEntry from the FIFO file.  If EOF is reached,
     /// returns None.  No further reads should be attempted after EOF.
     pub async fn next(&mut self) -> Result<Option<LogEntry>, ReaderError> {
+        loop {
+            let err = match self.next_frame().await {
+                Err(err) if self.skip_invalid && err.is_recoverable() => err,
+                results => return results,
+            };
+
+            // the body of an oversized frame hasn't been read yet
+            if let ReaderError::FrameTooLarge { size, .. } = err {
+                if !self.discard(size).await? {
+                    return Ok(None);
+                }
+            }
+
+            self.skipped += 1;
+
+            warn!(
+                error = %err,
+                skipped = self.skipped,
+                "Skipping invalid log entry frame",
+            );
+        }
+    }
+
+    async fn next_frame(&mut self) -> Result<Option<LogEntry>, ReaderError> {
         let mut size: [u8; 4] = [0; 4];
 
         let maybe_size = self.reader
@@ -74,6 +140,14 @@ impl<T: AsyncReadExt> Reader<T> {
         }
         
         let buffer_size = u32::from_be_bytes(size) as usize;
+
+        if buffer_size > self.max_frame_size {
+            return Err(ReaderError::FrameTooLarge {
+                size: buffer_size,
+                max: self.max_frame_size,
+            });
+        }
+
         let mut buffer = vec![0; buffer_size];
 
         let maybe_bytes = self.reader
@@ -95,4 +169,115 @@ impl<T: AsyncReadExt> Reader<T> {
             .map_err(ReaderError::from)
             .map(Some)
     }
+
+    /// Reads and drops `size` bytes without buffering them.  Returns false if EOF was
+    /// reached first.
+    async fn discard(&mut self, size: usize) -> Result<bool, std::io::Error> {
+        let discarded = tokio::io::copy(
+            &mut (&mut self.reader).take(size as u64),
+            &mut tokio::io::sink(),
+        ).await?;
+
+        Ok(discarded == size as u64)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use docker_protobuf::LogEntry;
+    use prost::Message;
+
+    use super::*;
+
+    fn frame(line: &str) -> Vec<u8> {
+        let entry = LogEntry {
+            source: "stdout".to_string(),
+            time_nano: 1,
+            line: line
+                .as_bytes()
+                .to_vec(),
+            partial: false,
+            partial_log_metadata: None,
+        };
+        let body = entry.encode_to_vec();
+        let mut frame = (body.len() as u32)
+            .to_be_bytes()
+            .to_vec();
+
+        frame.extend(body);
+        frame
+    }
+
+    // a frame whose body isn't a valid LogEntry
+    fn corrupt_frame() -> Vec<u8> {
+        let mut frame = 3u32
+            .to_be_bytes()
+            .to_vec();
+
+        frame.extend([0xff, 0xff, 0xff]);
+        frame
+    }
+
+    fn oversized_frame(size: usize) -> Vec<u8> {
+        let mut frame = (size as u32)
+            .to_be_bytes()
+            .to_vec();
+
+        frame.extend(vec![0; size]);
+        frame
+    }
+
+    #[tokio::test]
+    async fn test_rejects_oversized_frame() {
+        let data = [oversized_frame(64), frame("after")].concat();
+        let mut reader = Reader::new(&data[..])
+            .with_max_frame_size(32);
+
+        let results = reader
+            .next()
+            .await;
+
+        assert!(matches!(results, Err(ReaderError::FrameTooLarge { size: 64, max: 32 })));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_corrupt_frame() {
+        let data = [corrupt_frame(), frame("after")].concat();
+        let mut reader = Reader::new(&data[..]);
+
+        assert!(matches!(reader.next().await, Err(ReaderError::Protobuf(_))));
+    }
+
+    #[tokio::test]
+    async fn test_skips_invalid_frames() {
+        let data = [
+            frame("first"),
+            oversized_frame(64),
+            corrupt_frame(),
+            frame("second"),
+        ].concat();
+
+        let mut reader = Reader::new(&data[..])
+            .with_max_frame_size(32)
+            .with_skip_invalid(true);
+
+        assert_eq!(reader.next().await.unwrap().unwrap().line, b"first");
+        assert_eq!(reader.next().await.unwrap().unwrap().line, b"second");
+        assert!(reader.next().await.unwrap().is_none());
+        assert_eq!(reader.skipped(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_truncated_oversized_frame() {
+        let mut data = oversized_frame(64);
+
+        data.truncate(20);
+
+        let mut reader = Reader::new(&data[..])
+            .with_max_frame_size(32)
+            .with_skip_invalid(true);
+
+        assert!(reader.next().await.unwrap().is_none());
+    }
 }
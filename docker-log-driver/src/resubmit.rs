@@ -0,0 +1,421 @@
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use tracing::{
+    info,
+    warn,
+};
+
+use crate::{
+    client::Ingest,
+    config::Config,
+    container::ContainerInfo,
+    dead_letter::{
+        self,
+        DeadLetterRecord,
+    },
+    error::BoxedError,
+    sender::Sender,
+};
+
+
+/// Number of messages accepted, and rejected again, by a resubmission
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResubmitStats {
+    pub accepted: u64,
+    pub rejected: u64,
+}
+
+
+/// Sends the messages in dead-letter files to a sink again, e.g. once whatever
+/// caused them to be rejected is fixed.  A directory stands for all of the dead-letter
+/// files in it, oldest first.
+///
+/// A file is removed once all of its messages are accepted; messages which are rejected
+/// again are left in it with the new reason.  Resubmission stops at the first batch
+/// which fails, leaving it and everything after it to be resubmitted later.  A file the
+/// plugin is still writing to is skipped, as it couldn't be rewritten without losing
+/// whatever is appended meanwhile.
+pub async fn resubmit<T: Ingest>(config: &Config, client: T, paths: &[PathBuf]) -> Result<ResubmitStats, BoxedError> {
+    let mut sender = Sender::new(config, client, None);
+    let mut stats = ResubmitStats::default();
+    let batch_size = config.batch_max_count.max(1);
+
+    for path in paths {
+        let files = match path.is_dir() {
+            true => dead_letter::list_files(path),
+            false => vec![path.clone()],
+        };
+
+        for file in files {
+            resubmit_file(&mut sender, batch_size, &file, &mut stats)
+                .await?;
+        }
+    }
+
+    Ok(stats)
+}
+
+
+/// Returns the sink's configuration and the container that the messages in a dead-letter
+/// file or directory were sent with, per the source recorded alongside them.  Without
+/// one, e.g. for files written by older versions, `config` is used as it is.
+pub async fn source(config: &Config, path: &Path) -> Result<(Config, ContainerInfo), BoxedError> {
+    let dir = match path.is_dir() {
+        true => path,
+        false => path
+            .parent()
+            .unwrap_or(Path::new(".")),
+    };
+
+    let source = match dead_letter::read_source(dir).await? {
+        Some(source) => source,
+        None => return Ok((config.clone(), ContainerInfo::default())),
+    };
+
+    let opts = &source.container.config;
+    let sink = config
+        .with_log_opts(opts)?
+        .sinks(opts)?
+        .into_iter()
+        .find(|sink| sink.name == source.sink)
+        .ok_or_else(|| format!("Container {} no longer has a {} sink", source.container.container_id, source.sink))?;
+
+    Ok((sink.config, source.container))
+}
+
+
+async fn resubmit_file<T: Ingest>(sender: &mut Sender<T>, batch_size: usize, path: &Path, stats: &mut ResubmitStats) -> Result<(), BoxedError> {
+    let _lock = match dead_letter::try_lock(path).await? {
+        Some(lock) => lock,
+        None => {
+            warn!(
+                path = ?path,
+                "Skipping dead-letter file which is still being written to",
+            );
+
+            return Ok(());
+        },
+    };
+
+    let records = dead_letter::read_records(path)
+        .await?;
+    let mut remaining = Vec::new();
+    let mut failure = None;
+
+    for batch in records.chunks(batch_size) {
+        if failure.is_some() {
+            remaining.extend_from_slice(batch);
+            continue;
+        }
+
+        let messages = batch
+            .iter()
+            .map(|record| record.message.clone())
+            .collect::<Vec<_>>();
+
+        match sender.ingest(&messages).await {
+            Ok(response) => {
+                let mut rejected = response.rejected;
+
+                rejected.sort_by_key(|rejection| rejection.index);
+                rejected.dedup_by_key(|rejection| rejection.index);
+
+                let rejected = rejected
+                    .into_iter()
+                    .filter_map(|rejection| {
+                        let message = messages.get(rejection.index)?;
+                        let reason = rejection.reason
+                            .unwrap_or_else(|| "rejected".to_string());
+
+                        Some(DeadLetterRecord::new(message.clone(), reason))
+                    })
+                    .collect::<Vec<_>>();
+
+                stats.accepted += (messages.len() - rejected.len()) as u64;
+                stats.rejected += rejected.len() as u64;
+                remaining.extend(rejected);
+            },
+            Err(err) => {
+                remaining.extend(
+                    messages
+                        .into_iter()
+                        .map(|message| DeadLetterRecord::from_error(message, &err))
+                );
+
+                failure = Some(err);
+            },
+        }
+    }
+
+    dead_letter::rewrite_records(path, &remaining)
+        .await?;
+
+    match failure {
+        Some(err) => {
+            warn!(
+                error = %err,
+                path = ?path,
+                remaining = remaining.len(),
+                "Error resubmitting dead-lettered log messages",
+            );
+
+            Err(err.into())
+        },
+        None => {
+            info!(
+                path = ?path,
+                count = records.len(),
+                rejected = remaining.len(),
+                "Resubmitted dead-lettered log messages",
+            );
+
+            Ok(())
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use envconfig::Envconfig;
+
+    use crate::{
+        client::{
+            HttpClients,
+            IngestError,
+            IngestResponse,
+            Rejection,
+        },
+        dead_letter::{
+            DeadLetter,
+            DeadLetterOptions,
+            DeadLetterSource,
+        },
+        log::LogMessage,
+        sink::SinkKind,
+    };
+
+    use super::*;
+
+    // rejects messages containing "bad", and fails once `failures` reaches zero
+    struct TestClient {
+        failures: Option<usize>,
+    }
+
+    #[async_trait::async_trait]
+    impl Ingest for TestClient {
+        fn new(_: &Config, _: &ContainerInfo, _: &HttpClients) -> Result<Self, BoxedError> {
+            Err("built by the test with its responses".into())
+        }
+
+        async fn ingest(&mut self, messages: &[LogMessage]) -> Result<IngestResponse, IngestError> {
+            if let Some(failures) = self.failures.as_mut() {
+                if *failures == 0 {
+                    return Err(IngestError::Status {
+                        status: reqwest::StatusCode::BAD_REQUEST,
+                        retry_after: None,
+                        body: String::new(),
+                    });
+                }
+
+                *failures -= 1;
+            }
+
+            let rejected = messages
+                .iter()
+                .enumerate()
+                .filter(|(_, message)| message.message.contains("bad"))
+                .map(|(index, _)| Rejection { index, reason: Some("still bad".to_string()) })
+                .collect::<Vec<_>>();
+
+            Ok(IngestResponse {
+                count: Some(messages.len() - rejected.len()),
+                rejected,
+            })
+        }
+    }
+
+    fn message(text: &str) -> LogMessage {
+        LogMessage {
+            timestamp: chrono::Utc::now(),
+            message: text.to_string(),
+            level: 3,
+            context: None,
+        }
+    }
+
+    fn config() -> Config {
+        Config {
+            batch_max_count: 2,
+            retry_max_attempts: 1,
+            ..Config::init_from_hashmap(&HashMap::new()).unwrap()
+        }
+    }
+
+    async fn dead_letter(root: &Path, messages: &[&str]) -> PathBuf {
+        let mut dead_letter = DeadLetter::open(root, "container", DeadLetterOptions::from(&config()))
+            .await
+            .unwrap();
+
+        let records = messages
+            .iter()
+            .map(|text| DeadLetterRecord::new(message(text), "400 Bad Request"))
+            .collect::<Vec<_>>();
+
+        dead_letter.write(&records).await.unwrap();
+        DeadLetter::dir(root, "container")
+    }
+
+    async fn remaining(dir: &Path) -> Vec<(String, String)> {
+        let mut remaining = Vec::new();
+
+        for file in dead_letter::list_files(dir) {
+            for record in dead_letter::read_records(&file).await.unwrap() {
+                remaining.push((record.message.message, record.reason));
+            }
+        }
+
+        remaining
+    }
+
+    #[tokio::test]
+    async fn test_resubmit() {
+        let root = tempfile::tempdir()
+            .unwrap();
+        let dir = dead_letter(root.path(), &["1", "2", "bad 3"]).await;
+        let client = TestClient {
+            failures: None,
+        };
+
+        let stats = resubmit(&config(), client, std::slice::from_ref(&dir))
+            .await
+            .unwrap();
+
+        assert_eq!(stats, ResubmitStats { accepted: 2, rejected: 1 });
+        assert_eq!(remaining(&dir).await, vec![("bad 3".to_string(), "still bad".to_string())]);
+
+        // once nothing is rejected, the file is removed
+        let root = tempfile::tempdir()
+            .unwrap();
+        let dir = dead_letter(root.path(), &["4"]).await;
+        let client = TestClient {
+            failures: None,
+        };
+
+        resubmit(&config(), client, std::slice::from_ref(&dir)).await.unwrap();
+        assert!(dead_letter::list_files(&dir).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resubmit_skips_open_file() {
+        let root = tempfile::tempdir()
+            .unwrap();
+        let dir = dead_letter(root.path(), &["1"]).await;
+
+        // as the plugin does while the container is logging
+        let mut writer = DeadLetter::open(root.path(), "container", DeadLetterOptions::from(&config()))
+            .await
+            .unwrap();
+
+        writer.write(&[DeadLetterRecord::new(message("2"), "400 Bad Request")]).await.unwrap();
+
+        let client = TestClient {
+            failures: None,
+        };
+        let stats = resubmit(&config(), client, std::slice::from_ref(&dir))
+            .await
+            .unwrap();
+
+        assert_eq!(stats, ResubmitStats::default());
+        assert_eq!(remaining(&dir).await.len(), 2);
+
+        drop(writer);
+
+        let client = TestClient {
+            failures: None,
+        };
+        let stats = resubmit(&config(), client, std::slice::from_ref(&dir))
+            .await
+            .unwrap();
+
+        assert_eq!(stats, ResubmitStats { accepted: 2, rejected: 0 });
+        assert!(dead_letter::list_files(&dir).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resubmit_stops_at_failure() {
+        let root = tempfile::tempdir()
+            .unwrap();
+        let dir = dead_letter(root.path(), &["1", "2", "3", "4", "5"]).await;
+        let client = TestClient {
+            failures: Some(1),
+        };
+
+        assert!(resubmit(&config(), client, std::slice::from_ref(&dir)).await.is_err());
+
+        let remaining = remaining(&dir).await;
+        let messages = remaining
+            .iter()
+            .map(|(message, _)| message.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(messages, vec!["3", "4", "5"]);
+        assert_eq!(remaining[0].1, "Unexpected status: 400 Bad Request");
+        assert_eq!(remaining[2].1, "400 Bad Request");
+    }
+
+    #[tokio::test]
+    async fn test_source() {
+        let root = tempfile::tempdir()
+            .unwrap();
+        let dir = dead_letter(root.path(), &["1"]).await;
+        let file = dead_letter::list_files(&dir)
+            .remove(0);
+
+        // nothing recorded, as by older versions
+        let (sink, info) = source(&config(), &file)
+            .await
+            .unwrap();
+
+        assert_eq!(sink.sink, SinkKind::Ingest);
+        assert!(info.container_id.is_empty());
+
+        let writer = DeadLetter::open(root.path(), "container", DeadLetterOptions::from(&config()))
+            .await
+            .unwrap();
+
+        writer.write_source(&DeadLetterSource {
+            sink: "archive".to_string(),
+            container: ContainerInfo {
+                config: HashMap::from([
+                    ("sinks".to_string(), "ingest,archive".to_string()),
+                    ("queue-policy".to_string(), "drop-oldest".to_string()),
+                    ("archive.sink".to_string(), "file".to_string()),
+                ]),
+                container_id: "container".to_string(),
+                container_labels: HashMap::from([("app".to_string(), "web".to_string())]),
+                container_env: vec!["SECRET=hunter2".to_string()],
+                ..ContainerInfo::default()
+            },
+        }).await.unwrap();
+
+        for path in [&dir, &file] {
+            let (sink, info) = source(&config(), path)
+                .await
+                .unwrap();
+
+            assert_eq!(sink.sink, SinkKind::File);
+            assert!(sink.state_dir.ends_with("sinks/archive"));
+            assert_eq!(info.container_id, "container");
+            assert_eq!(info.container_labels["app"], "web");
+
+            // the environment isn't kept
+            assert!(info.container_env.is_empty());
+        }
+    }
+}
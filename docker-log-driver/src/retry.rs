@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use crate::{
+    client::IngestError,
+    config::Config,
+};
+
+
+/// When, and how long to wait before, a failed request is sent again.
+///
+/// Delays grow exponentially from `initial_backoff` up to `max_backoff`, with full
+/// jitter.  A `Retry-After` from the endpoint is used instead when present.  Requests
+/// are abandoned after `max_attempts`, once waiting would go past `max_elapsed`, or if
+/// the failure isn't retryable.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub max_elapsed: Duration,
+}
+
+impl From<&Config> for RetryPolicy {
+    fn from(config: &Config) -> Self {
+        Self {
+            max_attempts: config.retry_max_attempts.max(1),
+            initial_backoff: config.retry_initial_backoff.0,
+            max_backoff: config.retry_max_backoff.0,
+            max_elapsed: config.retry_max_elapsed.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Upper bound of the delay after the given attempt, before jitter
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt
+            .saturating_sub(1)
+            .min(31);
+
+        self.initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff)
+    }
+
+    /// Returns how long to wait before the next attempt, or None if the request should
+    /// be abandoned.  `attempt` counts from 1; `elapsed` is the time since the first.
+    pub fn delay(&self, attempt: u32, elapsed: Duration, err: &IngestError) -> Option<Duration> {
+        if !err.is_retryable() || attempt >= self.max_attempts {
+            return None;
+        }
+
+        let delay = err
+            .retry_after()
+            .unwrap_or_else(|| {
+                self.backoff(attempt)
+                    .mul_f64(fastrand::f64())
+            });
+
+        if elapsed + delay > self.max_elapsed {
+            return None;
+        }
+
+        Some(delay)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            max_elapsed: Duration::from_secs(60),
+        }
+    }
+
+    fn status(status: u16, retry_after: Option<Duration>) -> IngestError {
+        IngestError::Status {
+            status: StatusCode::from_u16(status).unwrap(),
+            retry_after,
+            body: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = policy();
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_millis(500));
+        assert_eq!(policy.backoff(100), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_delay_is_jittered() {
+        let policy = policy();
+
+        for attempt in 1..5 {
+            let delay = policy
+                .delay(attempt, Duration::ZERO, &status(503, None))
+                .unwrap();
+
+            assert!(delay <= policy.backoff(attempt));
+        }
+    }
+
+    #[test]
+    fn test_max_attempts() {
+        assert!(policy().delay(4, Duration::ZERO, &status(503, None)).is_some());
+        assert!(policy().delay(5, Duration::ZERO, &status(503, None)).is_none());
+    }
+
+    #[test]
+    fn test_max_elapsed() {
+        let retry_after = status(429, Some(Duration::from_secs(30)));
+
+        assert!(policy().delay(1, Duration::from_secs(60), &status(503, None)).is_none());
+        assert!(policy().delay(1, Duration::from_secs(40), &retry_after).is_none());
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let delay = policy()
+            .delay(1, Duration::ZERO, &status(429, Some(Duration::from_secs(2))));
+
+        assert_eq!(delay, Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_permanent_failure() {
+        assert!(policy().delay(1, Duration::ZERO, &status(400, None)).is_none());
+    }
+}
@@ -0,0 +1,641 @@
+use std::time::Duration;
+
+use tokio::{
+    sync::watch,
+    time::Instant,
+};
+use tracing::{
+    error,
+    info,
+    warn,
+};
+
+use crate::{
+    client::{
+        Ingest,
+        IngestError,
+        IngestResponse,
+    },
+    config::Config,
+    dead_letter::{
+        DeadLetter,
+        DeadLetterRecord,
+    },
+    log::LogMessage,
+    retry::RetryPolicy,
+    spool::Spool,
+};
+
+
+/// Counts of what happened to the messages sent to the ingest API
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeliveryStats {
+    pub delivered: u64,
+    // rejected by the ingest API, or given up on
+    pub rejected: u64,
+    // responses whose accepted count didn't match the batch
+    pub mismatches: u64,
+}
+
+
+/// Checks what the ingest API reports about each batch, and writes messages which
+/// weren't delivered to the dead-letter file
+#[derive(Default)]
+struct Rejections {
+    dead_letter: Option<DeadLetter>,
+    stats: DeliveryStats,
+}
+
+impl Rejections {
+    async fn accepted(&mut self, messages: &[LogMessage], response: IngestResponse) {
+        let mut records = Vec::new();
+
+        for rejection in response.rejected.iter() {
+            match messages.get(rejection.index) {
+                Some(message) => {
+                    let reason = rejection.reason
+                        .clone()
+                        .unwrap_or_else(|| "rejected".to_string());
+
+                    records.push(DeadLetterRecord::new(message.clone(), reason));
+                },
+                None => warn!(
+                    index = rejection.index,
+                    count = messages.len(),
+                    "Ingest API rejected a message outside the batch",
+                ),
+            }
+        }
+
+        let accepted = messages.len().saturating_sub(records.len());
+
+        if let Some(count) = response.count.filter(|count| *count != accepted) {
+            warn!(
+                accepted = count,
+                rejected = records.len(),
+                count = messages.len(),
+                "Ingest API accepted {} of {} messages", count, messages.len(),
+            );
+
+            self.stats.mismatches += 1;
+        }
+
+        self.stats.delivered += accepted as u64;
+
+        if !records.is_empty() {
+            warn!(
+                rejected = records.len(),
+                count = messages.len(),
+                "Ingest API rejected {} of {} messages", records.len(), messages.len(),
+            );
+
+            self.dead_letter(records)
+                .await;
+        }
+    }
+
+    async fn failed(&mut self, messages: &[LogMessage], err: &IngestError) {
+        let records = messages
+            .iter()
+            .map(|message| DeadLetterRecord::from_error(message.clone(), err))
+            .collect();
+
+        self.dead_letter(records)
+            .await;
+    }
+
+    async fn dead_letter(&mut self, records: Vec<DeadLetterRecord>) {
+        self.stats.rejected += records.len() as u64;
+
+        let dead_letter = match self.dead_letter.as_mut() {
+            Some(dead_letter) => dead_letter,
+            None => return,
+        };
+
+        if let Err(err) = dead_letter.write(&records).await {
+            error!(
+                error = %err,
+                count = records.len(),
+                path = ?dead_letter.path(),
+                "Error writing rejected log messages to the dead-letter file",
+            );
+        }
+    }
+}
+
+
+/// Resolves once `stop` is set, or never if there is no stop signal or it's dropped
+async fn stopped(stop: Option<watch::Receiver<bool>>) {
+    if let Some(mut stop) = stop {
+        while !*stop.borrow() {
+            if stop.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+
+        return;
+    }
+
+    std::future::pending().await
+}
+
+
+/// Delivers batches to the ingest API, retrying failures according to the container's
+/// RetryPolicy.
+///
+/// Batches which still can't be delivered are appended to the spool, if there is one,
+/// and replayed every `replay_interval` until the endpoint recovers.  While anything is
+/// spooled new batches are spooled behind it, so messages are delivered in order.
+/// Messages the ingest API rejects, and batches which are given up on, are written to
+/// the dead-letter file.
+///
+/// Once the stop signal is set, requests and retries are abandoned, so that whatever
+/// is left goes to the spool, or the dead-letter file, rather than being lost.
+pub struct Sender<T> {
+    client: T,
+    policy: RetryPolicy,
+    spool: Option<Spool>,
+    replay_interval: Duration,
+    replay_at: Option<Instant>,
+    rejections: Rejections,
+    stop: Option<watch::Receiver<bool>>,
+}
+
+impl<T: Ingest> Sender<T> {
+    pub fn new(config: &Config, client: T, spool: Option<Spool>) -> Self {
+        let replay_at = spool
+            .as_ref()
+            .filter(|spool| !spool.is_empty())
+            .map(|_| Instant::now());
+
+        Self {
+            client,
+            policy: RetryPolicy::from(config),
+            spool,
+            replay_interval: config.spool_replay_interval.0,
+            replay_at,
+            rejections: Rejections::default(),
+            stop: None,
+        }
+    }
+
+    pub fn with_dead_letter(mut self, dead_letter: Option<DeadLetter>) -> Self {
+        self.rejections.dead_letter = dead_letter;
+        self
+    }
+
+    pub fn with_stop(mut self, stop: watch::Receiver<bool>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stop
+            .as_ref()
+            .is_some_and(|stop| *stop.borrow())
+    }
+
+    pub fn stats(&self) -> DeliveryStats {
+        self.rejections.stats
+    }
+
+    /// Returns the time at which spooled batches should next be replayed
+    pub fn replay_at(&self) -> Option<Instant> {
+        self.replay_at
+    }
+
+    pub async fn send(&mut self, messages: Vec<LogMessage>) {
+        let spooling = self.spool
+            .as_ref()
+            .map(|spool| !spool.is_empty())
+            .unwrap_or(false);
+
+        if spooling {
+            self.spool(&messages)
+                .await;
+
+            return;
+        }
+
+        match self.ingest(&messages).await {
+            Ok(response) => {
+                self.rejections
+                    .accepted(&messages, response)
+                    .await;
+            },
+            Err(err) if err.is_retryable() && self.spool.is_some() => {
+                warn!(
+                    error = %err,
+                    count = messages.len(),
+                    "Error ingesting log messages; spooling them for replay",
+                );
+
+                self.spool(&messages)
+                    .await;
+            },
+            Err(err) => {
+                error!(
+                    error = %err,
+                    count = messages.len(),
+                    "Error ingesting log messages",
+                );
+
+                self.rejections
+                    .failed(&messages, &err)
+                    .await;
+            },
+        }
+    }
+
+    async fn spool(&mut self, messages: &[LogMessage]) {
+        let spool = match self.spool.as_mut() {
+            Some(spool) => spool,
+            None => return,
+        };
+
+        if let Err(err) = spool.append(messages).await {
+            error!(
+                error = %err,
+                count = messages.len(),
+                "Error spooling log messages",
+            );
+        }
+
+        if self.replay_at.is_none() {
+            self.replay_at = Some(Instant::now() + self.replay_interval);
+        }
+    }
+
+    /// Sends spooled batches, oldest first, until the spool is empty or a batch fails.
+    /// Each batch is attempted once; the whole spool is retried after `replay_interval`.
+    pub async fn replay(&mut self) {
+        let spool = match self.spool.as_mut() {
+            Some(spool) => spool,
+            None => return,
+        };
+
+        self.replay_at = None;
+
+        loop {
+            if self.stop.as_ref().is_some_and(|stop| *stop.borrow()) {
+                return; // left spooled for the next time the container logs
+            }
+
+            let messages = match spool.peek().await {
+                Ok(Some(messages)) => messages,
+                Ok(None) => break,
+                Err(err) => {
+                    // unreadable batches can never be delivered
+                    error!(
+                        error = %err,
+                        "Error reading spooled log messages; discarding them",
+                    );
+
+                    match spool.discard().await {
+                        Ok(_) => continue,
+                        Err(err) => {
+                            error!(error = %err, "Error discarding spooled log messages");
+                            self.replay_at = Some(Instant::now() + self.replay_interval);
+                            return;
+                        },
+                    }
+                },
+            };
+
+            let results = tokio::select! {
+                results = self.client.ingest(&messages) => results,
+                _ = stopped(self.stop.clone()) => return,
+            };
+
+            let results = match results {
+                Ok(response) => {
+                    self.rejections
+                        .accepted(&messages, response)
+                        .await;
+
+                    spool.ack().await
+                },
+                Err(err) if err.is_retryable() => {
+                    warn!(
+                        error = %err,
+                        pending = spool.len(),
+                        "Error replaying spooled log messages; retrying in {:?}", self.replay_interval,
+                    );
+
+                    self.replay_at = Some(Instant::now() + self.replay_interval);
+                    return;
+                },
+                Err(err) => {
+                    error!(
+                        error = %err,
+                        count = messages.len(),
+                        "Error replaying spooled log messages; discarding them",
+                    );
+
+                    self.rejections
+                        .failed(&messages, &err)
+                        .await;
+
+                    spool.discard().await
+                },
+            };
+
+            if let Err(err) = results {
+                error!(error = %err, "Error updating spool");
+                self.replay_at = Some(Instant::now() + self.replay_interval);
+                return;
+            }
+        }
+
+        let stats = spool.stats();
+
+        info!(
+            spooled = stats.spooled,
+            replayed = stats.replayed,
+            evicted = stats.evicted,
+            "Replayed all spooled log messages",
+        );
+    }
+
+    /// Makes a final attempt at replaying the spool; anything left is replayed the next
+    /// time the container starts logging
+    pub async fn finish(mut self) {
+        if self.replay_at.is_some() && !self.is_stopped() {
+            self.replay()
+                .await;
+        }
+
+        if let Some(spool) = self.spool.as_ref().filter(|spool| !spool.is_empty()) {
+            let stats = spool.stats();
+
+            warn!(
+                pending = spool.len(),
+                spooled = stats.spooled,
+                replayed = stats.replayed,
+                evicted = stats.evicted,
+                "Leaving {} log messages spooled", spool.len(),
+            );
+        }
+
+        let stats = self.stats();
+
+        if stats.rejected > 0 || stats.mismatches > 0 {
+            warn!(
+                delivered = stats.delivered,
+                rejected = stats.rejected,
+                mismatches = stats.mismatches,
+                "{} log messages were not delivered", stats.rejected,
+            );
+        }
+    }
+
+    /// Sends a batch directly, retrying it according to the RetryPolicy
+    pub async fn ingest(&mut self, messages: &[LogMessage]) -> Result<IngestResponse, IngestError> {
+        let started = Instant::now();
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+
+            if self.is_stopped() {
+                return Err(IngestError::Stopped);
+            }
+
+            let results = tokio::select! {
+                results = self.client.ingest(messages) => results,
+                _ = stopped(self.stop.clone()) => return Err(IngestError::Stopped),
+            };
+
+            let err = match results {
+                Ok(response) => return Ok(response),
+                Err(err) => err,
+            };
+
+            let delay = match self.policy.delay(attempt, started.elapsed(), &err) {
+                Some(delay) => delay,
+                None => return Err(err),
+            };
+
+            warn!(
+                error = %err,
+                attempt = attempt,
+                count = messages.len(),
+                "Error ingesting log messages; retrying in {:?}", delay,
+            );
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => (),
+                _ = stopped(self.stop.clone()) => return Err(IngestError::Stopped),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use envconfig::Envconfig;
+
+    use crate::{
+        client::{
+            HttpClients,
+            Rejection,
+        },
+        container::ContainerInfo,
+        dead_letter::DeadLetterOptions,
+        error::BoxedError,
+        spool::SpoolOptions,
+    };
+
+    use super::*;
+
+    // fails until `failures` reaches zero, recording what it delivers
+    #[derive(Default)]
+    struct TestClient {
+        failures: usize,
+        status: u16,
+        // indexes of messages in each batch to reject
+        rejected: Vec<usize>,
+        delivered: Vec<String>,
+    }
+
+    #[async_trait::async_trait]
+    impl Ingest for TestClient {
+        fn new(_: &Config, _: &ContainerInfo, _: &HttpClients) -> Result<Self, BoxedError> {
+            Err("built by the test with its responses".into())
+        }
+
+        async fn ingest(&mut self, messages: &[LogMessage]) -> Result<IngestResponse, IngestError> {
+            if self.failures > 0 {
+                self.failures -= 1;
+
+                return Err(IngestError::Status {
+                    status: reqwest::StatusCode::from_u16(self.status).unwrap(),
+                    retry_after: None,
+                    body: String::new(),
+                });
+            }
+
+            let rejected = self.rejected
+                .iter()
+                .map(|index| Rejection { index: *index, reason: Some("invalid".to_string()) })
+                .collect::<Vec<_>>();
+
+            self.delivered.extend(messages.iter().map(|message| message.message.clone()));
+
+            Ok(IngestResponse {
+                count: Some(messages.len() - rejected.len()),
+                rejected,
+            })
+        }
+    }
+
+    fn message(text: &str) -> LogMessage {
+        LogMessage {
+            timestamp: chrono::Utc::now(),
+            message: text.to_string(),
+            level: 3,
+            context: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spool_and_replay() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let config = Config {
+            retry_max_attempts: 1,
+            ..Config::init_from_hashmap(&HashMap::new()).unwrap()
+        };
+        let spool = Spool::open(dir.path(), "container", SpoolOptions::from(&config))
+            .await
+            .unwrap();
+        let client = TestClient {
+            failures: 2,
+            status: 503,
+            ..TestClient::default()
+        };
+
+        let mut sender = Sender::new(&config, client, Some(spool));
+
+        assert!(sender.replay_at().is_none());
+
+        // the first batch fails and is spooled; the second is spooled behind it
+        sender.send(vec![message("1")]).await;
+        sender.send(vec![message("2")]).await;
+
+        assert!(sender.replay_at().is_some());
+        assert!(sender.client.delivered.is_empty());
+
+        // the endpoint is still failing
+        sender.replay().await;
+        assert!(sender.replay_at().is_some());
+
+        sender.replay().await;
+        assert!(sender.replay_at().is_none());
+        assert_eq!(sender.client.delivered, vec!["1", "2"]);
+
+        // delivered directly once the spool is empty
+        sender.send(vec![message("3")]).await;
+        assert_eq!(sender.client.delivered, vec!["1", "2", "3"]);
+
+        let stats = sender.spool
+            .as_ref()
+            .unwrap()
+            .stats();
+
+        assert_eq!((stats.spooled, stats.replayed, stats.evicted), (2, 2, 0));
+    }
+
+    #[tokio::test]
+    async fn test_replays_existing_spool() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let config = Config::init_from_hashmap(&HashMap::new())
+            .unwrap();
+        let mut spool = Spool::open(dir.path(), "container", SpoolOptions::from(&config))
+            .await
+            .unwrap();
+
+        spool.append(&[message("spooled")]).await.unwrap();
+        drop(spool);
+
+        // e.g. after the plugin restarts
+        let spool = Spool::open(dir.path(), "container", SpoolOptions::from(&config))
+            .await
+            .unwrap();
+        let client = TestClient::default();
+
+        let mut sender = Sender::new(&config, client, Some(spool));
+
+        assert!(sender.replay_at().is_some());
+
+        // new batches are delivered after those already spooled
+        sender.send(vec![message("new")]).await;
+        sender.replay().await;
+
+        assert_eq!(sender.client.delivered, vec!["spooled", "new"]);
+    }
+
+    #[tokio::test]
+    async fn test_rejections_dead_lettered() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let config = Config::init_from_hashmap(&HashMap::new())
+            .unwrap();
+        let dead_letter = DeadLetter::open(dir.path(), "container", DeadLetterOptions::from(&config))
+            .await
+            .unwrap();
+        let client = TestClient {
+            rejected: vec![1],
+            ..TestClient::default()
+        };
+
+        let mut sender = Sender::new(&config, client, None)
+            .with_dead_letter(Some(dead_letter));
+
+        sender.send(vec![message("1"), message("2"), message("3")]).await;
+
+        // a permanent failure dead-letters the whole batch
+        sender.client.failures = 1;
+        sender.client.status = 400;
+        sender.send(vec![message("4")]).await;
+
+        assert_eq!(sender.stats(), DeliveryStats { delivered: 2, rejected: 2, mismatches: 0 });
+
+        let path = sender.rejections.dead_letter
+            .as_ref()
+            .unwrap()
+            .path();
+        let records = tokio::fs::read_to_string(path)
+            .await
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<DeadLetterRecord>(line).unwrap())
+            .map(|record| (record.message.message, record.reason))
+            .collect::<Vec<_>>();
+
+        assert_eq!(records, vec![
+            ("2".to_string(), "invalid".to_string()),
+            ("4".to_string(), "Unexpected status: 400 Bad Request".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_accepted_count_mismatch() {
+        let mut rejections = Rejections::default();
+        let response = IngestResponse {
+            count: Some(1),
+            rejected: Vec::new(),
+        };
+
+        rejections.accepted(&[message("1"), message("2")], response).await;
+
+        assert_eq!(rejections.stats.mismatches, 1);
+    }
+}
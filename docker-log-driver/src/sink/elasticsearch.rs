@@ -0,0 +1,479 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::SecondsFormat;
+use reqwest::header::{
+    HeaderMap,
+    CONTENT_ENCODING,
+    CONTENT_TYPE,
+};
+use serde::Deserialize;
+use serde_json::{
+    json,
+    Value,
+};
+use tokio::time::Instant;
+use tracing::{
+    debug,
+    warn,
+};
+
+use crate::{
+    auth,
+    client::{
+        self,
+        ClientOptions,
+        HttpClients,
+        Ingest,
+        IngestError,
+        IngestResponse,
+        Rejection,
+    },
+    compression::Compression,
+    config::Config,
+    container::ContainerInfo,
+    error::BoxedError,
+    log::LogMessage,
+    metrics,
+    retry::RetryPolicy,
+};
+
+
+const BULK_PATH: &str = "/_bulk";
+
+
+/// The result of one action in a bulk request
+#[derive(Debug, Deserialize)]
+struct BulkItem {
+    status: u16,
+    #[serde(default)]
+    error: Option<Value>,
+}
+
+impl BulkItem {
+    fn status(&self) -> reqwest::StatusCode {
+        reqwest::StatusCode::from_u16(self.status)
+            .unwrap_or(reqwest::StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    /// The item's error as `type: reason`, e.g. `mapper_parsing_exception: failed to parse`
+    fn reason(&self) -> String {
+        let error = match self.error.as_ref() {
+            Some(error) => error,
+            None => return self.status().to_string(),
+        };
+
+        match (error.get("type").and_then(Value::as_str), error.get("reason").and_then(Value::as_str)) {
+            (Some(kind), Some(reason)) => format!("{}: {}", kind, reason),
+            (Some(kind), None) => kind.to_string(),
+            _ => error.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BulkResponse {
+    // each item is keyed by its action, e.g. {"create": {...}}
+    #[serde(default)]
+    items: Vec<HashMap<String, BulkItem>>,
+}
+
+
+/// Makes an index name lowercase, replacing characters Elasticsearch doesn't allow
+fn sanitize_index(name: &str) -> String {
+    name
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            ' ' | ',' | '/' | '\\' | '*' | '?' | '"' | '<' | '>' | '|' | '#' | ':' => '_',
+            _ => c,
+        })
+        .collect()
+}
+
+
+/// Indexes messages with the Elasticsearch (or OpenSearch) `_bulk` API.
+///
+/// Each message's index comes from `elasticsearch-index`, with strftime escapes (e.g.
+/// `%Y.%m.%d`) taken from its timestamp and `{field}` from its context or the
+/// container's labels.  Items which fail with a retryable status are retried on their
+/// own, per the RetryPolicy; items which fail otherwise are reported as rejected.
+pub struct ElasticsearchClient {
+    // as configured; used in logs and metrics
+    url: String,
+    bulk_url: String,
+    client: reqwest::Client,
+    headers: HeaderMap,
+    compression: Compression,
+    compression_min_size: usize,
+    index: String,
+    policy: RetryPolicy,
+    container_labels: HashMap<String, String>,
+}
+
+impl ElasticsearchClient {
+    fn index_name(&self, message: &LogMessage) -> String {
+        let dated = message.timestamp
+            .format(&self.index)
+            .to_string();
+
+        let mut name = String::new();
+        let mut rest = dated.as_str();
+
+        while let Some(start) = rest.find('{') {
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+
+            let value = super::metadata(message, &self.container_labels, &rest[start + 1..end])
+                .filter(|value| !value.is_empty())
+                .unwrap_or_else(|| "unknown".to_string());
+
+            name.push_str(&rest[..start]);
+            name.push_str(&value);
+            rest = &rest[end + 1..];
+        }
+
+        name.push_str(rest);
+        sanitize_index(&name)
+    }
+
+    fn encode(&self, messages: &[&LogMessage]) -> Result<Vec<u8>, serde_json::Error> {
+        let mut body = Vec::new();
+
+        for message in messages {
+            let mut document = serde_json::to_value(message)?;
+
+            if let Value::Object(fields) = &mut document {
+                fields.insert("@timestamp".to_string(), Value::String(message.timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true)));
+            }
+
+            serde_json::to_writer(&mut body, &json!({ "create": { "_index": self.index_name(message) } }))?;
+            body.push(b'\n');
+            serde_json::to_writer(&mut body, &document)?;
+            body.push(b'\n');
+        }
+
+        Ok(body)
+    }
+
+    /// Sends a bulk request, returning the result of each item
+    async fn bulk(&self, messages: &[&LogMessage]) -> Result<Vec<BulkItem>, IngestError> {
+        let body = self.encode(messages)
+            .map_err(|e| IngestError::Encode(e.into()))?;
+        let (body, encoding) = self.compression
+            .encode(body, self.compression_min_size)
+            .map_err(IngestError::Encode)?;
+
+        let mut request = self.client
+            .post(&self.bulk_url)
+            .headers(self.headers.clone())
+            .header(CONTENT_TYPE, "application/x-ndjson")
+            .body(body);
+
+        if let Some(encoding) = encoding {
+            request = request.header(CONTENT_ENCODING, encoding);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(IngestError::Request)?;
+        let body = client::check_status(response)
+            .await?
+            .bytes()
+            .await
+            .map_err(IngestError::Response)?;
+
+        let items = serde_json::from_slice::<BulkResponse>(&body)
+            .map_err(IngestError::Decode)?
+            .items
+            .into_iter()
+            .filter_map(|item| item.into_values().next())
+            .collect::<Vec<_>>();
+
+        if items.len() != messages.len() {
+            return Err(IngestError::Decode(serde::de::Error::custom(format!(
+                "expected {} bulk items, got {}", messages.len(), items.len(),
+            ))));
+        }
+
+        Ok(items)
+    }
+}
+
+#[async_trait]
+impl Ingest for ElasticsearchClient {
+    fn new(config: &Config, info: &ContainerInfo, clients: &HttpClients) -> Result<Self, BoxedError> {
+        let options = ClientOptions::from(config);
+
+        Ok(Self {
+            url: config.elasticsearch_url.clone(),
+            bulk_url: format!("{}{}", options.tls.url(&config.elasticsearch_url).trim_end_matches('/'), BULK_PATH),
+            client: clients.get(&config.elasticsearch_url, &options)?,
+            headers: auth::headers(config)?,
+            compression: config.compression,
+            compression_min_size: config.compression_min_size.0 as usize,
+            index: config.elasticsearch_index.clone(),
+            policy: RetryPolicy::from(config),
+            container_labels: info.container_labels.clone(),
+        })
+    }
+
+    /// A failed request is returned as an error, so the whole batch is retried.  Once a
+    /// request succeeds, only the items which failed are sent again.
+    async fn ingest(&mut self, messages: &[LogMessage]) -> Result<IngestResponse, IngestError> {
+        let started = Instant::now();
+        let mut pending = (0..messages.len()).collect::<Vec<_>>();
+        let mut rejected = Vec::new();
+        let mut attempt = 0;
+
+        while !pending.is_empty() {
+            attempt += 1;
+
+            let batch = pending
+                .iter()
+                .map(|index| &messages[*index])
+                .collect::<Vec<_>>();
+
+            let (retry, err) = match self.bulk(&batch).await {
+                Ok(items) => {
+                    metrics::record_batch(&self.url, batch.len());
+
+                    let mut retry = Vec::new();
+                    let mut err = None;
+
+                    for (index, item) in pending.iter().copied().zip(items) {
+                        let status = item.status();
+
+                        if status.is_success() {
+                            continue;
+                        }
+
+                        let item_err = IngestError::Status {
+                            status,
+                            retry_after: None,
+                            body: item.reason(),
+                        };
+
+                        match item_err.is_retryable() {
+                            true => {
+                                retry.push(index);
+                                err = Some(item_err);
+                            },
+                            false => rejected.push(Rejection {
+                                index,
+                                reason: Some(item.reason()),
+                            }),
+                        }
+                    }
+
+                    (retry, err)
+                },
+                Err(err) if attempt == 1 => {
+                    metrics::record_failure(&self.url);
+                    return Err(err);
+                },
+                Err(err) => {
+                    metrics::record_failure(&self.url);
+                    (pending.clone(), Some(err))
+                },
+            };
+
+            let err = match err {
+                Some(err) => err,
+                None => break,
+            };
+
+            match self.policy.delay(attempt, started.elapsed(), &err) {
+                Some(delay) => {
+                    warn!(
+                        error = %err,
+                        attempt = attempt,
+                        count = retry.len(),
+                        "Error indexing {} log messages; retrying them in {:?}", retry.len(), delay,
+                    );
+
+                    tokio::time::sleep(delay)
+                        .await;
+                },
+                None => {
+                    let reason = match err.body() {
+                        Some(body) if !body.is_empty() => body.to_string(),
+                        _ => err.to_string(),
+                    };
+
+                    rejected.extend(
+                        retry
+                            .iter()
+                            .map(|index| Rejection {
+                                index: *index,
+                                reason: Some(reason.clone()),
+                            })
+                    );
+
+                    break;
+                },
+            }
+
+            pending = retry;
+        }
+
+        debug!(
+            endpoint = %metrics::label(&self.url),
+            count = messages.len(),
+            rejected = rejected.len(),
+            "Indexed log messages",
+        );
+
+        Ok(IngestResponse {
+            count: Some(messages.len() - rejected.len()),
+            rejected,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        Mutex,
+    };
+
+    use envconfig::Envconfig;
+
+    use super::*;
+
+    type Requests = Arc<Mutex<Vec<Vec<Value>>>>;
+
+    // serves the bulk API, answering with each of `responses` in turn and recording the
+    // documents in each request
+    fn serve(responses: Vec<Value>) -> (String, Requests) {
+        let requests = Requests::default();
+        let recorded = requests.clone();
+        let app = axum::Router::new()
+            .route(BULK_PATH, axum::routing::post(move |body: String| {
+                let documents = body
+                    .lines()
+                    .skip(1)
+                    .step_by(2)
+                    .map(|line| serde_json::from_str::<Value>(line).unwrap())
+                    .collect::<Vec<_>>();
+
+                let mut requests = recorded
+                    .lock()
+                    .unwrap();
+                let response = responses[requests.len()].clone();
+
+                requests.push(documents);
+                async move { axum::Json(response) }
+            }));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service())
+        );
+
+        (url, requests)
+    }
+
+    fn client(url: &str) -> ElasticsearchClient {
+        let config = Config::init_from_hashmap(&HashMap::from([
+            ("ELASTICSEARCH_URL".to_string(), url.to_string()),
+            ("ELASTICSEARCH_INDEX".to_string(), "logs-{compose_service}-{source}-%Y.%m.%d".to_string()),
+            ("RETRY_INITIAL_BACKOFF".to_string(), "1ms".to_string()),
+        ])).unwrap();
+        let info = ContainerInfo {
+            container_labels: HashMap::from([("com.docker.compose.service".to_string(), "Web".to_string())]),
+            ..Default::default()
+        };
+
+        ElasticsearchClient::new(&config, &info, &HttpClients::default())
+            .unwrap()
+    }
+
+    fn message(text: &str) -> LogMessage {
+        LogMessage {
+            timestamp: chrono::TimeZone::with_ymd_and_hms(&chrono::Utc, 2023, 1, 2, 3, 4, 5).unwrap(),
+            message: text.to_string(),
+            level: 3,
+            context: Some(json!({ "source": "stdout" })),
+        }
+    }
+
+    fn item(status: u16) -> Value {
+        match status {
+            200..=299 => json!({ "create": { "status": status } }),
+            _ => json!({ "create": { "status": status, "error": { "type": "test_exception", "reason": format!("failed with {}", status) } } }),
+        }
+    }
+
+    #[test]
+    fn test_index_name() {
+        let client = client("http://localhost:9200");
+
+        assert_eq!(client.index_name(&message("hello")), "logs-web-stdout-2023.01.02");
+
+        let message = LogMessage {
+            context: None,
+            ..message("hello")
+        };
+
+        assert_eq!(client.index_name(&message), "logs-web-unknown-2023.01.02");
+    }
+
+    #[tokio::test]
+    async fn test_retry_failed_items() {
+        let (url, requests) = serve(vec![
+            json!({ "errors": true, "items": [item(201), item(429), item(400), item(503)] }),
+            json!({ "errors": true, "items": [item(201), item(503)] }),
+            json!({ "errors": false, "items": [item(201)] }),
+        ]);
+        let mut client = client(&url);
+        let messages = ["1", "2", "3", "4"]
+            .iter()
+            .map(|text| message(text))
+            .collect::<Vec<_>>();
+
+        let response = client.ingest(&messages)
+            .await
+            .unwrap();
+
+        assert_eq!(response.count, Some(3));
+        assert_eq!(response.rejected, vec![Rejection { index: 2, reason: Some("test_exception: failed with 400".to_string()) }]);
+
+        let sent = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|documents| documents.iter().map(|document| document["message"].as_str().unwrap().to_string()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(sent, vec![vec!["1", "2", "3", "4"], vec!["2", "4"], vec!["4"]]);
+        assert_eq!(requests.lock().unwrap()[0][0]["@timestamp"], "2023-01-02T03:04:05Z");
+    }
+
+    #[tokio::test]
+    async fn test_give_up_on_items() {
+        let responses = (0..5)
+            .map(|_| json!({ "errors": true, "items": [item(503)] }))
+            .collect();
+        let (url, requests) = serve(responses);
+        let mut client = client(&url);
+
+        let response = client.ingest(&[message("1")])
+            .await
+            .unwrap();
+
+        assert_eq!(response.count, Some(0));
+        assert_eq!(response.rejected[0].reason.as_deref(), Some("test_exception: failed with 503"));
+        assert_eq!(requests.lock().unwrap().len(), 5);
+    }
+}
@@ -0,0 +1,178 @@
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use async_trait::async_trait;
+use tokio::{
+    fs::{
+        self,
+        File,
+    },
+    io::AsyncWriteExt,
+};
+
+use crate::{
+    client::{
+        HttpClients,
+        Ingest,
+        IngestError,
+        IngestResponse,
+    },
+    config::{
+        is_contained,
+        Config,
+    },
+    container::ContainerInfo,
+    error::BoxedError,
+    log::LogMessage,
+};
+
+
+/// Appends messages to a local file as newline delimited JSON, e.g. to keep a copy of
+/// a container's logs while migrating between destinations.  The file isn't rotated.
+pub struct FileSink {
+    path: PathBuf,
+    // opened with the first batch, as `new` can't wait on the filesystem
+    file: Option<File>,
+}
+
+impl FileSink {
+    /// `file-path` with `{id}` and `{name}` replaced by the container's ID and name, or
+    /// a file named after the container, under `file-dir` or the state directory
+    pub fn path(config: &Config, info: &ContainerInfo) -> Result<PathBuf, BoxedError> {
+        let dir = match config.file_dir.as_deref() {
+            Some(dir) => PathBuf::from(dir),
+            None => Path::new(&config.state_dir).join("file"),
+        };
+
+        let name = match config.file_path.as_deref() {
+            Some(template) => PathBuf::from(
+                template
+                    .replace("{id}", &info.container_id)
+                    .replace("{name}", info.name())
+            ),
+            None => PathBuf::from(format!("{}.ndjson", info.container_id)),
+        };
+
+        if !is_contained(&name) {
+            return Err(format!("{} is outside of {}", name.display(), dir.display()).into());
+        }
+
+        Ok(dir.join(name))
+    }
+
+    async fn open(&mut self) -> Result<&mut File, std::io::Error> {
+        if self.file.is_none() {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir).await?;
+            }
+
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await?;
+
+            self.file = Some(file);
+        }
+
+        Ok(self.file.as_mut().unwrap())
+    }
+}
+
+#[async_trait]
+impl Ingest for FileSink {
+    fn new(config: &Config, info: &ContainerInfo, _: &HttpClients) -> Result<Self, BoxedError> {
+        Ok(Self {
+            path: Self::path(config, info)?,
+            file: None,
+        })
+    }
+
+    async fn ingest(&mut self, messages: &[LogMessage]) -> Result<IngestResponse, IngestError> {
+        let mut lines = Vec::new();
+
+        for message in messages {
+            serde_json::to_writer(&mut lines, message)
+                .map_err(|e| IngestError::Encode(e.into()))?;
+            lines.push(b'\n');
+        }
+
+        let file = self
+            .open()
+            .await
+            .map_err(IngestError::Write)?;
+
+        file
+            .write_all(&lines)
+            .await
+            .map_err(IngestError::Write)?;
+        file
+            .flush()
+            .await
+            .map_err(IngestError::Write)?;
+
+        Ok(IngestResponse {
+            count: Some(messages.len()),
+            rejected: Vec::new(),
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use envconfig::Envconfig;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+        let config = Config {
+            file_dir: Some(dir.path().display().to_string()),
+            file_path: Some("{name}/{id}.ndjson".to_string()),
+            ..Config::init_from_hashmap(&HashMap::new()).unwrap()
+        };
+        let info = ContainerInfo {
+            container_id: "abc123".to_string(),
+            container_name: "/web".to_string(),
+            ..Default::default()
+        };
+
+        let path = FileSink::path(&config, &info)
+            .unwrap();
+        assert_eq!(path, dir.path().join("web").join("abc123.ndjson"));
+
+        for count in [1, 2] {
+            let mut sink = FileSink::new(&config, &info, &HttpClients::default())
+                .unwrap();
+
+            let response = sink.ingest(&[LogMessage::dropped(count)])
+                .await
+                .unwrap();
+
+            assert_eq!(response.count, Some(1));
+        }
+
+        // appended to after reopening
+        let lines = std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<LogMessage>(line).unwrap().message)
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines, vec!["1 messages dropped", "2 messages dropped"]);
+
+        let info = ContainerInfo {
+            container_name: "/..".to_string(),
+            ..info
+        };
+
+        assert!(FileSink::path(&config, &info).is_err());
+    }
+}
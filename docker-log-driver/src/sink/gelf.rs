@@ -0,0 +1,499 @@
+use std::{
+    fmt,
+    str::FromStr,
+};
+
+use async_trait::async_trait;
+use serde_json::{
+    json,
+    Map,
+    Value,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{
+        TcpStream,
+        UdpSocket,
+    },
+};
+use tracing::debug;
+
+use crate::{
+    client::{
+        HttpClients,
+        Ingest,
+        IngestError,
+        IngestResponse,
+        Rejection,
+    },
+    compression::Compression,
+    config::Config,
+    container::ContainerInfo,
+    error::BoxedError,
+    log::LogMessage,
+    metrics,
+};
+
+use super::severity;
+
+
+// Chunked GELF magic bytes, followed by an 8 byte message ID, sequence number and count
+const CHUNK_MAGIC: [u8; 2] = [0x1e, 0x0f];
+
+const CHUNK_HEADER_SIZE: usize = 12;
+
+// Graylog discards messages split into more chunks than this
+const MAX_CHUNKS: usize = 128;
+
+
+/// Transport GELF messages are sent over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GelfProtocol {
+    Udp,
+    Tcp,
+}
+
+
+/// Where a gelf sink sends to, e.g. `udp://graylog:12201`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GelfAddress {
+    pub protocol: GelfProtocol,
+    // host and port
+    pub address: String,
+}
+
+impl FromStr for GelfAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, address) = s
+            .trim()
+            .split_once("://")
+            .ok_or_else(|| format!("Invalid address: {}; expected udp://host:port or tcp://host:port", s))?;
+
+        let protocol = match scheme {
+            "udp" => GelfProtocol::Udp,
+            "tcp" => GelfProtocol::Tcp,
+            _ => return Err(format!("Invalid protocol: {}; expected udp or tcp", scheme)),
+        };
+
+        match address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(Self {
+                protocol,
+                address: address.to_string(),
+            }),
+            _ => Err(format!("Invalid address: {}; expected host:port", address)),
+        }
+    }
+}
+
+impl fmt::Display for GelfAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.protocol {
+            GelfProtocol::Udp => write!(f, "udp://{}", self.address),
+            GelfProtocol::Tcp => write!(f, "tcp://{}", self.address),
+        }
+    }
+}
+
+
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+
+/// Additional field names may only contain word characters, `.` and `-`, and `_id` is
+/// reserved
+fn field_name(key: &str) -> String {
+    let name = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') { c } else { '_' })
+        .collect::<String>();
+
+    match name.as_str() {
+        "id" => "_id_".to_string(),
+        _ => format!("_{}", name),
+    }
+}
+
+
+/// Sends messages to Graylog, or anything else accepting GELF 1.1, over UDP or TCP.
+///
+/// Over UDP each message is a datagram, gzipped per `gelf-compression` and split into
+/// chunks of at most `gelf-chunk-size` bytes.  Over TCP messages are null-byte delimited
+/// and sent uncompressed, as Graylog requires.
+pub struct GelfClient {
+    address: GelfAddress,
+    connection: Option<Connection>,
+    host: String,
+    compression: Compression,
+    chunk_size: usize,
+}
+
+impl GelfClient {
+    fn message(&self, message: &LogMessage) -> Value {
+        let mut gelf = Map::new();
+
+        gelf.insert("version".to_string(), json!("1.1"));
+        gelf.insert("host".to_string(), json!(self.host));
+        gelf.insert("short_message".to_string(), json!(message.message));
+        gelf.insert("timestamp".to_string(), json!(message.timestamp.timestamp_millis() as f64 / 1000.0));
+        gelf.insert("level".to_string(), json!(severity(message.level)));
+
+        if let Some(Value::Object(context)) = message.context.as_ref() {
+            for (key, value) in context {
+                let value = match value {
+                    Value::Null => continue,
+                    Value::String(_) | Value::Number(_) => value.clone(),
+                    _ => Value::String(value.to_string()),
+                };
+
+                gelf.insert(field_name(key), value);
+            }
+        }
+
+        Value::Object(gelf)
+    }
+
+    /// Splits a datagram into chunks, or returns None if it needs more than Graylog accepts
+    fn chunks(&self, payload: Vec<u8>) -> Option<Vec<Vec<u8>>> {
+        if payload.len() <= self.chunk_size {
+            return Some(vec![payload]);
+        }
+
+        let id = fastrand::u64(..).to_be_bytes();
+        let parts = payload.chunks(self.chunk_size - CHUNK_HEADER_SIZE);
+        let count = parts.len();
+
+        if count > MAX_CHUNKS {
+            return None;
+        }
+
+        let chunks = parts
+            .enumerate()
+            .map(|(sequence, part)| {
+                let mut chunk = Vec::with_capacity(CHUNK_HEADER_SIZE + part.len());
+
+                chunk.extend_from_slice(&CHUNK_MAGIC);
+                chunk.extend_from_slice(&id);
+                chunk.push(sequence as u8);
+                chunk.push(count as u8);
+                chunk.extend_from_slice(part);
+                chunk
+            })
+            .collect();
+
+        Some(chunks)
+    }
+
+    async fn connect(&self) -> Result<Connection, std::io::Error> {
+        match self.address.protocol {
+            GelfProtocol::Udp => {
+                let remote = tokio::net::lookup_host(&self.address.address)
+                    .await?
+                    .next()
+                    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses resolved"))?;
+                let local = if remote.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let socket = UdpSocket::bind(local)
+                    .await?;
+
+                socket.connect(remote)
+                    .await?;
+
+                Ok(Connection::Udp(socket))
+            },
+            GelfProtocol::Tcp => {
+                let stream = TcpStream::connect(&self.address.address)
+                    .await?;
+
+                stream.set_nodelay(true)?;
+                Ok(Connection::Tcp(stream))
+            },
+        }
+    }
+
+    async fn write(&self, connection: &mut Connection, messages: &[LogMessage]) -> Result<Vec<Rejection>, std::io::Error> {
+        let mut rejected = Vec::new();
+
+        match connection {
+            Connection::Udp(socket) => {
+                for (index, message) in messages.iter().enumerate() {
+                    let payload = self.compression.compress(self.message(message).to_string().as_bytes())?;
+                    let size = payload.len();
+
+                    let Some(chunks) = self.chunks(payload) else {
+                        rejected.push(Rejection {
+                            index,
+                            reason: Some(format!("{} bytes is too large to send in {} chunks", size, MAX_CHUNKS)),
+                        });
+                        continue;
+                    };
+
+                    for chunk in chunks {
+                        socket.send(&chunk)
+                            .await?;
+                    }
+                }
+            },
+            Connection::Tcp(stream) => {
+                let mut frames = Vec::new();
+
+                for message in messages {
+                    serde_json::to_writer(&mut frames, &self.message(message))?;
+                    frames.push(0);
+                }
+
+                stream.write_all(&frames)
+                    .await?;
+            },
+        }
+
+        Ok(rejected)
+    }
+
+    /// Sends over the open connection, or a new one; a connection which fails is dropped,
+    /// to be reopened on the next attempt
+    async fn send(&mut self, messages: &[LogMessage]) -> Result<Vec<Rejection>, std::io::Error> {
+        let mut connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => self.connect().await?,
+        };
+
+        let rejected = self.write(&mut connection, messages)
+            .await?;
+
+        self.connection = Some(connection);
+        Ok(rejected)
+    }
+}
+
+#[async_trait]
+impl Ingest for GelfClient {
+    fn new(config: &Config, info: &ContainerInfo, _: &HttpClients) -> Result<Self, BoxedError> {
+        Ok(Self {
+            address: config.gelf_address.clone(),
+            connection: None,
+            host: config.gelf_host
+                .clone()
+                .unwrap_or_else(|| info.name().to_string()),
+            compression: config.gelf_compression,
+            chunk_size: config.gelf_chunk_size,
+        })
+    }
+
+    async fn ingest(&mut self, messages: &[LogMessage]) -> Result<IngestResponse, IngestError> {
+        let endpoint = self.address.to_string();
+
+        match self.send(messages).await {
+            Ok(rejected) => {
+                metrics::record_batch(&endpoint, messages.len() - rejected.len());
+
+                debug!(
+                    endpoint = endpoint,
+                    count = messages.len() - rejected.len(),
+                    "Sent GELF messages",
+                );
+
+                Ok(IngestResponse {
+                    count: Some(messages.len() - rejected.len()),
+                    rejected,
+                })
+            },
+            Err(err) => {
+                metrics::record_failure(&endpoint);
+                Err(IngestError::Write(err))
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::Read,
+    };
+
+    use envconfig::Envconfig;
+    use tokio::{
+        io::AsyncReadExt,
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    fn client(pairs: &[(&str, &str)]) -> GelfClient {
+        let env = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        let config = Config::init_from_hashmap(&env)
+            .unwrap();
+        let info = ContainerInfo {
+            container_name: "/web".to_string(),
+            ..Default::default()
+        };
+
+        GelfClient::new(&config, &info, &HttpClients::default())
+            .unwrap()
+    }
+
+    fn message(text: &str) -> LogMessage {
+        LogMessage {
+            timestamp: chrono::TimeZone::timestamp_millis_opt(&chrono::Utc, 1_672_628_645_250).unwrap(),
+            message: text.to_string(),
+            level: 5,
+            context: Some(json!({
+                "container_name": "web",
+                "source": "stderr",
+                "id": 7,
+                "com.example/team": "payments",
+                "retry": true,
+                "trace": null,
+            })),
+        }
+    }
+
+    #[test]
+    fn test_address() {
+        let address = "tcp://graylog:12201".parse::<GelfAddress>()
+            .unwrap();
+
+        assert_eq!(address.protocol, GelfProtocol::Tcp);
+        assert_eq!(address.address, "graylog:12201");
+        assert_eq!(address.to_string(), "tcp://graylog:12201");
+
+        for invalid in ["graylog:12201", "http://graylog:12201", "udp://graylog", "udp://:12201"] {
+            assert!(invalid.parse::<GelfAddress>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_message() {
+        let client = client(&[]);
+
+        assert_eq!(client.message(&message("hello")), json!({
+            "version": "1.1",
+            "host": "web",
+            "short_message": "hello",
+            "timestamp": 1_672_628_645.25,
+            "level": 3,
+            "_container_name": "web",
+            "_source": "stderr",
+            "_id_": 7,
+            "_com.example_team": "payments",
+            "_retry": "true",
+        }));
+    }
+
+    #[test]
+    fn test_chunks() {
+        let client = client(&[("GELF_CHUNK_SIZE", "128")]);
+
+        assert_eq!(client.chunks(vec![1; 128]).unwrap().len(), 1);
+
+        let chunks = client.chunks((0..250).map(|i| i as u8).collect())
+            .unwrap();
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[2].len(), CHUNK_HEADER_SIZE + 18);
+
+        for (sequence, chunk) in chunks.iter().enumerate() {
+            assert_eq!(chunk[..2], CHUNK_MAGIC);
+            assert_eq!(chunk[2..10], chunks[0][2..10]);
+            assert_eq!(chunk[10], sequence as u8);
+            assert_eq!(chunk[11], 3);
+        }
+
+        let payload = chunks
+            .iter()
+            .flat_map(|chunk| chunk[CHUNK_HEADER_SIZE..].to_vec())
+            .collect::<Vec<_>>();
+
+        assert_eq!(payload, (0..250).map(|i| i as u8).collect::<Vec<_>>());
+        assert!(client.chunks(vec![0; 116 * MAX_CHUNKS + 1]).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let address = format!("udp://{}", server.local_addr().unwrap());
+        let mut client = client(&[("GELF_ADDRESS", &address), ("GELF_CHUNK_SIZE", "512")]);
+
+        let random = |len: usize| (0..len)
+            .map(|_| fastrand::alphanumeric())
+            .collect::<String>();
+        let response = client.ingest(&[message("hello"), message(&random(2000))])
+            .await
+            .unwrap();
+
+        assert_eq!(response.count, Some(2));
+
+        // the first is a single gzipped datagram, the second chunked
+        let mut datagram = vec![0; 65536];
+        let len = server.recv(&mut datagram)
+            .await
+            .unwrap();
+        let mut decoded = String::new();
+
+        flate2::read::GzDecoder::new(&datagram[..len])
+            .read_to_string(&mut decoded)
+            .unwrap();
+
+        assert_eq!(serde_json::from_str::<Value>(&decoded).unwrap()["short_message"], "hello");
+
+        let len = server.recv(&mut datagram)
+            .await
+            .unwrap();
+
+        assert_eq!(datagram[..2], CHUNK_MAGIC);
+        assert_eq!(len, 512);
+
+        // too large to chunk, even compressed
+        let response = client.ingest(&[message(&random(100 * 1024))])
+            .await
+            .unwrap();
+
+        assert_eq!(response.count, Some(0));
+        assert_eq!(response.rejected[0].index, 0);
+    }
+
+    #[tokio::test]
+    async fn test_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let address = format!("tcp://{}", listener.local_addr().unwrap());
+        let mut client = client(&[("GELF_ADDRESS", &address)]);
+
+        client.ingest(&[message("one"), message("two")])
+            .await
+            .unwrap();
+
+        let (mut stream, _) = listener.accept()
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+
+        while received.iter().filter(|&&b| b == 0).count() < 2 {
+            let mut buf = [0; 4096];
+            let n = stream.read(&mut buf)
+                .await
+                .unwrap();
+
+            received.extend_from_slice(&buf[..n]);
+        }
+
+        let messages = received
+            .split(|&b| b == 0)
+            .filter(|frame| !frame.is_empty())
+            .map(|frame| serde_json::from_slice::<Value>(frame).unwrap()["short_message"].clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(messages, vec!["one", "two"]);
+    }
+}
@@ -0,0 +1,474 @@
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    fmt,
+    str::FromStr,
+};
+
+use async_trait::async_trait;
+use prost::Message;
+use reqwest::header::{
+    HeaderMap,
+    HeaderValue,
+    CONTENT_ENCODING,
+    CONTENT_TYPE,
+};
+use serde_json::{
+    json,
+    Value,
+};
+use tracing::debug;
+
+use crate::{
+    auth,
+    client::{
+        self,
+        ClientOptions,
+        HttpClients,
+        Ingest,
+        IngestError,
+        IngestResponse,
+    },
+    compression::Compression,
+    config::Config,
+    container::ContainerInfo,
+    error::BoxedError,
+    log::LogMessage,
+    metrics,
+    snappy,
+};
+
+
+const PUSH_PATH: &str = "/loki/api/v1/push";
+
+const TENANT_HEADER: &str = "X-Scope-OrgID";
+
+
+/// Body format of push requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LokiEncoding {
+    // snappy compressed protobuf, as Promtail sends
+    Protobuf,
+    Json,
+}
+
+impl FromStr for LokiEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "protobuf" => Ok(LokiEncoding::Protobuf),
+            "json" => Ok(LokiEncoding::Json),
+            _ => Err(format!("Invalid Loki encoding: {}; expected protobuf or json", s)),
+        }
+    }
+}
+
+impl fmt::Display for LokiEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LokiEncoding::Protobuf => write!(f, "protobuf"),
+            LokiEncoding::Json => write!(f, "json"),
+        }
+    }
+}
+
+
+// logproto.PushRequest and the messages it contains, from Loki's push.proto
+#[derive(Clone, PartialEq, Message)]
+struct PushRequest {
+    #[prost(message, repeated, tag = "1")]
+    streams: Vec<StreamAdapter>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct StreamAdapter {
+    #[prost(string, tag = "1")]
+    labels: String,
+    #[prost(message, repeated, tag = "2")]
+    entries: Vec<EntryAdapter>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct EntryAdapter {
+    #[prost(message, optional, tag = "1")]
+    timestamp: Option<prost_types::Timestamp>,
+    #[prost(string, tag = "2")]
+    line: String,
+}
+
+
+/// A stream's labels, by name
+type Labels = BTreeMap<String, String>;
+
+
+/// Replaces characters Loki doesn't allow in label names with `_`
+fn label_name(name: &str) -> String {
+    name
+        .chars()
+        .enumerate()
+        .map(|(index, c)| match c {
+            'a'..='z' | 'A'..='Z' | '_' => c,
+            '0'..='9' if index > 0 => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Labels in the `{name="value", ...}` form used by the protobuf encoding
+fn label_string(labels: &Labels) -> String {
+    let pairs = labels
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, Value::String(value.clone())))
+        .collect::<Vec<_>>();
+
+    format!("{{{}}}", pairs.join(", "))
+}
+
+fn nanos(message: &LogMessage) -> String {
+    format!("{}{:09}", message.timestamp.timestamp(), message.timestamp.timestamp_subsec_nanos())
+}
+
+
+/// Pushes messages to Grafana Loki.  Messages are grouped into streams by the
+/// labels in `loki-labels`, each taken from the message's context (e.g.
+/// `container_name` or `source`) or the container's Docker labels.
+pub struct LokiClient {
+    // as configured; used in logs and metrics
+    url: String,
+    push_url: String,
+    client: reqwest::Client,
+    headers: HeaderMap,
+    encoding: LokiEncoding,
+    compression: Compression,
+    compression_min_size: usize,
+    labels: Vec<String>,
+    container_labels: HashMap<String, String>,
+    container_name: String,
+}
+
+impl LokiClient {
+    /// Groups messages by their labels, keeping each stream in timestamp order
+    fn streams<'a>(&self, messages: &'a [LogMessage]) -> BTreeMap<Labels, Vec<&'a LogMessage>> {
+        let mut streams = BTreeMap::<Labels, Vec<&LogMessage>>::new();
+
+        for message in messages {
+            let mut labels = self.labels
+                .iter()
+                .filter_map(|name| Some((label_name(name), super::metadata(message, &self.container_labels, name)?)))
+                .collect::<Labels>();
+
+            // Loki needs at least one label per stream
+            if labels.is_empty() {
+                labels.insert("container_name".to_string(), self.container_name.clone());
+            }
+
+            streams
+                .entry(labels)
+                .or_default()
+                .push(message);
+        }
+
+        for entries in streams.values_mut() {
+            entries.sort_by_key(|message| message.timestamp);
+        }
+
+        streams
+    }
+
+    fn encode_json(streams: &BTreeMap<Labels, Vec<&LogMessage>>) -> Result<Vec<u8>, serde_json::Error> {
+        let streams = streams
+            .iter()
+            .map(|(labels, entries)| json!({
+                "stream": labels,
+                "values": entries
+                    .iter()
+                    .map(|message| json!([nanos(message), message.message]))
+                    .collect::<Vec<_>>(),
+            }))
+            .collect::<Vec<_>>();
+
+        serde_json::to_vec(&json!({ "streams": streams }))
+    }
+
+    fn encode_protobuf(streams: &BTreeMap<Labels, Vec<&LogMessage>>) -> Vec<u8> {
+        let request = PushRequest {
+            streams: streams
+                .iter()
+                .map(|(labels, entries)| StreamAdapter {
+                    labels: label_string(labels),
+                    entries: entries
+                        .iter()
+                        .map(|message| EntryAdapter {
+                            timestamp: Some(prost_types::Timestamp {
+                                seconds: message.timestamp.timestamp(),
+                                nanos: message.timestamp.timestamp_subsec_nanos() as i32,
+                            }),
+                            line: message.message.clone(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        };
+
+        request.encode_to_vec()
+    }
+
+    /// Returns the request body, its Content-Type and its Content-Encoding
+    fn body(&self, messages: &[LogMessage]) -> Result<(Vec<u8>, &'static str, Option<&'static str>), std::io::Error> {
+        let streams = self.streams(messages);
+
+        match self.encoding {
+            LokiEncoding::Protobuf => Ok((snappy::compress(&Self::encode_protobuf(&streams)), "application/x-protobuf", None)),
+            LokiEncoding::Json => {
+                let (body, encoding) = self.compression.encode(Self::encode_json(&streams)?, self.compression_min_size)?;
+
+                Ok((body, "application/json", encoding))
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Ingest for LokiClient {
+    fn new(config: &Config, info: &ContainerInfo, clients: &HttpClients) -> Result<Self, BoxedError> {
+        let options = ClientOptions::from(config);
+        let mut headers = auth::headers(config)?;
+
+        if let Some(tenant) = config.loki_tenant.as_deref() {
+            headers.insert(TENANT_HEADER, HeaderValue::from_str(tenant).map_err(|_| "Invalid loki-tenant")?);
+        }
+
+        Ok(Self {
+            url: config.loki_url.clone(),
+            push_url: format!("{}{}", options.tls.url(&config.loki_url).trim_end_matches('/'), PUSH_PATH),
+            client: clients.get(&config.loki_url, &options)?,
+            headers,
+            encoding: config.loki_encoding,
+            compression: config.compression,
+            compression_min_size: config.compression_min_size.0 as usize,
+            labels: config
+                .loki_labels
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect(),
+            container_labels: info.container_labels.clone(),
+            container_name: info.name().to_string(),
+        })
+    }
+
+    async fn ingest(&mut self, messages: &[LogMessage]) -> Result<IngestResponse, IngestError> {
+        let (body, content_type, encoding) = self.body(messages)
+            .map_err(IngestError::Encode)?;
+
+        let mut request = self.client
+            .post(&self.push_url)
+            .headers(self.headers.clone())
+            .header(CONTENT_TYPE, content_type)
+            .body(body);
+
+        if let Some(encoding) = encoding {
+            request = request.header(CONTENT_ENCODING, encoding);
+        }
+
+        let results = match request.send().await {
+            Ok(response) => client::check_status(response).await,
+            Err(err) => Err(IngestError::Request(err)),
+        };
+
+        match results {
+            Ok(_) => {
+                metrics::record_batch(&self.url, messages.len());
+
+                debug!(
+                    endpoint = %metrics::label(&self.url),
+                    count = messages.len(),
+                    "Pushed log messages to Loki",
+                );
+
+                Ok(IngestResponse::default())
+            },
+            Err(err) => {
+                metrics::record_failure(&self.url);
+                Err(err)
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        Mutex,
+    };
+
+    use envconfig::Envconfig;
+
+    use super::*;
+
+    type Requests = Arc<Mutex<Vec<(HeaderMap, Vec<u8>)>>>;
+
+    // serves the push API, recording each request's headers and body
+    fn serve() -> (String, Requests) {
+        let requests = Requests::default();
+        let recorded = requests.clone();
+        let app = axum::Router::new()
+            .route(PUSH_PATH, axum::routing::post(move |headers: HeaderMap, body: axum::body::Bytes| {
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((headers, body.to_vec()));
+
+                async { reqwest::StatusCode::NO_CONTENT }
+            }));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service())
+        );
+
+        (url, requests)
+    }
+
+    fn client(pairs: &[(&str, &str)]) -> LokiClient {
+        let config = Config::init_from_hashmap(
+            &pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        ).unwrap();
+        let info = ContainerInfo {
+            container_id: "abc123".to_string(),
+            container_name: "/web".to_string(),
+            container_labels: HashMap::from([("com.docker.compose.service".to_string(), "frontend".to_string())]),
+            ..Default::default()
+        };
+
+        LokiClient::new(&config, &info, &HttpClients::default())
+            .unwrap()
+    }
+
+    fn message(text: &str, source: &str, seconds: i64) -> LogMessage {
+        LogMessage {
+            timestamp: chrono::TimeZone::timestamp_opt(&chrono::Utc, seconds, 5).unwrap(),
+            message: text.to_string(),
+            level: 3,
+            context: Some(json!({
+                "container_name": "web",
+                "source": source,
+            })),
+        }
+    }
+
+    #[test]
+    fn test_streams() {
+        let client = client(&[("LOKI_LABELS", "container_name, source, com.docker.compose.service, missing")]);
+        let messages = vec![
+            message("out 2", "stdout", 2),
+            message("err", "stderr", 1),
+            message("out 1", "stdout", 1),
+        ];
+
+        let streams = client.streams(&messages);
+        let labels = streams
+            .keys()
+            .map(label_string)
+            .collect::<Vec<_>>();
+
+        assert_eq!(labels, vec![
+            r#"{com_docker_compose_service="frontend", container_name="web", source="stderr"}"#,
+            r#"{com_docker_compose_service="frontend", container_name="web", source="stdout"}"#,
+        ]);
+
+        let stdout = streams
+            .values()
+            .nth(1)
+            .unwrap()
+            .iter()
+            .map(|message| message.message.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(stdout, vec!["out 1", "out 2"]);
+
+        // without any of the labels, streams fall back to the container's name
+        let client = self::client(&[("LOKI_LABELS", "missing")]);
+        let labels = client
+            .streams(&messages)
+            .into_keys()
+            .collect::<Vec<_>>();
+
+        assert_eq!(labels, vec![Labels::from([("container_name".to_string(), "web".to_string())])]);
+    }
+
+    #[test]
+    fn test_encode_protobuf() {
+        let client = client(&[]);
+        let messages = vec![message("hello \"loki\"", "stdout", 1)];
+
+        let request = PushRequest::decode(&LokiClient::encode_protobuf(&client.streams(&messages))[..])
+            .unwrap();
+
+        assert_eq!(request.streams.len(), 1);
+        assert_eq!(request.streams[0].labels, r#"{container_name="web", source="stdout"}"#);
+        assert_eq!(request.streams[0].entries[0].line, "hello \"loki\"");
+        assert_eq!(request.streams[0].entries[0].timestamp, Some(prost_types::Timestamp { seconds: 1, nanos: 5 }));
+    }
+
+    #[tokio::test]
+    async fn test_push_json() {
+        let (url, requests) = serve();
+        let mut client = client(&[
+            ("LOKI_URL", &url),
+            ("LOKI_ENCODING", "json"),
+            ("LOKI_TENANT", "team-a"),
+        ]);
+
+        client.ingest(&[message("hello", "stdout", 1)])
+            .await
+            .unwrap();
+
+        let requests = requests
+            .lock()
+            .unwrap();
+        let (headers, body) = &requests[0];
+
+        assert_eq!(headers[TENANT_HEADER], "team-a");
+        assert_eq!(headers[CONTENT_TYPE], "application/json");
+        assert_eq!(serde_json::from_slice::<Value>(body).unwrap(), json!({
+            "streams": [{
+                "stream": {"container_name": "web", "source": "stdout"},
+                "values": [["1000000005", "hello"]],
+            }],
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_push_protobuf() {
+        let (url, requests) = serve();
+        let mut client = client(&[("LOKI_URL", &url)]);
+
+        client.ingest(&[message("hello", "stdout", 1)])
+            .await
+            .unwrap();
+
+        let requests = requests
+            .lock()
+            .unwrap();
+        let (headers, body) = &requests[0];
+
+        assert_eq!(headers[CONTENT_TYPE], "application/x-protobuf");
+        assert!(headers.get(TENANT_HEADER).is_none());
+        assert!(!body.is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+};
+
+use async_trait::async_trait;
+use serde_json::Value;
+
+use crate::{
+    client::{
+        HttpClients,
+        Ingest,
+        IngestClient,
+        IngestError,
+        IngestResponse,
+    },
+    config::Config,
+    container::ContainerInfo,
+    error::BoxedError,
+    log::LogMessage,
+};
+
+mod elasticsearch;
+mod file;
+mod gelf;
+mod loki;
+mod splunk;
+mod syslog;
+
+pub use elasticsearch::ElasticsearchClient;
+pub use file::FileSink;
+pub use gelf::{
+    GelfAddress,
+    GelfClient,
+};
+pub use loki::{
+    LokiClient,
+    LokiEncoding,
+};
+pub use splunk::SplunkClient;
+pub use syslog::{
+    SyslogAddress,
+    SyslogClient,
+    SyslogFacility,
+    SyslogFormat,
+    SyslogFraming,
+    SyslogSeverities,
+};
+
+
+/// The kinds of destination a container's messages can be sent to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkKind {
+    Ingest,
+    File,
+    Loki,
+    Elasticsearch,
+    Splunk,
+    Gelf,
+    Syslog,
+}
+
+impl FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "ingest" => Ok(SinkKind::Ingest),
+            "file" => Ok(SinkKind::File),
+            "loki" => Ok(SinkKind::Loki),
+            "elasticsearch" => Ok(SinkKind::Elasticsearch),
+            "splunk" => Ok(SinkKind::Splunk),
+            "gelf" => Ok(SinkKind::Gelf),
+            "syslog" => Ok(SinkKind::Syslog),
+            _ => Err(format!("Invalid sink: {}; expected ingest, file, loki, elasticsearch, splunk, gelf or syslog", s)),
+        }
+    }
+}
+
+impl fmt::Display for SinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SinkKind::Ingest => write!(f, "ingest"),
+            SinkKind::File => write!(f, "file"),
+            SinkKind::Loki => write!(f, "loki"),
+            SinkKind::Elasticsearch => write!(f, "elasticsearch"),
+            SinkKind::Splunk => write!(f, "splunk"),
+            SinkKind::Gelf => write!(f, "gelf"),
+            SinkKind::Syslog => write!(f, "syslog"),
+        }
+    }
+}
+
+
+/// Looks up a field used to label or route a message: `level`, a field of its context
+/// (e.g. `container_name` or `source`), or one of the container's Docker labels.
+/// `compose_<name>` is short for the `com.docker.compose.<name>` label.
+fn metadata(message: &LogMessage, container_labels: &HashMap<String, String>, name: &str) -> Option<String> {
+    if name == "level" {
+        return Some(message.level.to_string());
+    }
+
+    let value = message.context
+        .as_ref()
+        .and_then(|context| context.get(name));
+
+    match value {
+        Some(Value::String(value)) => return Some(value.clone()),
+        Some(Value::Number(value)) => return Some(value.to_string()),
+        Some(Value::Bool(value)) => return Some(value.to_string()),
+        _ => (),
+    }
+
+    let compose = name
+        .strip_prefix("compose_")
+        .map(|name| format!("com.docker.compose.{}", name));
+
+    container_labels
+        .get(name)
+        .or_else(|| container_labels.get(compose.as_deref()?))
+        .cloned()
+}
+
+
+/// Maps a message's level, from 1 (trace) to 6 (fatal), to the syslog severity GELF and
+/// syslog sinks use by default
+fn severity(level: i32) -> u8 {
+    match level {
+        i32::MIN..=2 => 7,
+        3 => 6,
+        4 => 4,
+        5 => 3,
+        6 => 2,
+        _ => 1,
+    }
+}
+
+
+/// A container's client for one of its sinks, of the kind its configuration's `sink`
+/// setting names
+pub enum Sink {
+    Ingest(IngestClient),
+    File(FileSink),
+    Loki(LokiClient),
+    Elasticsearch(ElasticsearchClient),
+    Splunk(SplunkClient),
+    Gelf(GelfClient),
+    Syslog(SyslogClient),
+}
+
+#[async_trait]
+impl Ingest for Sink {
+    fn new(config: &Config, info: &ContainerInfo, clients: &HttpClients) -> Result<Self, BoxedError> {
+        match config.sink {
+            SinkKind::Ingest => Ok(Sink::Ingest(IngestClient::new(config, info, clients)?)),
+            SinkKind::File => Ok(Sink::File(FileSink::new(config, info, clients)?)),
+            SinkKind::Loki => Ok(Sink::Loki(LokiClient::new(config, info, clients)?)),
+            SinkKind::Elasticsearch => Ok(Sink::Elasticsearch(ElasticsearchClient::new(config, info, clients)?)),
+            SinkKind::Splunk => Ok(Sink::Splunk(SplunkClient::new(config, info, clients)?)),
+            SinkKind::Gelf => Ok(Sink::Gelf(GelfClient::new(config, info, clients)?)),
+            SinkKind::Syslog => Ok(Sink::Syslog(SyslogClient::new(config, info, clients)?)),
+        }
+    }
+
+    async fn ingest(&mut self, messages: &[LogMessage]) -> Result<IngestResponse, IngestError> {
+        match self {
+            Sink::Ingest(client) => client.ingest(messages).await,
+            Sink::File(file) => file.ingest(messages).await,
+            Sink::Loki(loki) => loki.ingest(messages).await,
+            Sink::Elasticsearch(elasticsearch) => elasticsearch.ingest(messages).await,
+            Sink::Splunk(splunk) => splunk.ingest(messages).await,
+            Sink::Gelf(gelf) => gelf.ingest(messages).await,
+            Sink::Syslog(syslog) => syslog.ingest(messages).await,
+        }
+    }
+}
@@ -0,0 +1,468 @@
+use std::{
+    collections::HashMap,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use reqwest::header::{
+    HeaderMap,
+    AUTHORIZATION,
+    CONTENT_ENCODING,
+    CONTENT_TYPE,
+};
+use serde::Deserialize;
+use serde_json::{
+    json,
+    Map,
+    Value,
+};
+use tokio::time::Instant;
+use tracing::{
+    debug,
+    warn,
+};
+
+use crate::{
+    auth,
+    client::{
+        self,
+        ClientOptions,
+        HttpClients,
+        Ingest,
+        IngestError,
+        IngestResponse,
+    },
+    compression::Compression,
+    config::Config,
+    container::ContainerInfo,
+    error::BoxedError,
+    log::LogMessage,
+    metrics,
+};
+
+
+const EVENT_PATH: &str = "/services/collector/event";
+
+const ACK_PATH: &str = "/services/collector/ack";
+
+const CHANNEL_HEADER: &str = "X-Splunk-Request-Channel";
+
+
+#[derive(Debug, Deserialize)]
+struct EventResponse {
+    #[serde(default, rename = "ackId")]
+    ack_id: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AckResponse {
+    #[serde(default)]
+    acks: HashMap<String, bool>,
+}
+
+
+/// How indexer acknowledgement is waited for
+#[derive(Debug, Clone, Copy)]
+struct AckOptions {
+    interval: Duration,
+    timeout: Duration,
+}
+
+
+/// A random version 4 UUID, identifying the channel acknowledgements are tracked on
+fn channel_id() -> String {
+    let bytes = (0..16)
+        .map(|index| match index {
+            6 => 0x40 | (fastrand::u8(..) & 0x0f),
+            8 => 0x80 | (fastrand::u8(..) & 0x3f),
+            _ => fastrand::u8(..),
+        })
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    format!("{}-{}-{}-{}-{}", &bytes[..8], &bytes[8..12], &bytes[12..16], &bytes[16..20], &bytes[20..])
+}
+
+/// HEC only indexes fields whose values are strings, or arrays of strings
+fn field_value(value: &Value) -> Value {
+    match value {
+        Value::String(_) => value.clone(),
+        Value::Array(values) if values.iter().all(Value::is_string) => value.clone(),
+        _ => Value::String(value.to_string()),
+    }
+}
+
+
+/// Sends messages to a Splunk HTTP Event Collector.
+///
+/// Each message becomes an event whose `event` is the message, `time` its timestamp,
+/// `source` its stream, and whose `fields` are its level and context.  With indexer
+/// acknowledgement, a batch only succeeds once Splunk confirms it has been indexed.
+pub struct SplunkClient {
+    // as configured; used in logs and metrics
+    url: String,
+    event_url: String,
+    ack_url: String,
+    client: reqwest::Client,
+    headers: HeaderMap,
+    compression: Compression,
+    compression_min_size: usize,
+    host: String,
+    index: Option<String>,
+    sourcetype: Option<String>,
+    ack: Option<AckOptions>,
+}
+
+impl SplunkClient {
+    fn event(&self, message: &LogMessage) -> Value {
+        let mut fields = Map::new();
+        let mut source = None;
+
+        if let Some(Value::Object(context)) = message.context.as_ref() {
+            for (key, value) in context {
+                match key.as_str() {
+                    "source" => source = value.as_str(),
+                    _ => {
+                        fields.insert(key.clone(), field_value(value));
+                    },
+                }
+            }
+        }
+
+        fields.insert("level".to_string(), Value::String(message.level.to_string()));
+
+        let mut event = json!({
+            "time": message.timestamp.timestamp_millis() as f64 / 1000.0,
+            "host": self.host,
+            "event": message.message,
+            "fields": fields,
+        });
+
+        let metadata = [
+            ("source", source),
+            ("index", self.index.as_deref()),
+            ("sourcetype", self.sourcetype.as_deref()),
+        ];
+
+        for (key, value) in metadata {
+            if let Some(value) = value {
+                event[key] = Value::String(value.to_string());
+            }
+        }
+
+        event
+    }
+
+    /// Events are sent concatenated, rather than as a JSON array
+    fn encode(&self, messages: &[LogMessage]) -> Result<Vec<u8>, serde_json::Error> {
+        let mut body = Vec::new();
+
+        for message in messages {
+            serde_json::to_writer(&mut body, &self.event(message))?;
+            body.push(b'\n');
+        }
+
+        Ok(body)
+    }
+
+    /// Waits until Splunk acknowledges that a batch has been indexed
+    async fn wait_for_ack(&self, ack_id: u64, options: AckOptions) -> Result<(), IngestError> {
+        let deadline = Instant::now() + options.timeout;
+        let body = json!({ "acks": [ack_id] });
+
+        while Instant::now() < deadline {
+            tokio::time::sleep(options.interval)
+                .await;
+
+            let response = self.client
+                .post(&self.ack_url)
+                .headers(self.headers.clone())
+                .json(&body)
+                .send()
+                .await;
+
+            let results = match response {
+                Ok(response) => match client::check_status(response).await {
+                    Ok(response) => response
+                        .json::<AckResponse>()
+                        .await
+                        .map_err(IngestError::Response),
+                    Err(err) => Err(err),
+                },
+                Err(err) => Err(IngestError::Request(err)),
+            };
+
+            match results {
+                Ok(response) if response.acks.get(&ack_id.to_string()) == Some(&true) => return Ok(()),
+                Ok(_) => (),
+                Err(err) => warn!(
+                    error = %err,
+                    ack_id = ack_id,
+                    "Error checking Splunk indexer acknowledgement",
+                ),
+            }
+        }
+
+        Err(IngestError::Unacknowledged(ack_id))
+    }
+}
+
+#[async_trait]
+impl Ingest for SplunkClient {
+    fn new(config: &Config, info: &ContainerInfo, clients: &HttpClients) -> Result<Self, BoxedError> {
+        let options = ClientOptions::from(config);
+        let uri = options.tls
+            .url(&config.splunk_url)
+            .trim_end_matches('/')
+            .to_string();
+        let mut headers = auth::headers(config)?;
+
+        let token = auth::resolve_secret(&config.splunk_token, &config.splunk_token_file, "splunk-token")?
+            .ok_or("splunk-token or splunk-token-file is required")?;
+
+        auth::insert_header(&mut headers, AUTHORIZATION.as_str(), &format!("Splunk {}", token))?;
+
+        if config.splunk_ack {
+            auth::insert_header(&mut headers, CHANNEL_HEADER, &channel_id())?;
+        }
+
+        Ok(Self {
+            url: config.splunk_url.clone(),
+            event_url: format!("{}{}", uri, EVENT_PATH),
+            ack_url: format!("{}{}", uri, ACK_PATH),
+            client: clients.get(&config.splunk_url, &options)?,
+            headers,
+            compression: config.compression,
+            compression_min_size: config.compression_min_size.0 as usize,
+            host: config.splunk_host
+                .clone()
+                .unwrap_or_else(|| info.name().to_string()),
+            index: config.splunk_index.clone(),
+            sourcetype: config.splunk_sourcetype.clone(),
+            ack: config.splunk_ack.then_some(AckOptions {
+                interval: config.splunk_ack_interval.0,
+                timeout: config.splunk_ack_timeout.0,
+            }),
+        })
+    }
+
+    async fn ingest(&mut self, messages: &[LogMessage]) -> Result<IngestResponse, IngestError> {
+        let body = self.encode(messages)
+            .map_err(|e| IngestError::Encode(e.into()))?;
+        let (body, encoding) = self.compression
+            .encode(body, self.compression_min_size)
+            .map_err(IngestError::Encode)?;
+
+        let mut request = self.client
+            .post(&self.event_url)
+            .headers(self.headers.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+
+        if let Some(encoding) = encoding {
+            request = request.header(CONTENT_ENCODING, encoding);
+        }
+
+        let results = match request.send().await {
+            Ok(response) => client::check_status(response).await,
+            Err(err) => Err(IngestError::Request(err)),
+        };
+
+        let response = match results {
+            Ok(response) => response,
+            Err(err) => {
+                metrics::record_failure(&self.url);
+                return Err(err);
+            },
+        };
+
+        if let Some(options) = self.ack {
+            let body = response
+                .bytes()
+                .await
+                .map_err(IngestError::Response)?;
+            let ack_id = serde_json::from_slice::<EventResponse>(&body)
+                .map_err(IngestError::Decode)?
+                .ack_id
+                .ok_or_else(|| IngestError::Decode(serde::de::Error::custom("no ackId; is indexer acknowledgement enabled for the token?")))?;
+
+            if let Err(err) = self.wait_for_ack(ack_id, options).await {
+                metrics::record_failure(&self.url);
+                return Err(err);
+            }
+        }
+
+        metrics::record_batch(&self.url, messages.len());
+
+        debug!(
+            endpoint = %metrics::label(&self.url),
+            count = messages.len(),
+            "Sent log messages to Splunk",
+        );
+
+        Ok(IngestResponse::default())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    };
+
+    use envconfig::Envconfig;
+
+    use super::*;
+
+    type Requests = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+    // serves HEC, acknowledging each batch on the `acked_after`th check
+    fn serve(acked_after: usize) -> (String, Requests) {
+        let requests = Requests::default();
+        let recorded = requests.clone();
+        let checks = Arc::new(AtomicUsize::new(0));
+        let app = axum::Router::new()
+            .route(EVENT_PATH, axum::routing::post(move |headers: HeaderMap, body: String| {
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((headers, body));
+
+                async { axum::Json(json!({ "text": "Success", "code": 0, "ackId": 7 })) }
+            }))
+            .route(ACK_PATH, axum::routing::post(move |axum::Json(body): axum::Json<Value>| {
+                let acked = checks.fetch_add(1, Ordering::SeqCst) + 1 >= acked_after;
+                let acks = Map::from_iter([(body["acks"][0].to_string(), Value::Bool(acked))]);
+
+                async move { axum::Json(json!({ "acks": acks })) }
+            }));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service())
+        );
+
+        (url, requests)
+    }
+
+    fn client(pairs: &[(&str, &str)]) -> SplunkClient {
+        let mut env = HashMap::from([
+            ("SPLUNK_TOKEN".to_string(), "token".to_string()),
+            ("SPLUNK_ACK_INTERVAL".to_string(), "1ms".to_string()),
+            ("SPLUNK_ACK_TIMEOUT".to_string(), "200ms".to_string()),
+        ]);
+
+        env.extend(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+
+        let config = Config::init_from_hashmap(&env)
+            .unwrap();
+        let info = ContainerInfo {
+            container_name: "/web".to_string(),
+            ..Default::default()
+        };
+
+        SplunkClient::new(&config, &info, &HttpClients::default())
+            .unwrap()
+    }
+
+    fn message() -> LogMessage {
+        LogMessage {
+            timestamp: chrono::TimeZone::timestamp_millis_opt(&chrono::Utc, 1_672_628_645_250).unwrap(),
+            message: "hello".to_string(),
+            level: 3,
+            context: Some(json!({
+                "container_name": "web",
+                "source": "stderr",
+                "attempt": 2,
+                "tags": ["a", "b"],
+            })),
+        }
+    }
+
+    #[test]
+    fn test_event() {
+        let client = client(&[("SPLUNK_INDEX", "containers"), ("SPLUNK_SOURCETYPE", "docker")]);
+
+        assert_eq!(client.event(&message()), json!({
+            "time": 1_672_628_645.25,
+            "host": "web",
+            "source": "stderr",
+            "index": "containers",
+            "sourcetype": "docker",
+            "event": "hello",
+            "fields": {
+                "container_name": "web",
+                "attempt": "2",
+                "tags": ["a", "b"],
+                "level": "3",
+            },
+        }));
+
+        let client = self::client(&[("SPLUNK_HOST", "docker-01")]);
+        let event = client.event(&message());
+
+        assert_eq!(event["host"], "docker-01");
+        assert!(event.get("index").is_none());
+    }
+
+    #[test]
+    fn test_token_required() {
+        let config = Config::init_from_hashmap(&HashMap::new())
+            .unwrap();
+
+        assert!(SplunkClient::new(&config, &ContainerInfo::default(), &HttpClients::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ingest() {
+        let (url, requests) = serve(1);
+        let mut client = client(&[("SPLUNK_URL", url.as_str())]);
+
+        client.ingest(&[message(), message()])
+            .await
+            .unwrap();
+
+        let requests = requests
+            .lock()
+            .unwrap();
+        let (headers, body) = &requests[0];
+
+        assert_eq!(headers[AUTHORIZATION], "Splunk token");
+        assert!(headers.get(CHANNEL_HEADER).is_none());
+        assert_eq!(body.lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_indexer_acknowledgement() {
+        let (url, requests) = serve(3);
+        let mut client = client(&[("SPLUNK_URL", url.as_str()), ("SPLUNK_ACK", "true")]);
+
+        client.ingest(&[message()])
+            .await
+            .unwrap();
+
+        assert_eq!(requests.lock().unwrap()[0].0[CHANNEL_HEADER].len(), 36);
+
+        // never acknowledged in time
+        let (url, _) = serve(usize::MAX);
+        let mut client = self::client(&[("SPLUNK_URL", url.as_str()), ("SPLUNK_ACK", "true")]);
+
+        let err = client.ingest(&[message()])
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, IngestError::Unacknowledged(7)));
+        assert!(err.is_retryable());
+    }
+}
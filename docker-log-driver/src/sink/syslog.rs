@@ -0,0 +1,735 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    str::FromStr,
+};
+
+use async_trait::async_trait;
+use chrono::SecondsFormat;
+use serde_json::Value;
+use tokio::{
+    io::{
+        AsyncWrite,
+        AsyncWriteExt,
+    },
+    net::{
+        TcpStream,
+        UdpSocket,
+        UnixDatagram,
+    },
+};
+use tokio_native_tls::TlsConnector;
+use tracing::debug;
+
+use crate::{
+    client::{
+        HttpClients,
+        Ingest,
+        IngestError,
+        IngestResponse,
+        Rejection,
+    },
+    config::Config,
+    container::ContainerInfo,
+    error::BoxedError,
+    log::LogMessage,
+    metrics,
+    tls::TlsOptions,
+};
+
+
+// SD-ID of the structured data element holding a message's context; 32473 is the
+// private enterprise number reserved for documentation (RFC 5612)
+const SD_ID: &str = "docker@32473";
+
+// Largest payload of a UDP datagram
+const MAX_DATAGRAM: usize = 65507;
+
+const SEVERITIES: [&str; 8] = ["emerg", "alert", "crit", "err", "warning", "notice", "info", "debug"];
+
+const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv", "ftp",
+    "ntp", "security", "console", "solaris-cron", "local0", "local1", "local2", "local3", "local4", "local5",
+    "local6", "local7",
+];
+
+
+/// Transport syslog messages are sent over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyslogTransport {
+    Udp,
+    Tcp,
+    Tls,
+    Unix,
+}
+
+
+/// Where a syslog sink sends to, e.g. `udp://siem:514`, `tls://siem:6514` or
+/// `unix:///dev/log`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyslogAddress {
+    pub transport: SyslogTransport,
+    // host and port, or the socket's path
+    pub address: String,
+}
+
+impl SyslogAddress {
+    /// The host, without its port or IPv6 brackets
+    fn host(&self) -> &str {
+        self.address
+            .rsplit_once(':')
+            .map_or(self.address.as_str(), |(host, _)| host)
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+    }
+}
+
+impl FromStr for SyslogAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, address) = s
+            .trim()
+            .split_once("://")
+            .ok_or_else(|| format!("Invalid address: {}; expected udp://, tcp://, tls:// or unix://", s))?;
+
+        let (transport, default_port) = match scheme {
+            "udp" => (SyslogTransport::Udp, 514),
+            "tcp" => (SyslogTransport::Tcp, 514),
+            "tls" => (SyslogTransport::Tls, 6514),
+            "unix" if address.starts_with('/') => return Ok(Self {
+                transport: SyslogTransport::Unix,
+                address: address.to_string(),
+            }),
+            "unix" => return Err(format!("Invalid socket path: {}; expected an absolute path", address)),
+            _ => return Err(format!("Invalid transport: {}; expected udp, tcp, tls or unix", scheme)),
+        };
+
+        let address = match address.rsplit_once(':') {
+            Some((_, port)) if port.parse::<u16>().is_ok() => address.to_string(),
+            _ => format!("{}:{}", address, default_port),
+        };
+
+        let address = Self { transport, address };
+
+        match address.host().is_empty() {
+            true => Err(format!("Invalid address: {}; missing host", s)),
+            false => Ok(address),
+        }
+    }
+}
+
+impl fmt::Display for SyslogAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.transport {
+            SyslogTransport::Udp => write!(f, "udp://{}", self.address),
+            SyslogTransport::Tcp => write!(f, "tcp://{}", self.address),
+            SyslogTransport::Tls => write!(f, "tls://{}", self.address),
+            SyslogTransport::Unix => write!(f, "unix://{}", self.address),
+        }
+    }
+}
+
+
+/// Message format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyslogFormat {
+    Rfc5424,
+    Rfc3164,
+}
+
+impl FromStr for SyslogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "rfc5424" => Ok(SyslogFormat::Rfc5424),
+            "rfc3164" => Ok(SyslogFormat::Rfc3164),
+            _ => Err(format!("Invalid syslog format: {}; expected rfc5424 or rfc3164", s)),
+        }
+    }
+}
+
+impl fmt::Display for SyslogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyslogFormat::Rfc5424 => write!(f, "rfc5424"),
+            SyslogFormat::Rfc3164 => write!(f, "rfc3164"),
+        }
+    }
+}
+
+
+/// How messages are delimited over TCP and TLS (RFC 6587)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyslogFraming {
+    OctetCounting,
+    Newline,
+}
+
+impl FromStr for SyslogFraming {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "octet-counting" => Ok(SyslogFraming::OctetCounting),
+            "newline" => Ok(SyslogFraming::Newline),
+            _ => Err(format!("Invalid syslog framing: {}; expected octet-counting or newline", s)),
+        }
+    }
+}
+
+impl fmt::Display for SyslogFraming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyslogFraming::OctetCounting => write!(f, "octet-counting"),
+            SyslogFraming::Newline => write!(f, "newline"),
+        }
+    }
+}
+
+
+/// A facility, by name (e.g. `local0`) or code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyslogFacility(pub u8);
+
+impl FromStr for SyslogFacility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        FACILITIES
+            .iter()
+            .position(|name| *name == s)
+            .or_else(|| s.parse::<usize>().ok().filter(|code| *code < FACILITIES.len()))
+            .map(|code| SyslogFacility(code as u8))
+            .ok_or_else(|| format!("Invalid facility: {}; expected e.g. user, daemon or local0-local7", s))
+    }
+}
+
+impl fmt::Display for SyslogFacility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", FACILITIES[self.0 as usize])
+    }
+}
+
+
+fn parse_severity(s: &str) -> Result<u8, String> {
+    let s = s.trim();
+
+    SEVERITIES
+        .iter()
+        .position(|name| *name == s)
+        .or_else(|| s.parse::<usize>().ok().filter(|code| *code < SEVERITIES.len()))
+        .map(|code| code as u8)
+        .ok_or_else(|| format!("Invalid severity: {}; expected e.g. err, warning or info", s))
+}
+
+
+/// Overrides of the severity a message's level maps to, e.g. `3=notice,5=crit`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyslogSeverities(pub BTreeMap<i32, u8>);
+
+impl SyslogSeverities {
+    pub fn severity(&self, level: i32) -> u8 {
+        self.0
+            .get(&level)
+            .copied()
+            .unwrap_or_else(|| super::severity(level))
+    }
+}
+
+impl FromStr for SyslogSeverities {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s
+            .split(',')
+            .map(|pair| pair.trim())
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (level, severity) = pair
+                    .split_once('=')
+                    .ok_or_else(|| format!("Invalid severity mapping: {}; expected level=severity", pair))?;
+                let level = level
+                    .trim()
+                    .parse::<i32>()
+                    .map_err(|_| format!("Invalid level: {}", level))?;
+
+                Ok((level, parse_severity(severity)?))
+            })
+            .collect::<Result<BTreeMap<_, _>, String>>()
+            .map(SyslogSeverities)
+    }
+}
+
+impl fmt::Display for SyslogSeverities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pairs = self.0
+            .iter()
+            .map(|(level, severity)| format!("{}={}", level, SEVERITIES[*severity as usize]))
+            .collect::<Vec<_>>();
+
+        write!(f, "{}", pairs.join(","))
+    }
+}
+
+
+/// Header fields may only contain printable ASCII, without spaces; `-` means empty
+fn header_field(value: &str, max_len: usize) -> String {
+    let value = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max_len)
+        .collect::<String>();
+
+    match value.is_empty() {
+        true => "-".to_string(),
+        false => value,
+    }
+}
+
+/// A structured data element of the message's context, or `-` if it has none
+fn structured_data(message: &LogMessage) -> String {
+    let params = match message.context.as_ref() {
+        Some(Value::Object(context)) => context
+            .iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| {
+                let name = key
+                    .chars()
+                    .map(|c| if c.is_ascii_graphic() && !matches!(c, '=' | ']' | '"') { c } else { '_' })
+                    .take(32)
+                    .collect::<String>();
+                let value = match value {
+                    Value::String(value) => value.clone(),
+                    _ => value.to_string(),
+                };
+                let value = value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace(']', "\\]");
+
+                format!(" {}=\"{}\"", name, value)
+            })
+            .collect::<String>(),
+        _ => String::new(),
+    };
+
+    match params.is_empty() {
+        true => "-".to_string(),
+        false => format!("[{}{}]", SD_ID, params),
+    }
+}
+
+/// The host's name, as the plugin sees it
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default()
+}
+
+
+enum Connection {
+    Udp(UdpSocket),
+    Unix(UnixDatagram),
+    Stream(Box<dyn AsyncWrite + Unpin + Send + Sync>),
+}
+
+
+/// Sends messages to a syslog server, or the local syslog socket.
+///
+/// Messages are RFC 5424, with their context as structured data, or RFC 3164, sent as
+/// one datagram each over UDP and unix sockets, or framed per RFC 6587 over TCP and TLS.
+pub struct SyslogClient {
+    address: SyslogAddress,
+    connection: Option<Connection>,
+    tls: Option<(TlsConnector, String)>,
+    format: SyslogFormat,
+    framing: SyslogFraming,
+    facility: SyslogFacility,
+    severities: SyslogSeverities,
+    hostname: String,
+    app_name: String,
+}
+
+impl SyslogClient {
+    fn format(&self, message: &LogMessage) -> String {
+        let priority = self.facility.0 as u32 * 8 + self.severities.severity(message.level) as u32;
+
+        match self.format {
+            SyslogFormat::Rfc5424 => format!(
+                "<{}>1 {} {} {} - - {} {}",
+                priority,
+                message.timestamp.to_rfc3339_opts(SecondsFormat::Micros, true),
+                header_field(&self.hostname, 255),
+                header_field(&self.app_name, 48),
+                structured_data(message),
+                message.message,
+            ),
+            SyslogFormat::Rfc3164 => format!(
+                "<{}>{} {} {}: {}",
+                priority,
+                message.timestamp.format("%b %e %H:%M:%S"),
+                header_field(&self.hostname, 255),
+                header_field(&self.app_name, 32),
+                message.message,
+            ),
+        }
+    }
+
+    fn frame(&self, message: &str) -> String {
+        match self.framing {
+            SyslogFraming::OctetCounting => format!("{} {}", message.len(), message),
+            SyslogFraming::Newline => format!("{}\n", message.replace(['\r', '\n'], " ")),
+        }
+    }
+
+    async fn connect(&self) -> Result<Connection, std::io::Error> {
+        match self.address.transport {
+            SyslogTransport::Udp => {
+                let remote = tokio::net::lookup_host(&self.address.address)
+                    .await?
+                    .next()
+                    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses resolved"))?;
+                let local = if remote.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let socket = UdpSocket::bind(local)
+                    .await?;
+
+                socket.connect(remote)
+                    .await?;
+
+                Ok(Connection::Udp(socket))
+            },
+            SyslogTransport::Unix => {
+                let socket = UnixDatagram::unbound()?;
+
+                socket.connect(&self.address.address)?;
+                Ok(Connection::Unix(socket))
+            },
+            SyslogTransport::Tcp | SyslogTransport::Tls => {
+                let stream = TcpStream::connect(&self.address.address)
+                    .await?;
+
+                stream.set_nodelay(true)?;
+
+                match self.tls.as_ref() {
+                    Some((connector, domain)) => {
+                        let stream = connector.connect(domain, stream)
+                            .await
+                            .map_err(std::io::Error::other)?;
+
+                        Ok(Connection::Stream(Box::new(stream)))
+                    },
+                    None => Ok(Connection::Stream(Box::new(stream))),
+                }
+            },
+        }
+    }
+
+    async fn write(&self, connection: &mut Connection, messages: &[LogMessage]) -> Result<Vec<Rejection>, std::io::Error> {
+        let mut rejected = Vec::new();
+
+        match connection {
+            Connection::Udp(_) | Connection::Unix(_) => {
+                for (index, message) in messages.iter().enumerate() {
+                    let datagram = self.format(message);
+
+                    if datagram.len() > MAX_DATAGRAM {
+                        rejected.push(Rejection {
+                            index,
+                            reason: Some(format!("{} bytes is too large for a datagram", datagram.len())),
+                        });
+                        continue;
+                    }
+
+                    match connection {
+                        Connection::Udp(socket) => socket.send(datagram.as_bytes()).await?,
+                        Connection::Unix(socket) => socket.send(datagram.as_bytes()).await?,
+                        Connection::Stream(_) => unreachable!(),
+                    };
+                }
+            },
+            Connection::Stream(stream) => {
+                let frames = messages
+                    .iter()
+                    .map(|message| self.frame(&self.format(message)))
+                    .collect::<String>();
+
+                stream.write_all(frames.as_bytes())
+                    .await?;
+                stream.flush()
+                    .await?;
+            },
+        }
+
+        Ok(rejected)
+    }
+
+    /// Sends over the open connection, or a new one; a connection which fails is dropped,
+    /// to be reopened on the next attempt
+    async fn send(&mut self, messages: &[LogMessage]) -> Result<Vec<Rejection>, std::io::Error> {
+        let mut connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => self.connect().await?,
+        };
+
+        let rejected = self.write(&mut connection, messages)
+            .await?;
+
+        self.connection = Some(connection);
+        Ok(rejected)
+    }
+}
+
+#[async_trait]
+impl Ingest for SyslogClient {
+    fn new(config: &Config, info: &ContainerInfo, _: &HttpClients) -> Result<Self, BoxedError> {
+        let address = config.syslog_address.clone();
+
+        let tls = match address.transport {
+            SyslogTransport::Tls => {
+                let options = TlsOptions::from(config);
+                let connector = options.connector(&address.to_string())?;
+                let domain = options.server_name
+                    .unwrap_or_else(|| address.host().to_string());
+
+                Some((connector, domain))
+            },
+            _ => None,
+        };
+
+        Ok(Self {
+            address,
+            connection: None,
+            tls,
+            format: config.syslog_format,
+            framing: config.syslog_framing,
+            facility: config.syslog_facility,
+            severities: config.syslog_severities.clone(),
+            hostname: config.syslog_hostname
+                .clone()
+                .unwrap_or_else(hostname),
+            app_name: config.syslog_app_name
+                .replace("{name}", info.name())
+                .replace("{id}", info.short_id()),
+        })
+    }
+
+    async fn ingest(&mut self, messages: &[LogMessage]) -> Result<IngestResponse, IngestError> {
+        let endpoint = self.address.to_string();
+
+        match self.send(messages).await {
+            Ok(rejected) => {
+                metrics::record_batch(&endpoint, messages.len() - rejected.len());
+
+                debug!(
+                    endpoint = endpoint,
+                    count = messages.len() - rejected.len(),
+                    "Sent syslog messages",
+                );
+
+                Ok(IngestResponse {
+                    count: Some(messages.len() - rejected.len()),
+                    rejected,
+                })
+            },
+            Err(err) => {
+                metrics::record_failure(&endpoint);
+                Err(IngestError::Write(err))
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use envconfig::Envconfig;
+    use serde_json::json;
+    use tokio::{
+        io::AsyncReadExt,
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    fn client(pairs: &[(&str, &str)]) -> SyslogClient {
+        let mut env = HashMap::from([
+            ("SYSLOG_HOSTNAME".to_string(), "docker-01".to_string()),
+        ]);
+
+        env.extend(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+
+        let config = Config::init_from_hashmap(&env)
+            .unwrap();
+        let info = ContainerInfo {
+            container_id: "0123456789abcdef".to_string(),
+            container_name: "/web".to_string(),
+            ..Default::default()
+        };
+
+        SyslogClient::new(&config, &info, &HttpClients::default())
+            .unwrap()
+    }
+
+    fn message(text: &str) -> LogMessage {
+        LogMessage {
+            timestamp: chrono::TimeZone::timestamp_millis_opt(&chrono::Utc, 1_672_628_645_250).unwrap(),
+            message: text.to_string(),
+            level: 5,
+            context: Some(json!({
+                "container_name": "web",
+                "path": "C:\\logs]",
+                "retry": true,
+                "trace": null,
+            })),
+        }
+    }
+
+    #[test]
+    fn test_address() {
+        let cases = [
+            ("udp://siem", SyslogTransport::Udp, "siem:514"),
+            ("tcp://siem:1514", SyslogTransport::Tcp, "siem:1514"),
+            ("tls://[::1]", SyslogTransport::Tls, "[::1]:6514"),
+            ("unix:///dev/log", SyslogTransport::Unix, "/dev/log"),
+        ];
+
+        for (value, transport, address) in cases {
+            let parsed = value.parse::<SyslogAddress>()
+                .unwrap();
+
+            assert_eq!((parsed.transport, parsed.address.as_str()), (transport, address));
+        }
+
+        assert_eq!("tls://[::1]".parse::<SyslogAddress>().unwrap().host(), "::1");
+
+        for invalid in ["siem:514", "http://siem", "udp://:514", "unix://dev/log"] {
+            assert!(invalid.parse::<SyslogAddress>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_facility_and_severities() {
+        assert_eq!("local3".parse::<SyslogFacility>(), Ok(SyslogFacility(19)));
+        assert_eq!("3".parse::<SyslogFacility>(), Ok(SyslogFacility(3)));
+        assert!("local8".parse::<SyslogFacility>().is_err());
+        assert!("24".parse::<SyslogFacility>().is_err());
+
+        let severities = "3=notice, 5=2".parse::<SyslogSeverities>()
+            .unwrap();
+
+        assert_eq!(severities.severity(3), 5);
+        assert_eq!(severities.severity(5), 2);
+        assert_eq!(severities.severity(4), 4);
+        assert_eq!(severities.to_string(), "3=notice,5=crit");
+        assert_eq!("".parse::<SyslogSeverities>(), Ok(SyslogSeverities::default()));
+
+        for invalid in ["3", "x=info", "3=loud", "3=8"] {
+            assert!(invalid.parse::<SyslogSeverities>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let client = client(&[("SYSLOG_FACILITY", "local0"), ("SYSLOG_APP_NAME", "app-{name} {id}")]);
+
+        assert_eq!(
+            client.format(&message("hello")),
+            r#"<131>1 2023-01-02T03:04:05.250000Z docker-01 app-web0123456789ab - - [docker@32473 container_name="web" path="C:\\logs\]" retry="true"] hello"#,
+        );
+
+        let client = self::client(&[("SYSLOG_FORMAT", "rfc3164")]);
+
+        assert_eq!(client.format(&message("hello")), "<11>Jan  2 03:04:05 docker-01 web: hello");
+
+        let client = self::client(&[]);
+        let bare = LogMessage {
+            context: None,
+            ..message("hello")
+        };
+
+        assert_eq!(client.format(&bare), "<11>1 2023-01-02T03:04:05.250000Z docker-01 web - - - hello");
+    }
+
+    #[tokio::test]
+    async fn test_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let address = format!("udp://{}", server.local_addr().unwrap());
+        let mut client = client(&[("SYSLOG_ADDRESS", &address), ("SYSLOG_FORMAT", "rfc3164")]);
+
+        let response = client.ingest(&[message("hello"), message(&"x".repeat(MAX_DATAGRAM))])
+            .await
+            .unwrap();
+
+        assert_eq!(response.count, Some(1));
+        assert_eq!(response.rejected[0].index, 1);
+
+        let mut datagram = vec![0; 1024];
+        let len = server.recv(&mut datagram)
+            .await
+            .unwrap();
+
+        assert_eq!(&datagram[..len], b"<11>Jan  2 03:04:05 docker-01 web: hello");
+    }
+
+    #[tokio::test]
+    async fn test_unix() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+        let path = dir.path().join("log");
+        let server = UnixDatagram::bind(&path)
+            .unwrap();
+        let address = format!("unix://{}", path.display());
+        let mut client = client(&[("SYSLOG_ADDRESS", &address)]);
+
+        client.ingest(&[message("hello")])
+            .await
+            .unwrap();
+
+        let mut datagram = vec![0; 1024];
+        let len = server.recv(&mut datagram)
+            .await
+            .unwrap();
+
+        assert!(String::from_utf8_lossy(&datagram[..len]).ends_with("] hello"));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_framing() {
+        let cases = [
+            ("octet-counting", "42 <11>Jan  2 03:04:05 docker-01 web: one\ntwo"),
+            ("newline", "<11>Jan  2 03:04:05 docker-01 web: one two\n"),
+        ];
+
+        for (framing, expected) in cases {
+            let listener = TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap();
+            let address = format!("tcp://{}", listener.local_addr().unwrap());
+            let mut client = client(&[("SYSLOG_ADDRESS", &address), ("SYSLOG_FORMAT", "rfc3164"), ("SYSLOG_FRAMING", framing)]);
+
+            client.ingest(&[message("one\ntwo")])
+                .await
+                .unwrap();
+            drop(client);
+
+            let (mut stream, _) = listener.accept()
+                .await
+                .unwrap();
+            let mut received = String::new();
+
+            stream.read_to_string(&mut received)
+                .await
+                .unwrap();
+
+            assert_eq!(received, expected);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// Snappy block format; see format_description.txt in google/snappy.  Only the
+// compressor is needed, for Loki's protobuf push requests.
+
+// Bits of the hash table of recent 4-byte sequences
+const HASH_BITS: u32 = 14;
+
+// Copies reference at most this far back, so they fit a 2-byte offset
+const MAX_OFFSET: usize = 65535;
+
+// Longest copy a single 2-byte offset element can encode
+const MAX_COPY: usize = 64;
+
+
+fn write_varint(output: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        output.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+
+    output.push(value as u8);
+}
+
+fn write_literal(output: &mut Vec<u8>, literal: &[u8]) {
+    if literal.is_empty() {
+        return;
+    }
+
+    let n = literal.len() - 1;
+
+    match n {
+        0..=59 => output.push((n as u8) << 2),
+        _ => {
+            let bytes = n.to_le_bytes();
+            let width = (usize::BITS - n.leading_zeros()).div_ceil(8) as usize;
+
+            output.push(((59 + width) as u8) << 2);
+            output.extend_from_slice(&bytes[..width]);
+        },
+    }
+
+    output.extend_from_slice(literal);
+}
+
+fn write_copy(output: &mut Vec<u8>, offset: usize, mut len: usize) {
+    while len > 0 {
+        let chunk = len.min(MAX_COPY);
+
+        output.push((((chunk - 1) as u8) << 2) | 0b10);
+        output.extend_from_slice(&(offset as u16).to_le_bytes());
+        len -= chunk;
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+    (value.wrapping_mul(0x1e35_a7bd) >> (32 - HASH_BITS)) as usize
+}
+
+
+/// Compresses data as a single snappy block (not the framed format)
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 2 + 16);
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let mut literal_start = 0;
+    let mut position = 0;
+
+    write_varint(&mut output, input.len() as u64);
+
+    while position + 4 <= input.len() {
+        let key = hash(&input[position..]);
+        let candidate = table[key];
+
+        table[key] = position;
+
+        let matched = candidate != usize::MAX
+            && position - candidate <= MAX_OFFSET
+            && input[candidate..candidate + 4] == input[position..position + 4];
+
+        if !matched {
+            position += 1;
+            continue;
+        }
+
+        let len = input[position..]
+            .iter()
+            .zip(&input[candidate..])
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        write_literal(&mut output, &input[literal_start..position]);
+        write_copy(&mut output, position - candidate, len);
+
+        position += len;
+        literal_start = position;
+    }
+
+    write_literal(&mut output, &input[literal_start..]);
+    output
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a straightforward decompressor, to check the output round trips
+    fn decompress(input: &[u8]) -> Vec<u8> {
+        let mut position = 0;
+        let mut len = 0u64;
+        let mut shift = 0;
+
+        loop {
+            let byte = input[position];
+
+            position += 1;
+            len |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+
+            if byte < 0x80 {
+                break;
+            }
+        }
+
+        let mut output = Vec::new();
+
+        while position < input.len() {
+            let tag = input[position];
+
+            position += 1;
+
+            match tag & 0b11 {
+                0b00 => {
+                    let mut n = (tag >> 2) as usize;
+
+                    if n >= 60 {
+                        let width = n - 59;
+                        let mut bytes = [0u8; 8];
+
+                        bytes[..width].copy_from_slice(&input[position..position + width]);
+                        n = usize::from_le_bytes(bytes);
+                        position += width;
+                    }
+
+                    output.extend_from_slice(&input[position..position + n + 1]);
+                    position += n + 1;
+                },
+                0b10 => {
+                    let n = (tag >> 2) as usize + 1;
+                    let offset = u16::from_le_bytes([input[position], input[position + 1]]) as usize;
+
+                    position += 2;
+
+                    for _ in 0..n {
+                        output.push(output[output.len() - offset]);
+                    }
+                },
+                _ => panic!("Unexpected element"),
+            }
+        }
+
+        assert_eq!(output.len() as u64, len);
+        output
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let repeated = "container_name=\"web\" ".repeat(500);
+        let random = (0..100_000)
+            .map(|_| fastrand::u8(..))
+            .collect::<Vec<_>>();
+
+        for input in [&b""[..], b"abc", b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", repeated.as_bytes(), &random] {
+            assert_eq!(decompress(&compress(input)), input);
+        }
+
+        assert!(compress(repeated.as_bytes()).len() < repeated.len() / 10);
+    }
+}
@@ -0,0 +1,836 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::SeekFrom,
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
+};
+
+use tokio::{
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::{
+        AsyncReadExt,
+        AsyncSeekExt,
+        AsyncWriteExt,
+    },
+};
+use tracing::warn;
+
+use crate::{
+    config::Config,
+    log::LogMessage,
+};
+
+
+// length, checksum and message count, each a big-endian u32
+const HEADER_SIZE: usize = 12;
+
+const CURSOR_FILE: &str = "cursor";
+
+
+/// What to do when appending a batch would take the spool over its size cap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpoolEviction {
+    // remove the oldest segments to make room
+    DropOldest,
+    // discard the new batch
+    DropNewest,
+}
+
+impl FromStr for SpoolEviction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "drop-oldest" => Ok(SpoolEviction::DropOldest),
+            "drop-newest" => Ok(SpoolEviction::DropNewest),
+            _ => Err(format!("Invalid eviction policy: {}; expected drop-oldest or drop-newest", s)),
+        }
+    }
+}
+
+impl fmt::Display for SpoolEviction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpoolEviction::DropOldest => write!(f, "drop-oldest"),
+            SpoolEviction::DropNewest => write!(f, "drop-newest"),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct SpoolOptions {
+    pub max_size: u64,
+    pub eviction: SpoolEviction,
+}
+
+impl From<&Config> for SpoolOptions {
+    fn from(config: &Config) -> Self {
+        Self {
+            max_size: config.spool_max_size.0,
+            eviction: config.spool_eviction,
+        }
+    }
+}
+
+impl SpoolOptions {
+    // the cap is split over several segments so eviction doesn't discard everything
+    fn segment_size(&self) -> u64 {
+        (self.max_size / 4).max(1)
+    }
+}
+
+
+/// Number of messages which have passed through a spool since it was opened
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SpoolStats {
+    pub spooled: u64,
+    pub replayed: u64,
+    pub evicted: u64,
+}
+
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{:020}.spool", segment))
+}
+
+/// Syncs a directory, so entries created or renamed in it survive a power loss
+async fn sync_dir(dir: &Path) -> Result<(), std::io::Error> {
+    File::open(dir)
+        .await?
+        .sync_all()
+        .await
+}
+
+fn encode_frame(messages: &[LogMessage]) -> Result<Vec<u8>, serde_json::Error> {
+    let body = serde_json::to_vec(messages)?;
+    let mut frame = Vec::with_capacity(HEADER_SIZE + body.len());
+
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&crc32fast::hash(&body).to_be_bytes());
+    frame.extend_from_slice(&(messages.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&body);
+    Ok(frame)
+}
+
+fn decode_header(header: &[u8]) -> (usize, u32, u64) {
+    let field = |i: usize| u32::from_be_bytes(
+        header[i * 4..(i + 1) * 4]
+            .try_into()
+            .expect("Headers are fixed size")
+    );
+
+    (field(0) as usize, field(1), field(2) as u64)
+}
+
+
+/// Total size and message count of the valid frames in a segment
+#[derive(Debug, Default, Clone, Copy)]
+struct Segment {
+    size: u64,
+    messages: u64,
+}
+
+/// Scans a segment, returning the valid frames up to the first torn or corrupt one
+fn scan_segment(bytes: &[u8]) -> Segment {
+    let mut segment = Segment::default();
+    let mut rest = bytes;
+
+    while rest.len() >= HEADER_SIZE {
+        let (size, checksum, messages) = decode_header(&rest[..HEADER_SIZE]);
+        let body = match rest.get(HEADER_SIZE..HEADER_SIZE + size) {
+            Some(body) if crc32fast::hash(body) == checksum => body,
+            _ => break,
+        };
+
+        segment.size += (HEADER_SIZE + body.len()) as u64;
+        segment.messages += messages;
+        rest = &rest[HEADER_SIZE + size..];
+    }
+
+    segment
+}
+
+
+/// Position of the next batch to replay, persisted so replay resumes after a restart
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Cursor {
+    segment: u64,
+    offset: u64,
+    // messages before `offset` in the segment
+    messages: u64,
+}
+
+impl Cursor {
+    async fn read(dir: &Path) -> Result<Option<Self>, std::io::Error> {
+        let contents = match tokio::fs::read_to_string(dir.join(CURSOR_FILE)).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let fields = contents
+            .split_whitespace()
+            .map(|field| field.parse::<u64>().ok())
+            .collect::<Option<Vec<_>>>();
+
+        match fields.as_deref() {
+            Some([segment, offset, messages]) => Ok(Some(Self {
+                segment: *segment,
+                offset: *offset,
+                messages: *messages,
+            })),
+            _ => {
+                warn!("Ignoring invalid spool cursor in {:?}", dir);
+                Ok(None)
+            },
+        }
+    }
+
+    // written to a temporary file and renamed, so a crash leaves either cursor intact;
+    // with `sync`, a power loss does too
+    async fn write(&self, dir: &Path, sync: bool) -> Result<(), std::io::Error> {
+        let tmp = dir.join(format!("{}.tmp", CURSOR_FILE));
+        let mut file = File::create(&tmp)
+            .await?;
+
+        file.write_all(format!("{} {} {}", self.segment, self.offset, self.messages).as_bytes())
+            .await?;
+
+        if sync {
+            file.sync_all()
+                .await?;
+        }
+
+        tokio::fs::rename(tmp, dir.join(CURSOR_FILE))
+            .await?;
+
+        match sync {
+            true => sync_dir(dir).await,
+            false => Ok(()),
+        }
+    }
+}
+
+
+/// Durable, per container queue of batches which couldn't be delivered, replayed in
+/// the order they were spooled.
+///
+/// Batches are appended to numbered segment files as a frame of a 4 byte length, CRC32
+/// and message count followed by the JSON array of messages.  Torn or corrupt frames
+/// left by a crash are truncated when the spool is opened.  Batches and the cursor are
+/// synced to disk as they are written, so both survive a power loss as well, unless
+/// syncing is turned off with `with_sync`.  The replay position is kept in a cursor
+/// file, so delivery is at-least-once; a batch sent just before a crash may be sent
+/// again.  Once the spool would exceed `max_size`, either the oldest segments
+/// or the new batch are evicted.
+pub struct Spool {
+    dir: PathBuf,
+    options: SpoolOptions,
+    segments: BTreeMap<u64, Segment>,
+    file: File,
+    cursor: Cursor,
+    // size and message count of the frame returned by `peek`
+    pending: Option<(u64, u64)>,
+    stats: SpoolStats,
+    // whether every batch, and every move of the cursor, is synced to disk
+    sync: bool,
+}
+
+impl Spool {
+    pub fn dir<P: AsRef<Path>>(root: P, container_id: &str) -> PathBuf {
+        root
+            .as_ref()
+            .join("spool")
+            .join(container_id)
+    }
+
+    pub async fn open<P: AsRef<Path>>(root: P, container_id: &str, options: SpoolOptions) -> Result<Self, std::io::Error> {
+        Self::open_dir(Self::dir(root, container_id), options)
+            .await
+    }
+
+    /// Opens a spool in an arbitrary directory, e.g. for spilling the send queue
+    pub async fn open_dir<P: Into<PathBuf>>(dir: P, options: SpoolOptions) -> Result<Self, std::io::Error> {
+        let dir = dir.into();
+
+        tokio::fs::create_dir_all(&dir)
+            .await?;
+
+        let mut cursor = Cursor::read(&dir)
+            .await?
+            .unwrap_or_default();
+        let mut segments = BTreeMap::new();
+        let mut entries = tokio::fs::read_dir(&dir)
+            .await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let number = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_suffix(".spool"))
+                .and_then(|name| name.parse::<u64>().ok());
+
+            let number = match number {
+                Some(number) => number,
+                None => continue,
+            };
+
+            // already replayed
+            if number < cursor.segment {
+                tokio::fs::remove_file(entry.path())
+                    .await?;
+
+                continue;
+            }
+
+            let bytes = tokio::fs::read(entry.path())
+                .await?;
+            let segment = scan_segment(&bytes);
+
+            if segment.size < bytes.len() as u64 {
+                warn!(
+                    path = ?entry.path(),
+                    "Truncating {} bytes of incomplete or corrupt spool data", bytes.len() as u64 - segment.size,
+                );
+
+                OpenOptions::new()
+                    .write(true)
+                    .open(entry.path())
+                    .await?
+                    .set_len(segment.size)
+                    .await?;
+            }
+
+            segments.insert(number, segment);
+        }
+
+        match segments.get(&cursor.segment) {
+            Some(segment) if cursor.offset <= segment.size => (),
+            _ => {
+                cursor = Cursor {
+                    segment: segments
+                        .keys()
+                        .next()
+                        .copied()
+                        .unwrap_or(0),
+                    ..Cursor::default()
+                };
+            },
+        }
+
+        let last = segments
+            .keys()
+            .last()
+            .copied()
+            .unwrap_or(cursor.segment);
+
+        segments
+            .entry(last)
+            .or_default();
+
+        let file = Self::open_segment(&dir, last)
+            .await?;
+
+        cursor.write(&dir, true)
+            .await?;
+
+        Ok(Self {
+            dir,
+            options,
+            segments,
+            file,
+            cursor,
+            pending: None,
+            stats: SpoolStats::default(),
+            sync: true,
+        })
+    }
+
+    /// Without syncing, batches are only synced to disk when a segment fills up, and the
+    /// cursor is only saved when it moves to another segment, so a restart may replay
+    /// part of a segment again.  For spills, which would otherwise sync twice per message.
+    pub fn with_sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    async fn open_segment(dir: &Path, segment: u64) -> Result<File, std::io::Error> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(dir, segment))
+            .await
+    }
+
+    /// Size in bytes of the batches waiting to be replayed
+    pub fn size(&self) -> u64 {
+        self.segments
+            .values()
+            .map(|segment| segment.size)
+            .sum::<u64>() - self.cursor.offset
+    }
+
+    /// Number of messages waiting to be replayed
+    pub fn len(&self) -> u64 {
+        self.segments
+            .values()
+            .map(|segment| segment.messages)
+            .sum::<u64>() - self.cursor.messages
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
+
+    pub fn stats(&self) -> SpoolStats {
+        self.stats
+    }
+
+    fn last_segment(&self) -> u64 {
+        *self.segments
+            .keys()
+            .last()
+            .expect("A spool always has a segment to write to")
+    }
+
+    /// Appends a batch.  Returns false if it was evicted rather than spooled.
+    pub async fn append(&mut self, messages: &[LogMessage]) -> Result<bool, std::io::Error> {
+        let frame = encode_frame(messages)?;
+        let size = frame.len() as u64;
+
+        while self.size() + size > self.options.max_size {
+            match self.options.eviction {
+                SpoolEviction::DropNewest => {
+                    self.stats.evicted += messages.len() as u64;
+                    return Ok(false);
+                },
+                SpoolEviction::DropOldest => {
+                    if !self.evict_oldest().await? {
+                        break; // only the segment being written is left
+                    }
+                },
+            }
+        }
+
+        let last = self.last_segment();
+
+        if self.segments[&last].size >= self.options.segment_size() {
+            if !self.sync {
+                self.file
+                    .sync_data()
+                    .await?;
+            }
+
+            self.file = Self::open_segment(&self.dir, last + 1)
+                .await?;
+            self.segments.insert(last + 1, Segment::default());
+
+            sync_dir(&self.dir)
+                .await?;
+        }
+
+        let last = self.last_segment();
+
+        if let Err(err) = self.write_frame(&frame).await {
+            // whatever part of the frame was written would shift every later frame, so
+            // it's cut off, or failing that, left behind in favour of a new segment
+            if self.file.set_len(self.segments[&last].size).await.is_err() {
+                self.file = Self::open_segment(&self.dir, last + 1)
+                    .await?;
+                self.segments.insert(last + 1, Segment::default());
+            }
+
+            return Err(err);
+        }
+
+        let segment = self.segments
+            .get_mut(&last)
+            .expect("The last segment exists");
+
+        segment.size += size;
+        segment.messages += messages.len() as u64;
+        self.stats.spooled += messages.len() as u64;
+
+        Ok(true)
+    }
+
+    async fn write_frame(&mut self, frame: &[u8]) -> Result<(), std::io::Error> {
+        self.file
+            .write_all(frame)
+            .await?;
+
+        self.file
+            .flush()
+            .await?;
+
+        // durable before the batch counts as spooled, and so before the cursor moves past it
+        match self.sync {
+            true => self.file.sync_data().await,
+            false => Ok(()),
+        }
+    }
+
+    /// Removes the oldest segment, unless it is the one being written to
+    async fn evict_oldest(&mut self) -> Result<bool, std::io::Error> {
+        if self.segments.len() < 2 {
+            return Ok(false);
+        }
+
+        let evicted = self.segments[&self.cursor.segment].messages - self.cursor.messages;
+
+        self.stats.evicted += evicted;
+        self.advance_segment()
+            .await?;
+
+        warn!(
+            dir = ?self.dir,
+            evicted = self.stats.evicted,
+            "Spool is full; evicted {} messages", evicted,
+        );
+
+        Ok(true)
+    }
+
+    /// Moves the cursor to the start of the oldest remaining segment, removing the
+    /// segment it was in
+    async fn advance_segment(&mut self) -> Result<(), std::io::Error> {
+        let previous = self.cursor.segment;
+
+        self.segments
+            .remove(&previous);
+
+        self.cursor = Cursor {
+            segment: self.segments
+                .keys()
+                .next()
+                .copied()
+                .expect("The segment being written is never removed"),
+            ..Cursor::default()
+        };
+        self.pending = None;
+
+        self.cursor
+            .write(&self.dir, self.sync)
+            .await?;
+
+        match tokio::fs::remove_file(segment_path(&self.dir, previous)).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the oldest batch without removing it; see `ack` and `discard`
+    pub async fn peek(&mut self) -> Result<Option<Vec<LogMessage>>, std::io::Error> {
+        loop {
+            let segment = self.segments[&self.cursor.segment];
+
+            if self.cursor.offset < segment.size {
+                break;
+            }
+
+            if self.cursor.segment == self.last_segment() {
+                return Ok(None);
+            }
+
+            self.advance_segment()
+                .await?;
+        }
+
+        let segment = self.segments[&self.cursor.segment];
+        let (body, checksum, count) = match self.read_frame(segment.size - self.cursor.offset).await {
+            Ok(frame) => frame,
+            Err(err) => {
+                // without a readable header the next frame can't be found, so `discard`
+                // skips the rest of the segment
+                self.pending = Some((segment.size - self.cursor.offset, segment.messages - self.cursor.messages));
+                return Err(err);
+            },
+        };
+
+        self.pending = Some(((HEADER_SIZE + body.len()) as u64, count));
+
+        if crc32fast::hash(&body) != checksum {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Spool frame failed its checksum"));
+        }
+
+        let messages = serde_json::from_slice(&body)?;
+
+        Ok(Some(messages))
+    }
+
+    /// Reads the frame at the cursor, which has `remaining` bytes of its segment left;
+    /// returns its body, checksum and message count
+    async fn read_frame(&self, remaining: u64) -> Result<(Vec<u8>, u32, u64), std::io::Error> {
+        let mut file = File::open(segment_path(&self.dir, self.cursor.segment))
+            .await?;
+
+        file.seek(SeekFrom::Start(self.cursor.offset))
+            .await?;
+
+        let mut header = [0; HEADER_SIZE];
+
+        file.read_exact(&mut header)
+            .await?;
+
+        let (size, checksum, count) = decode_header(&header);
+
+        if (HEADER_SIZE + size) as u64 > remaining {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Spool frame runs past the end of its segment"));
+        }
+
+        let mut body = vec![0; size];
+
+        file.read_exact(&mut body)
+            .await?;
+
+        Ok((body, checksum, count))
+    }
+
+    /// Removes the batch returned by `peek` once it has been delivered
+    pub async fn ack(&mut self) -> Result<(), std::io::Error> {
+        if let Some(count) = self.advance().await? {
+            self.stats.replayed += count;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the batch returned by `peek` without delivering it
+    pub async fn discard(&mut self) -> Result<(), std::io::Error> {
+        if let Some(count) = self.advance().await? {
+            self.stats.evicted += count;
+        }
+
+        Ok(())
+    }
+
+    async fn advance(&mut self) -> Result<Option<u64>, std::io::Error> {
+        let (size, count) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(None),
+        };
+
+        self.cursor.offset += size;
+        self.cursor.messages += count;
+
+        // start over with an empty segment, rather than growing the last one forever
+        if self.is_empty() {
+            let next = self.last_segment() + 1;
+
+            self.file = Self::open_segment(&self.dir, next)
+                .await?;
+            self.segments.insert(next, Segment::default());
+
+            if self.sync {
+                sync_dir(&self.dir)
+                    .await?;
+            }
+
+            while self.cursor.segment != next {
+                self.advance_segment()
+                    .await?;
+            }
+        } else if self.sync {
+            self.cursor
+                .write(&self.dir, true)
+                .await?;
+        }
+
+        Ok(Some(count))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(text: &str) -> LogMessage {
+        LogMessage {
+            timestamp: chrono::Utc::now(),
+            message: text.to_string(),
+            level: 3,
+            context: None,
+        }
+    }
+
+    fn options() -> SpoolOptions {
+        SpoolOptions {
+            max_size: 1024 * 1024,
+            eviction: SpoolEviction::DropOldest,
+        }
+    }
+
+    async fn replay(spool: &mut Spool) -> Vec<String> {
+        let mut replayed = Vec::new();
+
+        while let Some(messages) = spool.peek().await.unwrap() {
+            replayed.extend(messages.into_iter().map(|message| message.message));
+            spool.ack().await.unwrap();
+        }
+
+        replayed
+    }
+
+    #[tokio::test]
+    async fn test_replay_in_order() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let mut spool = Spool::open(dir.path(), "container", options())
+            .await
+            .unwrap();
+
+        assert!(spool.is_empty());
+        assert!(spool.peek().await.unwrap().is_none());
+
+        spool.append(&[message("1"), message("2")]).await.unwrap();
+        spool.append(&[message("3")]).await.unwrap();
+
+        assert_eq!(spool.len(), 3);
+
+        // an unacknowledged batch is returned again
+        assert_eq!(spool.peek().await.unwrap().unwrap().len(), 2);
+        assert_eq!(replay(&mut spool).await, vec!["1", "2", "3"]);
+        assert!(spool.is_empty());
+
+        assert_eq!(spool.stats(), SpoolStats {
+            spooled: 3,
+            replayed: 3,
+            evicted: 0,
+        });
+    }
+
+    #[tokio::test]
+    async fn test_survives_reopen() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let mut spool = Spool::open(dir.path(), "container", options())
+            .await
+            .unwrap();
+
+        spool.append(&[message("1")]).await.unwrap();
+        spool.append(&[message("2")]).await.unwrap();
+        spool.peek().await.unwrap();
+        spool.ack().await.unwrap();
+
+        drop(spool);
+
+        let mut spool = Spool::open(dir.path(), "container", options())
+            .await
+            .unwrap();
+
+        assert_eq!(spool.len(), 1);
+        assert_eq!(replay(&mut spool).await, vec!["2"]);
+    }
+
+    #[tokio::test]
+    async fn test_truncates_torn_frame() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let mut spool = Spool::open(dir.path(), "container", options())
+            .await
+            .unwrap();
+
+        spool.append(&[message("1")]).await.unwrap();
+
+        let path = segment_path(&Spool::dir(dir.path(), "container"), spool.last_segment());
+
+        drop(spool);
+
+        // a crash part way through writing a frame
+        let torn = encode_frame(&[message("2")])
+            .unwrap();
+        let mut bytes = std::fs::read(&path)
+            .unwrap();
+
+        bytes.extend_from_slice(&torn[..torn.len() - 4]);
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mut spool = Spool::open(dir.path(), "container", options())
+            .await
+            .unwrap();
+
+        spool.append(&[message("3")]).await.unwrap();
+        assert_eq!(replay(&mut spool).await, vec!["1", "3"]);
+    }
+
+    #[tokio::test]
+    async fn test_skips_truncated_frame() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let mut spool = Spool::open(dir.path(), "container", options())
+            .await
+            .unwrap();
+
+        spool.append(&[message("1")]).await.unwrap();
+        spool.append(&[message("2")]).await.unwrap();
+
+        // the segment is cut short while the spool is open, e.g. by a failing disk
+        let path = segment_path(&Spool::dir(dir.path(), "container"), spool.last_segment());
+        let bytes = std::fs::read(&path)
+            .unwrap();
+
+        std::fs::write(&path, &bytes[..HEADER_SIZE + 2]).unwrap();
+
+        assert!(spool.peek().await.is_err());
+        spool.discard().await.unwrap();
+
+        // the rest of the segment was skipped, rather than the same frame failing forever
+        assert!(spool.peek().await.unwrap().is_none());
+        assert_eq!(spool.stats().evicted, 2);
+
+        spool.append(&[message("3")]).await.unwrap();
+        assert_eq!(replay(&mut spool).await, vec!["3"]);
+    }
+
+    #[tokio::test]
+    async fn test_evict_oldest() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let frame_size = encode_frame(&[message("0")]).unwrap().len() as u64;
+        let mut spool = Spool::open(dir.path(), "container", SpoolOptions {
+            max_size: frame_size * 4,
+            eviction: SpoolEviction::DropOldest,
+        }).await.unwrap();
+
+        for i in 0..6 {
+            assert!(spool.append(&[message(&i.to_string())]).await.unwrap());
+        }
+
+        assert!(spool.size() <= frame_size * 4);
+        assert_eq!(spool.stats().evicted, 2);
+        assert_eq!(replay(&mut spool).await, vec!["2", "3", "4", "5"]);
+    }
+
+    #[tokio::test]
+    async fn test_evict_newest() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let frame_size = encode_frame(&[message("0")]).unwrap().len() as u64;
+        let mut spool = Spool::open(dir.path(), "container", SpoolOptions {
+            max_size: frame_size * 2,
+            eviction: SpoolEviction::DropNewest,
+        }).await.unwrap();
+
+        assert!(spool.append(&[message("0")]).await.unwrap());
+        assert!(spool.append(&[message("1")]).await.unwrap());
+        assert!(!spool.append(&[message("2")]).await.unwrap());
+
+        assert_eq!(spool.stats().evicted, 1);
+        assert_eq!(replay(&mut spool).await, vec!["0", "1"]);
+    }
+}
@@ -0,0 +1,128 @@
+use std::path::Path;
+
+use tokio::{
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::AsyncWriteExt,
+};
+
+
+/// Number of entries between index records within a segment
+pub const INDEX_INTERVAL: u64 = 64;
+
+const RECORD_SIZE: usize = 24;
+
+
+/// A sparse index record pointing at the `ordinal`th entry of a segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexRecord {
+    pub ordinal: u64,
+    pub time_nano: i64,
+    pub offset: u64,
+}
+
+impl IndexRecord {
+    fn to_bytes(self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+
+        bytes[0..8].copy_from_slice(&self.ordinal.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.time_nano.to_be_bytes());
+        bytes[16..24].copy_from_slice(&self.offset.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let field = |range: std::ops::Range<usize>| -> [u8; 8] {
+            bytes[range]
+                .try_into()
+                .expect("Index records are fixed size")
+        };
+
+        Self {
+            ordinal: u64::from_be_bytes(field(0..8)),
+            time_nano: i64::from_be_bytes(field(8..16)),
+            offset: u64::from_be_bytes(field(16..24)),
+        }
+    }
+}
+
+
+/// Reads every complete record from an index file.  A missing index is treated as
+/// empty and a trailing partial record (e.g. from a crash) is ignored.
+pub async fn read_index<P: AsRef<Path>>(path: P) -> Result<Vec<IndexRecord>, std::io::Error> {
+    let bytes = match tokio::fs::read(path).await {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    Ok(
+        bytes
+            .chunks_exact(RECORD_SIZE)
+            .map(IndexRecord::from_bytes)
+            .collect()
+    )
+}
+
+
+pub struct IndexWriter {
+    file: File,
+}
+
+impl IndexWriter {
+    pub async fn create<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+
+        Ok(Self { file })
+    }
+
+    pub async fn append(&mut self, record: IndexRecord) -> Result<(), std::io::Error> {
+        self.file
+            .write_all(&record.to_bytes())
+            .await?;
+
+        self.file
+            .flush()
+            .await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_index_round_trip() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+        let path = dir.path().join("0.idx");
+
+        let records = vec![
+            IndexRecord { ordinal: 0, time_nano: 10, offset: 0 },
+            IndexRecord { ordinal: 64, time_nano: -1, offset: 4096 },
+        ];
+
+        let mut writer = IndexWriter::create(&path)
+            .await
+            .unwrap();
+
+        for record in &records {
+            writer.append(*record).await.unwrap();
+        }
+
+        // a torn trailing record is ignored
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.extend_from_slice(&[1, 2, 3]);
+        std::fs::write(&path, bytes).unwrap();
+
+        assert_eq!(read_index(&path).await.unwrap(), records);
+        assert!(read_index(dir.path().join("missing.idx")).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,356 @@
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use docker_protobuf::LogEntry;
+use prost::Message;
+use tokio::{
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::AsyncWriteExt,
+};
+
+use crate::config::Config;
+
+use index::{
+    IndexRecord,
+    IndexWriter,
+    INDEX_INTERVAL,
+};
+
+mod index;
+mod reader;
+
+pub use reader::{
+    LogStoreReader,
+    Position,
+};
+
+
+/// Encodes a LogEntry using the same framing Docker uses for the FIFO; a 4 byte
+/// big-endian length followed by the protobuf encoded entry.
+pub fn encode_frame(entry: &LogEntry) -> Vec<u8> {
+    let body = entry.encode_to_vec();
+    let mut frame = Vec::with_capacity(body.len() + 4);
+
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&body);
+    frame
+}
+
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{:020}.log", segment))
+}
+
+fn index_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{:020}.idx", segment))
+}
+
+/// Returns the segment numbers in `dir` in ascending order.  A missing directory
+/// has no segments.
+async fn list_segments(dir: &Path) -> Result<Vec<u64>, std::io::Error> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut segments = Vec::new();
+
+    while let Some(entry) = entries.next_entry().await? {
+        let segment = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_suffix(".log"))
+            .and_then(|name| name.parse::<u64>().ok());
+
+        if let Some(segment) = segment {
+            segments.push(segment);
+        }
+    }
+
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+async fn remove_if_exists(path: PathBuf) -> Result<(), std::io::Error> {
+    match tokio::fs::remove_file(path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+
+/// Retention limits for a LogStore, equivalent to json-file's `max-size` and `max-file`.
+#[derive(Debug, Clone, Copy)]
+pub struct StoreOptions {
+    pub max_size: u64,
+    pub max_file: usize,
+}
+
+impl From<&Config> for StoreOptions {
+    fn from(config: &Config) -> Self {
+        Self {
+            max_size: config.max_size.0,
+            max_file: config.max_file.max(1),
+        }
+    }
+}
+
+
+/// Local, segmented copy of every LogEntry read from a container's FIFO.  Used to
+/// serve `LogDriver.ReadLogs`.
+///
+/// Each container has its own directory containing numbered segment files, which use
+/// the same framing as the FIFO, and a sparse index per segment mapping entry ordinals
+/// to timestamps and offsets.  A new segment is started whenever the store is opened
+/// or the current segment would exceed `max_size`; the oldest segments are removed
+/// once there are more than `max_file`.
+pub struct LogStore {
+    dir: PathBuf,
+    options: StoreOptions,
+    segment: u64,
+    file: File,
+    index: IndexWriter,
+    size: u64,
+    entries: u64,
+}
+
+impl LogStore {
+    pub fn dir<P: AsRef<Path>>(root: P, container_id: &str) -> PathBuf {
+        root
+            .as_ref()
+            .join("containers")
+            .join(container_id)
+    }
+
+    pub async fn open<P: AsRef<Path>>(root: P, container_id: &str, options: StoreOptions) -> Result<Self, std::io::Error> {
+        let dir = Self::dir(root, container_id);
+
+        tokio::fs::create_dir_all(&dir)
+            .await?;
+
+        let segment = list_segments(&dir)
+            .await?
+            .last()
+            .map(|segment| segment + 1)
+            .unwrap_or(0);
+
+        let (file, index) = Self::create_segment(&dir, segment)
+            .await?;
+
+        let store = Self {
+            dir,
+            options,
+            segment,
+            file,
+            index,
+            size: 0,
+            entries: 0,
+        };
+
+        store
+            .enforce_retention()
+            .await?;
+
+        Ok(store)
+    }
+
+    /// Removes the store for a container, if it exists
+    pub async fn remove<P: AsRef<Path>>(root: P, container_id: &str) -> Result<(), std::io::Error> {
+        match tokio::fs::remove_dir_all(Self::dir(root, container_id)).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn append(&mut self, entry: &LogEntry) -> Result<(), std::io::Error> {
+        let frame = encode_frame(entry);
+
+        if self.size > 0 && self.size + frame.len() as u64 > self.options.max_size {
+            self.rotate()
+                .await?;
+        }
+
+        if self.entries.is_multiple_of(INDEX_INTERVAL) {
+            self.index
+                .append(IndexRecord {
+                    ordinal: self.entries,
+                    time_nano: entry.time_nano,
+                    offset: self.size,
+                })
+                .await?;
+        }
+
+        self.file
+            .write_all(&frame)
+            .await?;
+
+        self.file
+            .flush()
+            .await?;
+
+        self.size += frame.len() as u64;
+        self.entries += 1;
+
+        Ok(())
+    }
+
+    async fn create_segment(dir: &Path, segment: u64) -> Result<(File, IndexWriter), std::io::Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(dir, segment))
+            .await?;
+
+        let index = IndexWriter::create(index_path(dir, segment))
+            .await?;
+
+        Ok((file, index))
+    }
+
+    async fn rotate(&mut self) -> Result<(), std::io::Error> {
+        let (file, index) = Self::create_segment(&self.dir, self.segment + 1)
+            .await?;
+
+        self.segment += 1;
+        self.file = file;
+        self.index = index;
+        self.size = 0;
+        self.entries = 0;
+
+        self.enforce_retention()
+            .await
+    }
+
+    async fn enforce_retention(&self) -> Result<(), std::io::Error> {
+        let segments = list_segments(&self.dir)
+            .await?;
+
+        let excess = segments
+            .len()
+            .saturating_sub(self.options.max_file);
+
+        for segment in &segments[..excess] {
+            // remove the segment first; segments are listed by their log file
+            remove_if_exists(segment_path(&self.dir, *segment)).await?;
+            remove_if_exists(index_path(&self.dir, *segment)).await?;
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use docker_protobuf::LogEntry;
+
+    use super::*;
+
+    pub(super) fn entry(line: &str, time_nano: i64) -> LogEntry {
+        LogEntry {
+            source: "stdout".to_string(),
+            time_nano,
+            line: line
+                .as_bytes()
+                .to_vec(),
+            partial: false,
+            partial_log_metadata: None,
+        }
+    }
+
+    pub(super) fn options() -> StoreOptions {
+        StoreOptions {
+            max_size: 1024 * 1024,
+            max_file: 5,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rotation_and_retention() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let frame_size = encode_frame(&entry("line 0", 1)).len() as u64;
+        let options = StoreOptions {
+            max_size: frame_size * 2,
+            max_file: 2,
+        };
+
+        let mut store = LogStore::open(dir.path(), "container", options)
+            .await
+            .unwrap();
+
+        for i in 0..6 {
+            store.append(&entry(&format!("line {}", i), i + 1)).await.unwrap();
+        }
+
+        // 3 segments of 2 entries were written; only the newest 2 are kept
+        let segments = list_segments(&LogStore::dir(dir.path(), "container"))
+            .await
+            .unwrap();
+
+        assert_eq!(segments, vec![1, 2]);
+
+        let mut reader = LogStoreReader::open(dir.path(), "container")
+            .await
+            .unwrap();
+
+        assert_eq!(reader.next().await.unwrap().unwrap().line, b"line 2");
+    }
+
+    #[tokio::test]
+    async fn test_reopen_starts_new_segment() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        LogStore::open(dir.path(), "container", options())
+            .await
+            .unwrap()
+            .append(&entry("first", 1))
+            .await
+            .unwrap();
+
+        LogStore::open(dir.path(), "container", options())
+            .await
+            .unwrap()
+            .append(&entry("second", 2))
+            .await
+            .unwrap();
+
+        let segments = list_segments(&LogStore::dir(dir.path(), "container"))
+            .await
+            .unwrap();
+
+        assert_eq!(segments, vec![0, 1]);
+
+        let mut reader = LogStoreReader::open(dir.path(), "container")
+            .await
+            .unwrap();
+
+        assert_eq!(reader.next().await.unwrap().unwrap().line, b"first");
+        assert_eq!(reader.next().await.unwrap().unwrap().line, b"second");
+        assert!(reader.next().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_remove() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        LogStore::open(dir.path(), "container", options())
+            .await
+            .unwrap();
+
+        LogStore::remove(dir.path(), "container").await.unwrap();
+        assert!(!LogStore::dir(dir.path(), "container").exists());
+
+        // removing a missing store is not an error
+        LogStore::remove(dir.path(), "container").await.unwrap();
+    }
+}
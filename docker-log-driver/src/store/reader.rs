@@ -0,0 +1,554 @@
+use std::{
+    io::SeekFrom,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use docker_protobuf::LogEntry;
+use tokio::{
+    fs::File,
+    io::{
+        AsyncReadExt,
+        AsyncSeekExt,
+    },
+};
+
+use crate::reader::{
+    Reader,
+    ReaderError,
+};
+
+use super::{
+    index::{
+        read_index,
+        IndexRecord,
+    },
+    index_path,
+    list_segments,
+    segment_path,
+    LogStore,
+};
+
+
+/// A location within a LogStore; the offset of a frame within a segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub segment: u64,
+    pub offset: u64,
+}
+
+
+/// Iterates the raw frames of a single segment.  If a frame is incomplete the
+/// iterator rewinds to the start of the frame so it can be retried once the writer
+/// has caught up.
+struct Frames {
+    file: File,
+    offset: u64,
+    max_frame_size: usize,
+}
+
+impl Frames {
+    async fn open(path: &Path, offset: u64, max_frame_size: usize) -> Result<Option<Self>, std::io::Error> {
+        let mut file = match File::open(path).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        file.seek(SeekFrom::Start(offset))
+            .await?;
+
+        Ok(Some(Self { file, offset, max_frame_size }))
+    }
+
+    /// Returns the offset and body of the next complete frame
+    async fn next(&mut self) -> Result<Option<(u64, Vec<u8>)>, std::io::Error> {
+        let mut size: [u8; 4] = [0; 4];
+
+        if !self.read_or_rewind(&mut size).await? {
+            return Ok(None);
+        }
+
+        let len = u32::from_be_bytes(size) as usize;
+
+        // a corrupt length mustn't be allocated
+        if len > self.max_frame_size {
+            let err = ReaderError::FrameTooLarge {
+                size: len,
+                max: self.max_frame_size,
+            };
+
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err));
+        }
+
+        let mut buffer = vec![0; len];
+
+        if !self.read_or_rewind(&mut buffer).await? {
+            return Ok(None);
+        }
+
+        let offset = self.offset;
+
+        self.offset += (size.len() + buffer.len()) as u64;
+        Ok(Some((offset, buffer)))
+    }
+
+    async fn read_or_rewind(&mut self, buffer: &mut [u8]) -> Result<bool, std::io::Error> {
+        match self.file.read_exact(buffer).await {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                self.file
+                    .seek(SeekFrom::Start(self.offset))
+                    .await?;
+
+                Ok(false)
+            },
+            Err(err) => Err(err),
+        }
+    }
+}
+
+
+fn decode(body: &[u8]) -> Result<LogEntry, std::io::Error> {
+    LogEntry::from_bytes(body)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+
+/// Reads entries back out of a LogStore, following the writer across segments.
+/// Reaching the end of the store is not terminal; `next` can be retried once more
+/// entries have been written.
+pub struct LogStoreReader {
+    dir: PathBuf,
+    segment: u64,
+    frames: Option<Frames>,
+    offset: u64,
+    max_frame_size: usize,
+}
+
+impl LogStoreReader {
+    /// Opens a reader positioned at the oldest retained entry for a container
+    pub async fn open<P: AsRef<Path>>(root: P, container_id: &str) -> Result<Self, std::io::Error> {
+        let dir = LogStore::dir(root, container_id);
+
+        // fail early if the container has never been logged
+        tokio::fs::metadata(&dir)
+            .await?;
+
+        let segment = list_segments(&dir)
+            .await?
+            .first()
+            .copied()
+            .unwrap_or(0);
+
+        Ok(Self {
+            dir,
+            segment,
+            frames: None,
+            offset: 0,
+            max_frame_size: Reader::<File>::DEFAULT_MAX_FRAME_SIZE,
+        })
+    }
+
+    /// Frames with a length prefix larger than `max_frame_size` are an error rather
+    /// than allocated; entries are stored as read from the FIFO, so no larger.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    pub fn position(&self) -> Position {
+        Position {
+            segment: self.segment,
+            offset: self.offset,
+        }
+    }
+
+    pub fn seek(&mut self, position: Position) {
+        self.segment = position.segment;
+        self.offset = position.offset;
+        self.frames = None;
+    }
+
+    /// Returns the next LogEntry in the store, or None if no complete entry is
+    /// currently available.
+    pub async fn next(&mut self) -> Result<Option<LogEntry>, ReaderError> {
+        loop {
+            if let Some(body) = self.next_frame().await? {
+                return LogEntry::from_bytes(&body)
+                    .map_err(ReaderError::from)
+                    .map(Some);
+            }
+
+            let next_segment = list_segments(&self.dir)
+                .await?
+                .into_iter()
+                .find(|segment| *segment > self.segment);
+
+            let next_segment = match next_segment {
+                Some(segment) => segment,
+                None => return Ok(None),
+            };
+
+            // The writer never returns to a segment once it has rotated, but it may have
+            // finished writing to this one after our last read.
+            if let Some(body) = self.next_frame().await? {
+                return LogEntry::from_bytes(&body)
+                    .map_err(ReaderError::from)
+                    .map(Some);
+            }
+
+            self.seek(Position {
+                segment: next_segment,
+                offset: 0,
+            });
+        }
+    }
+
+    async fn next_frame(&mut self) -> Result<Option<Vec<u8>>, std::io::Error> {
+        if self.frames.is_none() {
+            self.frames = Frames::open(&segment_path(&self.dir, self.segment), self.offset, self.max_frame_size)
+                .await?;
+        }
+
+        let frames = match self.frames.as_mut() {
+            Some(frames) => frames,
+            None => return Ok(None),
+        };
+
+        let frame = frames
+            .next()
+            .await?;
+
+        self.offset = frames.offset;
+        Ok(frame.map(|(_, body)| body))
+    }
+
+    /// Returns the position of the first entry at or after `time_nano`, assuming
+    /// entries were written in (roughly) chronological order.
+    pub async fn position_since(&self, time_nano: i64) -> Result<Position, std::io::Error> {
+        let segments = list_segments(&self.dir)
+            .await?;
+
+        // find the last segment starting at or before `time_nano`
+        let mut start = 0;
+
+        for (i, segment) in segments.iter().enumerate() {
+            match self.first_time_nano(*segment).await? {
+                Some(first) if first <= time_nano => start = i,
+                Some(_) => break,
+                None => (),
+            }
+        }
+
+        for segment in &segments[start.min(segments.len())..] {
+            // skip ahead using the last index record before `time_nano`
+            let offset = read_index(index_path(&self.dir, *segment))
+                .await?
+                .into_iter()
+                .take_while(|record| record.time_nano < time_nano)
+                .last()
+                .map(|record| record.offset)
+                .unwrap_or(0);
+
+            let mut frames = match Frames::open(&segment_path(&self.dir, *segment), offset, self.max_frame_size).await? {
+                Some(frames) => frames,
+                None => continue,
+            };
+
+            while let Some((offset, body)) = frames.next().await? {
+                if decode(&body)?.time_nano >= time_nano {
+                    return Ok(Position {
+                        segment: *segment,
+                        offset,
+                    });
+                }
+            }
+        }
+
+        self.end()
+            .await
+    }
+
+    /// Returns the position of the `count`th entry from the end of the store
+    pub async fn position_tail(&self, count: usize) -> Result<Position, std::io::Error> {
+        let segments = list_segments(&self.dir)
+            .await?;
+        let mut remaining = count as u64;
+
+        if remaining == 0 {
+            return self.end().await;
+        }
+
+        for segment in segments.iter().rev() {
+            let index = read_index(index_path(&self.dir, *segment))
+                .await?;
+            let entries = self.count_entries(*segment, &index)
+                .await?;
+
+            if entries >= remaining {
+                return self.position_of(*segment, &index, entries - remaining)
+                    .await;
+            }
+
+            remaining -= entries;
+        }
+
+        Ok(Position {
+            segment: segments
+                .first()
+                .copied()
+                .unwrap_or(0),
+            offset: 0,
+        })
+    }
+
+    /// Returns the position just past the last complete entry in the store
+    async fn end(&self) -> Result<Position, std::io::Error> {
+        let segment = match list_segments(&self.dir).await?.last() {
+            Some(segment) => *segment,
+            None => return Ok(self.position()),
+        };
+
+        let index = read_index(index_path(&self.dir, segment))
+            .await?;
+        let entries = self.count_entries(segment, &index)
+            .await?;
+
+        self.position_of(segment, &index, entries)
+            .await
+    }
+
+    async fn first_time_nano(&self, segment: u64) -> Result<Option<i64>, std::io::Error> {
+        let frame = match Frames::open(&segment_path(&self.dir, segment), 0, self.max_frame_size).await? {
+            Some(mut frames) => frames.next().await?,
+            None => None,
+        };
+
+        frame
+            .map(|(_, body)| decode(&body).map(|entry| entry.time_nano))
+            .transpose()
+    }
+
+    async fn count_entries(&self, segment: u64, index: &[IndexRecord]) -> Result<u64, std::io::Error> {
+        let (ordinal, offset) = index
+            .last()
+            .map(|record| (record.ordinal, record.offset))
+            .unwrap_or((0, 0));
+
+        let mut frames = match Frames::open(&segment_path(&self.dir, segment), offset, self.max_frame_size).await? {
+            Some(frames) => frames,
+            None => return Ok(0),
+        };
+
+        let mut count = ordinal;
+
+        while frames.next().await?.is_some() {
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Returns the position of the `ordinal`th entry in a segment, or the end of the
+    /// segment if it has fewer entries.
+    async fn position_of(&self, segment: u64, index: &[IndexRecord], ordinal: u64) -> Result<Position, std::io::Error> {
+        let (mut current, offset) = index
+            .iter()
+            .take_while(|record| record.ordinal <= ordinal)
+            .last()
+            .map(|record| (record.ordinal, record.offset))
+            .unwrap_or((0, 0));
+
+        let mut position = Position {
+            segment,
+            offset,
+        };
+
+        let mut frames = match Frames::open(&segment_path(&self.dir, segment), offset, self.max_frame_size).await? {
+            Some(frames) => frames,
+            None => return Ok(position),
+        };
+
+        while current < ordinal && frames.next().await?.is_some() {
+            current += 1;
+        }
+
+        position.offset = frames.offset;
+        Ok(position)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{
+        encode_frame,
+        StoreOptions,
+        tests::{
+            entry,
+            options,
+        },
+    };
+
+    async fn populate(root: &Path, options: StoreOptions, count: i64) {
+        let mut store = LogStore::open(root, "container", options)
+            .await
+            .unwrap();
+
+        for i in 0..count {
+            store.append(&entry(&format!("line {}", i), i * 10)).await.unwrap();
+        }
+    }
+
+    async fn read_from(reader: &mut LogStoreReader, position: Position) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        reader.seek(position);
+
+        while let Some(entry) = reader.next().await.unwrap() {
+            lines.push(String::from_utf8(entry.line).unwrap());
+        }
+
+        lines
+    }
+
+    // small segments so queries cross segment and index boundaries
+    fn small_segments() -> StoreOptions {
+        StoreOptions {
+            max_size: encode_frame(&entry("line 000", 0)).len() as u64 * 100,
+            max_file: 10,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_position_since() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        populate(dir.path(), small_segments(), 300).await;
+
+        let mut reader = LogStoreReader::open(dir.path(), "container")
+            .await
+            .unwrap();
+
+        let position = reader
+            .position_since(2455)
+            .await
+            .unwrap();
+        let lines = read_from(&mut reader, position).await;
+
+        assert_eq!(lines.first().unwrap(), "line 246");
+        assert_eq!(lines.len(), 300 - 246);
+
+        // a timestamp before the first entry starts at the beginning
+        let position = reader
+            .position_since(-1)
+            .await
+            .unwrap();
+
+        assert_eq!(read_from(&mut reader, position).await.len(), 300);
+
+        // a timestamp after the last entry starts at the end
+        let position = reader
+            .position_since(i64::MAX)
+            .await
+            .unwrap();
+
+        assert!(read_from(&mut reader, position).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_position_tail() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        populate(dir.path(), small_segments(), 300).await;
+
+        let mut reader = LogStoreReader::open(dir.path(), "container")
+            .await
+            .unwrap();
+
+        for count in [0, 1, 70, 150, 300, 1000] {
+            let position = reader
+                .position_tail(count)
+                .await
+                .unwrap();
+            let lines = read_from(&mut reader, position).await;
+
+            assert_eq!(lines.len(), count.min(300));
+
+            if count > 0 {
+                assert_eq!(lines.last().unwrap(), "line 299");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_follow_across_rotation() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        let frame_size = encode_frame(&entry("line 0", 1)).len() as u64;
+        let options = StoreOptions {
+            max_size: frame_size,
+            ..options()
+        };
+
+        let mut store = LogStore::open(dir.path(), "container", options)
+            .await
+            .unwrap();
+        let mut reader = LogStoreReader::open(dir.path(), "container")
+            .await
+            .unwrap();
+
+        for i in 1..4 {
+            store.append(&entry(&format!("line {}", i), i)).await.unwrap();
+
+            let entry = reader
+                .next()
+                .await
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(entry.time_nano, i);
+            assert!(reader.next().await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_frame_size() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        populate(dir.path(), options(), 1).await;
+
+        let path = segment_path(&LogStore::dir(dir.path(), "container"), 0);
+        let mut segment = std::fs::read(&path)
+            .unwrap();
+
+        // a length prefix of ~4 GiB, which mustn't be allocated
+        segment.extend_from_slice(&[0xff; 8]);
+        std::fs::write(&path, segment)
+            .unwrap();
+
+        let mut reader = LogStoreReader::open(dir.path(), "container")
+            .await
+            .unwrap()
+            .with_max_frame_size(1024);
+
+        assert!(reader.next().await.unwrap().is_some());
+        assert!(reader.next().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_open_missing_container() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+
+        assert!(LogStoreReader::open(dir.path(), "missing").await.is_err());
+    }
+}
@@ -1,138 +1,544 @@
 use std::path::PathBuf;
 
+use docker_protobuf::LogEntry;
+use serde_json::{
+    Map,
+    Value,
+};
 use tokio::{
     io::AsyncReadExt,
-    sync::oneshot::Receiver,
+    sync::{
+        oneshot::Receiver,
+        watch,
+    },
+    time::Instant,
+};
+use futures_util::future::join_all;
+use tracing::{
+    info,
+    info_span,
+    Instrument,
 };
-use tracing::info;
 
 use crate::{
     client::{
+        HttpClients,
         Ingest,
-        IngestClient,
     },
-    config::Config,
-    error::Loggable,
+    config::{
+        Config,
+        SinkConfig,
+    },
+    batch::{
+        BatchOptions,
+        Batcher,
+    },
+    container::ContainerInfo,
+    dead_letter::{
+        DeadLetter,
+        DeadLetterOptions,
+        DeadLetterSource,
+    },
+    error::{
+        BoxedError,
+        Loggable,
+    },
     log::LogMessage,
+    partial::Reassembler,
+    queue::{
+        self,
+        QueuePolicy,
+        QueueReceiver,
+        QueueSender,
+    },
+    reader::ReaderError,
+    sender::Sender,
+    sink::Sink,
+    spool::{
+        Spool,
+        SpoolOptions,
+    },
+    store::{
+        LogStore,
+        StoreOptions,
+    },
 };
 
 
-pub type ApiTask = Task<IngestClient>;
+pub type ApiTask = Task<Sink>;
 
 #[async_trait::async_trait]
 pub trait FifoProcessor {
-    fn new(config: Config) -> Self;
+    fn new(config: Config, info: ContainerInfo, clients: HttpClients) -> Self;
+
+    /// Opens the FIFO, and creates the client for each sink, ahead of `process` so
+    /// failures can be reported to Docker from `LogDriver.StartLogging`.
+    async fn open<P: Into<PathBuf> + Send>(&mut self, path: P) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>>;
     async fn process<P: Into<PathBuf> + Send>(self, path: P, receiver: Receiver<bool>) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>>; // TODO: select appropriate error type
 }
 
 pub struct Task<T> {
     config: Config,
-    _t: std::marker::PhantomData<T>,
+    info: ContainerInfo,
+    clients: HttpClients,
+    sinks: Vec<(SinkConfig, T)>,
+    fifo: Option<tokio::fs::File>,
+}
+
+
+impl<T: Ingest> Task<T> {
+    /// Opens the spool, spill and dead-letter file of a sink, per its own settings
+    async fn output(&self, sink: SinkConfig, client: T, stop: &watch::Receiver<bool>) -> Output<T> {
+        let config = sink.config;
+        let id = &self.info.container_id;
+
+        let spool = match config.spool_max_size.0 {
+            0 => None,
+            _ => Spool::open(&config.state_dir, id, SpoolOptions::from(&config))
+                .await
+                .map_err(BoxedError::from)
+                .log_error(format!("Failed to open {} spool for container {}", sink.name, id))
+                .ok(),
+        };
+
+        let spill = match config.queue_policy() {
+            // a spill is written and read a message at a time, so it's only synced as
+            // its segments fill up
+            QueuePolicy::Spill => Spool::open_dir(queue::spill_dir(&config.state_dir, id), SpoolOptions::from(&config))
+                .await
+                .map(|spool| spool.with_sync(false))
+                .map_err(BoxedError::from)
+                .log_error(format!("Failed to open {} spill for container {}", sink.name, id))
+                .ok(),
+            _ => None,
+        };
+
+        let dead_letter = match config.dead_letter {
+            true => self.open_dead_letter(&sink.name, &config)
+                .await
+                .map_err(BoxedError::from)
+                .log_error(format!("Failed to open {} dead-letter file for container {}", sink.name, id))
+                .ok(),
+            false => None,
+        };
+
+        let sender = Sender::new(&config, client, spool)
+            .with_dead_letter(dead_letter)
+            .with_stop(stop.clone());
+
+        Output {
+            spill,
+            ..Output::new(&sink.name, &config, sender)
+        }
+    }
+
+    async fn open_dead_letter(&self, name: &str, config: &Config) -> Result<DeadLetter, std::io::Error> {
+        let dead_letter = DeadLetter::open(&config.state_dir, &self.info.container_id, DeadLetterOptions::from(config))
+            .await?;
+
+        dead_letter
+            .write_source(&DeadLetterSource {
+                sink: name.to_string(),
+                container: self.info.clone(),
+            })
+            .await?;
+
+        Ok(dead_letter)
+    }
 }
 
 
 #[async_trait::async_trait]
 impl<T: Ingest + Sync + Send> FifoProcessor for Task<T> {
-    fn new(config: Config) -> Self {
+    fn new(config: Config, info: ContainerInfo, clients: HttpClients) -> Self {
         Self {
             config,
-            _t: std::marker::PhantomData,
+            info,
+            clients,
+            sinks: Vec::new(),
+            fifo: None,
         }
     }
 
-    async fn process<P: Into<PathBuf> + Send>(self, path: P, receiver: Receiver<bool>) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
-        let path = path.into();
+    async fn open<P: Into<PathBuf> + Send>(&mut self, path: P) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let mut sinks = Vec::new();
+
+        for sink in self.config.sinks(&self.info.config)? {
+            let client = T::new(&sink.config, &self.info, &self.clients)
+                .map_err(|e| format!("Failed to create {} sink: {}", sink.name, e))?;
+
+            sinks.push((sink, client));
+        }
+
+        self.sinks = sinks;
+
         let fp = tokio::fs::OpenOptions::new()
             .read(true)
-            .open(&path)
+            .open(path.into())
             .await?;
+
+        self.fifo = Some(fp);
+        Ok(())
+    }
+
+    async fn process<P: Into<PathBuf> + Send>(mut self, path: P, receiver: Receiver<bool>) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let path = path.into();
+
+        if self.fifo.is_none() || self.sinks.is_empty() {
+            self.open(&path)
+                .await?;
+        }
+
+        let fp = self.fifo
+            .take()
+            .expect("FIFO is opened above");
         let fpath = format!("{:?}", path);
 
-        tokio::select! {
+        // A failure to keep a local copy shouldn't prevent shipping logs
+        let store = LogStore::open(&self.config.state_dir, &self.info.container_id, StoreOptions::from(&self.config))
+            .await
+            .map_err(BoxedError::from)
+            .log_error(format!("Failed to open local log store for container {}", self.info.container_id))
+            .ok();
+
+        // set if buffered messages take too long to send once logging stops, so the
+        // senders spool or dead-letter them instead
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let mut outputs = Vec::new();
+
+        for (sink, client) in std::mem::take(&mut self.sinks) {
+            outputs.push(self.output(sink, client, &stop_rx).await);
+        }
+
+        let processing = process_file(&self.config, &self.info, fp, outputs, store);
+
+        tokio::pin!(processing);
+
+        let results = tokio::select! {
             _ = receiver => {
                 info!(
                     fpath = fpath,
                     "Received stop signal for {}", fpath,
                 );
 
+                // Docker closes the FIFO once logging stops; give buffered messages a
+                // chance to be sent before giving up on them
+                if tokio::time::timeout(DRAIN_TIMEOUT, &mut processing).await.is_err() {
+                    tracing::warn!(
+                        fpath = fpath,
+                        "Timed out sending buffered messages for {}; spooling them", fpath,
+                    );
+
+                    let _ = stop_tx.send(true);
+
+                    if tokio::time::timeout(DRAIN_TIMEOUT, &mut processing).await.is_err() {
+                        tracing::warn!(
+                            fpath = fpath,
+                            "Timed out spooling buffered messages for {}", fpath,
+                        );
+                    }
+                }
+
                 Ok(())
             },
-            results = process_file::<tokio::fs::File, T>(&self.config, fp) => {
+            results = &mut processing => {
                 results
                     .log_error(format!("Processing file {} resulted in error", fpath))
             }
+        };
+
+        // however processing ended, the store is only kept if asked to
+        let removed = match self.config.keep_local {
+            true => Ok(()),
+            false => LogStore::remove(&self.config.state_dir, &self.info.container_id)
+                .await
+                .map_err(BoxedError::from)
+                .log_error(format!("Failed to remove local log store for container {}", self.info.container_id)),
+        };
+
+        results.and(removed)
+    }
+}
+
+
+// Number of entries buffered between the FIFO reader and processing
+const ENTRY_BUFFER: usize = 1024;
+
+// How long buffered messages may take to send once logging has stopped
+const DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+
+/// A sink's sender, with the settings its messages are queued and batched with
+struct Output<T> {
+    name: String,
+    config: Config,
+    sender: Sender<T>,
+    spill: Option<Spool>,
+}
+
+impl<T> Output<T> {
+    fn new(name: &str, config: &Config, sender: Sender<T>) -> Self {
+        Self {
+            name: name.to_string(),
+            config: config.clone(),
+            sender,
+            spill: None,
         }
     }
 }
 
 
-async fn process_file<A: AsyncReadExt, T: Ingest>(config: &Config, file: A) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let mut reader = crate::reader::Reader::new(file);
-    let mut client = T::new(&config.log_ingest_api);
+/// Reads entries from the FIFO, converts them to LogMessages and sends them in batches
+/// to each sink.  Each stage runs as its own future, connected by bounded queues, so a
+/// slow sink doesn't hold up reading the FIFO until its message queue is full; what
+/// happens then depends on the sink's QueuePolicy, which can only block with one sink.
+async fn process_file<A: AsyncReadExt, T: Ingest>(config: &Config, info: &ContainerInfo, file: A, outputs: Vec<Output<T>>, store: Option<LogStore>) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let (entry_tx, entry_rx) = tokio::sync::mpsc::channel::<LogEntry>(ENTRY_BUFFER);
+    let mut queues = Vec::new();
+    let mut sending = Vec::new();
+
+    for output in outputs {
+        let (message_tx, message_rx) = queue::channel(output.config.queue_size, output.config.queue_policy(), output.spill);
+        let span = info_span!("sink", sink = output.name);
+
+        queues.push(message_tx);
+        sending.push(
+            send_messages(output.config, info, output.sender, message_rx)
+                .instrument(span)
+        );
+    }
+
+    // Reading runs as its own future so waiting on timers never cancels a read part way
+    // through a frame.
+    let (read_results, process_results, _) = tokio::join!(
+        read_entries(config, file, entry_tx),
+        process_entries(config, info, entry_rx, queues, store),
+        join_all(sending),
+    );
+
+    process_results?;
+    read_results?;
+
+    Ok(())
+}
+
+
+async fn read_entries<A: AsyncReadExt>(config: &Config, file: A, tx: tokio::sync::mpsc::Sender<LogEntry>) -> Result<(), ReaderError> {
+    let mut reader = crate::reader::Reader::new(file)
+        .with_max_frame_size(config.max_frame_size.0 as usize)
+        .with_skip_invalid(config.skip_invalid_frames);
+
+    // If empty, we received EOF
+    while let Some(entry) = reader.next().await? {
+        if tx.send(entry).await.is_err() {
+            break; // processing has stopped
+        }
+    }
+
+    if reader.skipped() > 0 {
+        tracing::warn!(
+            skipped = reader.skipped(),
+            "Skipped {} invalid frames", reader.skipped(),
+        );
+    }
+
+    Ok(())
+}
+
+
+async fn process_entries(config: &Config, info: &ContainerInfo, mut rx: tokio::sync::mpsc::Receiver<LogEntry>, tx: Vec<QueueSender>, mut store: Option<LogStore>) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let mut reassembler = Reassembler::new(
+        config.partial_max_size.0 as usize,
+        config.partial_timeout.0,
+    );
+    let context = info.context();
 
     loop {
-        let log_entry = reader
-            .next()
-            .await?;
-        
-        // TODO: This isn't super efficient.  We should probably use a MPSC channel to send the messages
-        // on a separate green thread.  For a first pass, this is fine.
+        let log_entry = match reassembler.next_deadline() {
+            Some(deadline) => match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(log_entry) => log_entry,
+                Err(_) => {
+                    for entry in reassembler.flush_expired(Instant::now()) {
+                        queue(&tx, &context, entry).await?;
+                    }
+
+                    continue;
+                },
+            },
+            None => rx.recv().await,
+        };
+
         match log_entry {
             Some(entry) => {
-                let message = LogMessage::try_from(entry)?; // TODO: select appropriate error type
-                let results = client
-                    .ingest(message)
-                    .await;
-                
-                match results {
-                    Ok(_) => {},
-                    Err(e) => {
+                if let Some(store) = store.as_mut() {
+                    if let Err(e) = store.append(&entry).await {
                         tracing::error!(
                             error = ?e,
-                            "Error ingesting log message",
+                            "Error writing log entry to local store",
                         );
                     }
                 }
+
+                for entry in reassembler.push(entry) {
+                    queue(&tx, &context, entry).await?;
+                }
             },
-            None => { // If empty, we received EOF
-                break; 
+            None => { // The reader has stopped
+                break;
             }
         }
     }
 
+    for entry in reassembler.flush_all() {
+        queue(&tx, &context, entry).await?;
+    }
+
     Ok(())
 }
 
+
+/// Converts an entry to a LogMessage and adds it to each sink's queue.  Only a lone sink
+/// may block here; with several, `block` is refused so one can't hold up the others.
+async fn queue(tx: &[QueueSender], context: &Map<String, Value>, entry: LogEntry) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let message = LogMessage::from(entry)
+        .with_context(context);
+
+    for tx in tx {
+        tx.send(message.clone())
+            .await?;
+    }
+
+    Ok(())
+}
+
+
+async fn send_messages<T: Ingest>(config: Config, info: &ContainerInfo, mut sender: Sender<T>, mut rx: QueueReceiver) {
+    let mut batcher = Batcher::new(BatchOptions::from(&config));
+    let context = info.context();
+    let mut report_at = Instant::now() + config.drop_report_interval.0;
+
+    loop {
+        let deadline = [batcher.deadline(), sender.replay_at(), Some(report_at)]
+            .into_iter()
+            .flatten()
+            .min();
+
+        let message = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(message) => message,
+                Err(_) => {
+                    let now = Instant::now();
+
+                    if batcher.deadline().is_some_and(|deadline| deadline <= now) {
+                        sender.send(batcher.take()).await;
+                    }
+
+                    if sender.replay_at().is_some_and(|replay_at| replay_at <= now) {
+                        sender.replay().await;
+                    }
+
+                    if report_at <= now {
+                        report_at = now + config.drop_report_interval.0;
+
+                        for batch in report_dropped(&rx, &context, &mut batcher) {
+                            sender.send(batch).await;
+                        }
+                    }
+
+                    continue;
+                },
+            },
+            None => rx.recv().await,
+        };
+
+        match message {
+            Some(message) => {
+                for batch in batcher.push(message) {
+                    sender.send(batch).await;
+                }
+            },
+            None => { // Processing has stopped
+                break;
+            }
+        }
+    }
+
+    for batch in report_dropped(&rx, &context, &mut batcher) {
+        sender.send(batch).await;
+    }
+
+    if !batcher.is_empty() {
+        sender.send(batcher.take()).await;
+    }
+
+    sender.finish()
+        .await;
+}
+
+
+/// Adds a message reporting any messages dropped since the last report
+fn report_dropped(rx: &QueueReceiver, context: &Map<String, Value>, batcher: &mut Batcher) -> Vec<Vec<LogMessage>> {
+    let dropped = rx.take_dropped();
+
+    if dropped == 0 {
+        return Vec::new();
+    }
+
+    tracing::warn!(
+        dropped = dropped,
+        total = rx.dropped(),
+        "Queue full; dropped {} messages", dropped,
+    );
+
+    batcher.push(LogMessage::dropped(dropped).with_context(context))
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
 
-    use docker_protobuf::LogEntry;
+    use docker_protobuf::{LogEntry, PartialLogEntryMetadata};
     use envconfig::Envconfig;
     use lazy_static::lazy_static;
     use prost::Message;
 
-    use crate::{log::LogMessage, client::Ingest, config::Config};
+    use crate::{log::LogMessage, client::{HttpClients, Ingest, IngestError, IngestResponse}, config::Config, container::ContainerInfo, error::BoxedError};
 
-    use super::process_file;
+    use super::{process_file, report_dropped, FifoProcessor, ApiTask, LogStore, Output, Sender};
+    use crate::sink::SinkKind;
 
     struct TestIngestClient;
 
     #[async_trait::async_trait]
     impl Ingest for TestIngestClient {
-        fn new<S: AsRef<str>>(_: S) -> Self {
-            Self
+        fn new(_: &Config, _: &ContainerInfo, _: &HttpClients) -> Result<Self, BoxedError> {
+            Ok(Self)
         }
 
-        async fn ingest(&mut self, message: LogMessage) -> Result<serde_json::Value, reqwest::Error> {
-            HASHMAP
+        async fn ingest(&mut self, messages: &[LogMessage]) -> Result<IngestResponse, IngestError> {
+            let count = messages.len();
+
+            for message in messages {
+                BATCH_SIZES
+                    .lock()
+                    .unwrap()
+                    .entry(message.message.to_owned())
+                    .or_default()
+                    .push(count);
+            }
+
+            let mut hashmap = HASHMAP
                 .lock()
-                .unwrap()
-                .entry(message.message.to_owned())
-                .or_insert_with(Vec::new)
-                .push(message);
+                .unwrap();
 
-            Ok(serde_json::json!({"count": 1}))
+            for message in messages {
+                hashmap
+                    .entry(message.message.to_owned())
+                    .or_default()
+                    .push(message.clone());
+            }
+
+            Ok(IngestResponse { count: Some(count), rejected: Vec::new() })
         }
     }
 
@@ -178,6 +584,14 @@ mod tests {
             .unwrap()
     }
 
+    fn info() -> ContainerInfo {
+        ContainerInfo {
+            container_id: "test_container_id".to_string(),
+            container_name: "/test_container".to_string(),
+            ..Default::default()
+        }
+    }
+
 
     // this is going to be a stupid way to run these tests
     lazy_static! {
@@ -188,6 +602,9 @@ mod tests {
                 )
             )
         };
+
+        // sizes of the batches each message was sent in
+        static ref BATCH_SIZES: std::sync::Mutex<HashMap<String, Vec<usize>>> = std::sync::Mutex::new(HashMap::new());
     }
 
     #[tokio::test]
@@ -219,7 +636,7 @@ mod tests {
             .unwrap()
             .remove(&test_key);
 
-        process_file::<&[u8], TestIngestClient>(&config, &data[..])
+        process_file(&config, &info(), &data[..], vec![Output::new("ingest", &config, Sender::new(&config, TestIngestClient, None))], None)
             .await
             .expect("Processing file should not result in error");
         
@@ -232,5 +649,400 @@ mod tests {
             .clone();
 
         assert_eq!(messages.len(), 2);
+
+        let context = messages[0]
+            .context
+            .as_ref()
+            .unwrap();
+
+        assert_eq!(context["container_id"], "test_container_id");
+        assert_eq!(context["container_name"], "test_container");
+        assert_eq!(context["source"], "test");
+    }
+
+    #[tokio::test]
+    async fn test_process_removes_store() {
+        let dir = tempfile::tempdir()
+            .unwrap();
+        let fifo = dir.path().join("fifo");
+        let data = ReadBuilder::default()
+            .add(LogEntry {
+                source: "test".to_string(),
+                time_nano: 0,
+                line: b"test_process_removes_store".to_vec(),
+                partial: false,
+                partial_log_metadata: None,
+            })
+            .build();
+
+        std::fs::write(&fifo, data)
+            .unwrap();
+
+        let config = Config {
+            state_dir: dir.path().to_str().unwrap().to_string(),
+            sink: SinkKind::File,
+            keep_local: false,
+            ..config()
+        };
+        let (_stop, receiver) = tokio::sync::oneshot::channel();
+
+        // processing ends when the FIFO does, without a stop signal
+        ApiTask::new(config, info(), HttpClients::default())
+            .process(&fifo, receiver)
+            .await
+            .unwrap();
+
+        assert!(!LogStore::dir(dir.path(), &info().container_id).exists());
+    }
+
+    #[tokio::test]
+    async fn test_open_missing_fifo() {
+        let mut task = ApiTask::new(config(), info(), HttpClients::default());
+
+        assert!(task.open("/tmp/does/not/exist/fifo").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_process_file_partial_entries() {
+        let test_key = "test_process_file_partial_entries".to_string();
+        let (head, tail) = test_key.split_at(10);
+        let fragment = |line: &str, ordinal: i32, last: bool| LogEntry {
+            source: "test".to_string(),
+            time_nano: 0,
+            line: line
+                .as_bytes()
+                .to_vec(),
+            partial: true,
+            partial_log_metadata: Some(PartialLogEntryMetadata {
+                last,
+                id: "partial".to_string(),
+                ordinal,
+            }),
+        };
+
+        let data = ReadBuilder::default()
+            .add(fragment(head, 1, false))
+            .add(fragment(tail, 2, true))
+            .build();
+
+        HASHMAP
+            .lock()
+            .unwrap()
+            .remove(&test_key);
+
+        process_file(&config(), &info(), &data[..], vec![Output::new("ingest", &config(), Sender::new(&config(), TestIngestClient, None))], None)
+            .await
+            .expect("Processing file should not result in error");
+
+        let messages = HASHMAP
+            .lock()
+            .unwrap()
+            .get(&test_key)
+            .expect("Should have received the reassembled message")
+            .clone();
+
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_process_file_batches() {
+        let test_key = "test_process_file_batches".to_string();
+        let mut builder = ReadBuilder::default();
+
+        for _ in 0..5 {
+            builder = builder.add(LogEntry {
+                source: "test".to_string(),
+                time_nano: 0,
+                line: test_key
+                    .as_bytes()
+                    .to_vec(),
+                partial: false,
+                partial_log_metadata: None,
+            });
+        }
+
+        let data = builder.build();
+        let config = Config {
+            batch_max_count: 2,
+            ..config()
+        };
+
+        BATCH_SIZES
+            .lock()
+            .unwrap()
+            .remove(&test_key);
+
+        process_file(&config, &info(), &data[..], vec![Output::new("ingest", &config, Sender::new(&config, TestIngestClient, None))], None)
+            .await
+            .expect("Processing file should not result in error");
+
+        let mut sizes = BATCH_SIZES
+            .lock()
+            .unwrap()
+            .get(&test_key)
+            .expect("Should have received messages")
+            .clone();
+
+        // two full batches, then the remainder once the FIFO is closed
+        sizes.dedup();
+        assert_eq!(sizes, vec![2, 1]);
+    }
+
+    // fails with the given statuses, in order, then succeeds
+    struct FlakyIngestClient {
+        statuses: Vec<u16>,
+        attempts: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl Ingest for FlakyIngestClient {
+        fn new(_: &Config, _: &ContainerInfo, _: &HttpClients) -> Result<Self, BoxedError> {
+            Err("built by the test with its responses".into())
+        }
+
+        async fn ingest(&mut self, messages: &[LogMessage]) -> Result<IngestResponse, IngestError> {
+            let attempt = self.attempts
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+
+            match self.statuses.get(attempt) {
+                Some(status) => Err(IngestError::Status {
+                    status: reqwest::StatusCode::from_u16(*status).unwrap(),
+                    retry_after: None,
+                    body: String::new(),
+                }),
+                None => Ok(IngestResponse { count: Some(messages.len()), rejected: Vec::new() }),
+            }
+        }
+    }
+
+    async fn attempts(statuses: Vec<u16>) -> usize {
+        let data = ReadBuilder::default()
+            .add(LogEntry {
+                source: "test".to_string(),
+                time_nano: 0,
+                line: b"flaky".to_vec(),
+                partial: false,
+                partial_log_metadata: None,
+            })
+            .build();
+
+        let config = Config {
+            retry_initial_backoff: "1ms".parse().unwrap(),
+            ..config()
+        };
+        let attempts = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let client = FlakyIngestClient {
+            statuses,
+            attempts: attempts.clone(),
+        };
+
+        process_file(&config, &info(), &data[..], vec![Output::new("ingest", &config, Sender::new(&config, client, None))], None)
+            .await
+            .expect("Processing file should not result in error");
+
+        attempts.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn test_process_file_retries() {
+        assert_eq!(attempts(vec![503, 429]).await, 3);
+
+        // permanent failures aren't retried
+        assert_eq!(attempts(vec![400]).await, 1);
+
+        // gives up after max attempts
+        assert_eq!(attempts(vec![503; 10]).await, 5);
+    }
+
+    #[tokio::test]
+    async fn test_process_file_sinks() {
+        let data = ReadBuilder::default()
+            .add(LogEntry {
+                source: "test".to_string(),
+                time_nano: 0,
+                line: b"sinks".to_vec(),
+                partial: false,
+                partial_log_metadata: None,
+            })
+            .build();
+
+        let config = Config {
+            retry_initial_backoff: "1ms".parse().unwrap(),
+            ..config()
+        };
+        let healthy = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let failing = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let outputs = vec![
+            Output::new("failing", &config, Sender::new(&config, FlakyIngestClient { statuses: vec![503; 10], attempts: failing.clone() }, None)),
+            Output::new("healthy", &config, Sender::new(&config, FlakyIngestClient { statuses: Vec::new(), attempts: healthy.clone() }, None)),
+        ];
+
+        process_file(&config, &info(), &data[..], outputs, None)
+            .await
+            .expect("Processing file should not result in error");
+
+        // each sink retries on its own
+        assert_eq!(failing.load(std::sync::atomic::Ordering::SeqCst), 5);
+        assert_eq!(healthy.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_process_file_stopped_while_failing() {
+        let mut builder = ReadBuilder::default();
+
+        for _ in 0..10 {
+            builder = builder.add(LogEntry {
+                source: "test".to_string(),
+                time_nano: 0,
+                line: b"stopped".to_vec(),
+                partial: false,
+                partial_log_metadata: None,
+            });
+        }
+
+        let data = builder.build();
+        let dir = tempfile::tempdir()
+            .unwrap();
+        let config = Config {
+            batch_max_count: 2,
+            retry_max_attempts: 1000,
+            retry_initial_backoff: "10ms".parse().unwrap(),
+            retry_max_backoff: "10ms".parse().unwrap(),
+            ..config()
+        };
+        let spool = crate::spool::Spool::open(dir.path(), "container", crate::spool::SpoolOptions::from(&config))
+            .await
+            .unwrap();
+        let client = FlakyIngestClient {
+            statuses: vec![503; 100_000],
+            attempts: Default::default(),
+        };
+        let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+        let sender = Sender::new(&config, client, Some(spool))
+            .with_stop(stop_rx);
+        let info = info();
+
+        let stopping = async {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            stop_tx.send(true).unwrap();
+        };
+
+        // the endpoint never recovers, so only stopping lets processing finish
+        let (results, _) = tokio::join!(
+            tokio::time::timeout(
+                std::time::Duration::from_secs(5),
+                process_file(&config, &info, &data[..], vec![Output::new("ingest", &config, sender)], None),
+            ),
+            stopping,
+        );
+
+        results
+            .expect("Processing should finish once stopped")
+            .expect("Processing file should not result in error");
+
+        // the batch being retried, and everything queued behind it, was spooled
+        let spool = crate::spool::Spool::open(dir.path(), "container", crate::spool::SpoolOptions::from(&config))
+            .await
+            .unwrap();
+
+        assert_eq!(spool.len(), 10);
+    }
+
+    // never finishes sending a batch if stuck; otherwise counts the messages sent
+    struct StuckIngestClient {
+        stuck: bool,
+        sent: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl Ingest for StuckIngestClient {
+        fn new(_: &Config, _: &ContainerInfo, _: &HttpClients) -> Result<Self, BoxedError> {
+            Err("built by the test with its responses".into())
+        }
+
+        async fn ingest(&mut self, messages: &[LogMessage]) -> Result<IngestResponse, IngestError> {
+            if self.stuck {
+                std::future::pending::<()>().await;
+            }
+
+            self.sent
+                .fetch_add(messages.len(), std::sync::atomic::Ordering::SeqCst);
+
+            Ok(IngestResponse { count: Some(messages.len()), rejected: Vec::new() })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_file_stuck_sink() {
+        let mut builder = ReadBuilder::default();
+
+        for _ in 0..50 {
+            builder = builder.add(LogEntry {
+                source: "test".to_string(),
+                time_nano: 0,
+                line: b"stuck".to_vec(),
+                partial: false,
+                partial_log_metadata: None,
+            });
+        }
+
+        let data = builder.build();
+        let config = Config {
+            queue_size: 2,
+            queue_policy: Some("drop-newest".parse().unwrap()),
+            batch_max_count: 1,
+            ..config()
+        };
+        // room for every message, so none are dropped however slowly they're sent
+        let roomy = Config {
+            queue_size: 100,
+            ..config.clone()
+        };
+        let stuck = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let healthy = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let outputs = vec![
+            Output::new("stuck", &config, Sender::new(&config, StuckIngestClient { stuck: true, sent: stuck.clone() }, None)),
+            Output::new("healthy", &roomy, Sender::new(&roomy, StuckIngestClient { stuck: false, sent: healthy.clone() }, None)),
+        ];
+
+        // the stuck sink never finishes, so neither does processing
+        let results = tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            process_file(&config, &info(), &data[..], outputs, None),
+        ).await;
+
+        assert!(results.is_err());
+        assert_eq!(stuck.load(std::sync::atomic::Ordering::SeqCst), 0);
+        assert_eq!(healthy.load(std::sync::atomic::Ordering::SeqCst), 50);
+    }
+
+    #[tokio::test]
+    async fn test_report_dropped() {
+        let (tx, rx) = crate::queue::channel(1, crate::queue::QueuePolicy::DropNewest, None);
+        let mut batcher = crate::batch::Batcher::new(crate::batch::BatchOptions {
+            max_count: 1,
+            max_bytes: 1024 * 1024,
+            linger: std::time::Duration::from_secs(1),
+        });
+
+        for _ in 0..3 {
+            tx.send(LogMessage::dropped(0)).await.unwrap();
+        }
+
+        let batches = report_dropped(&rx, &info().context(), &mut batcher);
+
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0][0].message, "2 messages dropped");
+
+        let context = batches[0][0]
+            .context
+            .as_ref()
+            .unwrap();
+
+        assert_eq!(context["dropped"], 2);
+        assert_eq!(context["container_id"], "test_container_id");
+
+        // nothing has been dropped since
+        assert!(report_dropped(&rx, &info().context(), &mut batcher).is_empty());
     }
 }
@@ -0,0 +1,405 @@
+use std::{
+    error::Error,
+    fmt,
+};
+
+use hyper::client::connect::dns::Name;
+use reqwest::{
+    dns::{
+        Addrs,
+        Resolve,
+        Resolving,
+    },
+    Certificate,
+    ClientBuilder,
+    Identity,
+    Url,
+};
+use tokio_native_tls::native_tls;
+use tracing::warn;
+
+use crate::{
+    config::Config,
+    metrics,
+};
+
+
+/// TLS settings for an endpoint.  The files are read when the endpoint's client is
+/// built, not per request.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TlsOptions {
+    pub ca_file: Option<String>,
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+    pub server_name: Option<String>,
+    pub insecure: bool,
+}
+
+impl From<&Config> for TlsOptions {
+    fn from(config: &Config) -> Self {
+        Self {
+            ca_file: config.tls_ca_file.clone(),
+            cert_file: config.tls_cert_file.clone(),
+            key_file: config.tls_key_file.clone(),
+            server_name: config.tls_server_name
+                .clone()
+                .filter(|name| !name.trim().is_empty()),
+            insecure: config.tls_insecure,
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum TlsError {
+    ReadFile {
+        path: String,
+        err: std::io::Error,
+    },
+    // the file contains no PEM certificates
+    NoCertificates(String),
+    InvalidCertificate {
+        path: String,
+        err: Box<dyn Error + Send + Sync>,
+    },
+    InvalidIdentity {
+        path: String,
+        err: Box<dyn Error + Send + Sync>,
+    },
+    InvalidServerName(String),
+    // a connector for raw TLS streams couldn't be built
+    Connector(native_tls::Error),
+}
+
+impl Error for TlsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TlsError::ReadFile { err, .. } => Some(err),
+            TlsError::InvalidCertificate { err, .. } => Some(err.as_ref()),
+            TlsError::InvalidIdentity { err, .. } => Some(err.as_ref()),
+            TlsError::Connector(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TlsError::ReadFile { path, err } => write!(f, "Failed to read {}: {}", path, err),
+            TlsError::NoCertificates(path) => write!(f, "No PEM certificates found in {}", path),
+            TlsError::InvalidCertificate { path, err } => write!(f, "Invalid certificate in {}: {}", path, err),
+            TlsError::InvalidIdentity { path, err } => write!(f, "Invalid client certificate or key in {}: {}", path, err),
+            TlsError::InvalidServerName(name) => write!(f, "Invalid TLS server name: {}", name),
+            TlsError::Connector(err) => write!(f, "Failed to build TLS connector: {}", err),
+        }
+    }
+}
+
+
+fn read_file(path: &str) -> Result<Vec<u8>, TlsError> {
+    std::fs::read(path)
+        .map_err(|err| TlsError::ReadFile {
+            path: path.to_string(),
+            err,
+        })
+}
+
+/// Splits a PEM bundle into its certificates; reqwest only parses one at a time
+fn pem_certificates(pem: &str) -> Vec<&str> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let mut certificates = Vec::new();
+    let mut rest = pem;
+
+    while let Some(start) = rest.find(BEGIN) {
+        let end = match rest[start..].find(END) {
+            Some(end) => start + end + END.len(),
+            None => break,
+        };
+
+        certificates.push(&rest[start..end]);
+        rest = &rest[end..];
+    }
+
+    certificates
+}
+
+/// Reads a CA bundle's certificates, failing if it has none
+fn read_ca_file(path: &str) -> Result<Vec<String>, TlsError> {
+    let pem = String::from_utf8_lossy(&read_file(path)?)
+        .into_owned();
+    let certificates = pem_certificates(&pem)
+        .into_iter()
+        .map(|certificate| certificate.to_string())
+        .collect::<Vec<_>>();
+
+    if certificates.is_empty() {
+        return Err(TlsError::NoCertificates(path.to_string()));
+    }
+
+    Ok(certificates)
+}
+
+
+/// Resolves the server name override to the addresses of the endpoint's actual host,
+/// so requests can be addressed to the server name without it being in DNS
+struct ServerNameResolver {
+    server_name: String,
+    host: String,
+}
+
+impl Resolve for ServerNameResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = match name.as_str() == self.server_name {
+            true => self.host.as_str(),
+            false => name.as_str(),
+        };
+        // the port is replaced by the connector
+        let address = format!("{}:0", host);
+
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host(address)
+                .await?;
+
+            Ok(Box::new(addrs) as Addrs)
+        })
+    }
+}
+
+
+impl TlsOptions {
+    /// Adds the CA bundle, client identity and verification settings to a client builder
+    pub fn configure(&self, builder: ClientBuilder, endpoint: &str) -> Result<ClientBuilder, TlsError> {
+        let mut builder = builder;
+
+        if let Some(path) = self.ca_file.as_ref() {
+            for certificate in read_ca_file(path)? {
+                let certificate = Certificate::from_pem(certificate.as_bytes())
+                    .map_err(|err| TlsError::InvalidCertificate {
+                        path: path.to_string(),
+                        err: err.into(),
+                    })?;
+
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        if let (Some(cert_file), Some(key_file)) = (self.cert_file.as_ref(), self.key_file.as_ref()) {
+            let identity = Identity::from_pkcs8_pem(&read_file(cert_file)?, &read_file(key_file)?)
+                .map_err(|err| TlsError::InvalidIdentity {
+                    path: cert_file.to_string(),
+                    err: err.into(),
+                })?;
+
+            builder = builder.identity(identity);
+        }
+
+        if let Some(server_name) = self.server_name.as_ref() {
+            let host = Url::parse(endpoint)
+                .ok()
+                .and_then(|url| url.host_str().map(|host| host.to_string()))
+                .ok_or_else(|| TlsError::InvalidServerName(server_name.to_string()))?;
+
+            builder = builder.dns_resolver(std::sync::Arc::new(ServerNameResolver {
+                server_name: server_name.to_string(),
+                host,
+            }));
+        }
+
+        if self.insecure {
+            warn!(endpoint = %metrics::label(endpoint), "TLS certificate verification is disabled");
+
+            builder = builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        }
+
+        Ok(builder)
+    }
+
+    /// Builds a connector with the same settings for TLS streams which aren't HTTP, e.g.
+    /// syslog.  Connections should verify `server_name` when it is set.
+    pub fn connector(&self, endpoint: &str) -> Result<tokio_native_tls::TlsConnector, TlsError> {
+        let mut builder = native_tls::TlsConnector::builder();
+
+        if let Some(path) = self.ca_file.as_ref() {
+            for certificate in read_ca_file(path)? {
+                let certificate = native_tls::Certificate::from_pem(certificate.as_bytes())
+                    .map_err(|err| TlsError::InvalidCertificate {
+                        path: path.to_string(),
+                        err: err.into(),
+                    })?;
+
+                builder.add_root_certificate(certificate);
+            }
+        }
+
+        if let (Some(cert_file), Some(key_file)) = (self.cert_file.as_ref(), self.key_file.as_ref()) {
+            let identity = native_tls::Identity::from_pkcs8(&read_file(cert_file)?, &read_file(key_file)?)
+                .map_err(|err| TlsError::InvalidIdentity {
+                    path: cert_file.to_string(),
+                    err: err.into(),
+                })?;
+
+            builder.identity(identity);
+        }
+
+        if self.insecure {
+            warn!(endpoint = %metrics::label(endpoint), "TLS certificate verification is disabled");
+
+            builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        }
+
+        builder
+            .build()
+            .map(tokio_native_tls::TlsConnector::from)
+            .map_err(TlsError::Connector)
+    }
+
+    /// Returns the URL requests to `endpoint` are sent to.  With a server name override
+    /// the host is replaced by the server name, so it is used for both SNI and
+    /// certificate verification; connections still go to the endpoint's host.
+    pub fn url(&self, endpoint: &str) -> String {
+        let server_name = match self.server_name.as_ref() {
+            Some(server_name) => server_name,
+            None => return endpoint.to_string(),
+        };
+
+        let mut url = match Url::parse(endpoint) {
+            Ok(url) => url,
+            Err(_) => return endpoint.to_string(),
+        };
+
+        if url.set_host(Some(server_name)).is_err() {
+            return endpoint.to_string();
+        }
+
+        // Url adds a trailing slash to an empty path
+        match endpoint.ends_with('/') {
+            true => url.to_string(),
+            false => url
+                .as_str()
+                .trim_end_matches('/')
+                .to_string(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    // self-signed, CN=ingest.internal
+    const CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----
+MIIDFzCCAf+gAwIBAgIUREaQqvb/msdQA38CXAap5qeTFEUwDQYJKoZIhvcNAQEL
+BQAwGjEYMBYGA1UEAwwPaW5nZXN0LmludGVybmFsMCAXDTI2MTAxODA0MTE1N1oY
+DzIxMjYwOTI0MDQxMTU3WjAaMRgwFgYDVQQDDA9pbmdlc3QuaW50ZXJuYWwwggEi
+MA0GCSqGSIb3DQEBAQUAA4IBDwAwggEKAoIBAQC478PxTPnq45h4drX0h7rXwdhm
+Q1e5e9Be8+dpb+tFLByBvUXB7eRqE4RLIEXGAq29FBgBcCcpeCGaNDtdn6eC7Jg0
+soK3uF91MyeCqPw2NrecEDnW9gBB1mkcB+Uua3fk/zCocflsYtgW8sz7hp/dzcWt
+bSU3BAqJUj+aDbAfcv8QUsPvx1SC4BUVasbYUkHn7TBga6l4Nb6mvO8erVk2CMHj
+5bCZMtdZaXsXXoy5NlSTOA6wNBR7y8kosBRAN+WY+vbb4q2W56NSgl08qg4FE/xo
+W1iJkJGXFN+ddJqQpR0HUAX1sxOvvHimSrmQG/28eGeH0Riiv/hXGFbyRT9rAgMB
+AAGjUzBRMB0GA1UdDgQWBBS3dEpEe4oZy/bMPUMJnJ11VXp5pTAfBgNVHSMEGDAW
+gBS3dEpEe4oZy/bMPUMJnJ11VXp5pTAPBgNVHRMBAf8EBTADAQH/MA0GCSqGSIb3
+DQEBCwUAA4IBAQAPwCMhAlx7+OsqcJxZz2F677igoDggTeKk8WB0aOb+3g+rstS9
+5CKa4bzm/NLb/+9WxDVXDQE+NYH6+pEii3qfzPDdDLTLbBi4/RSKq0eMhnQTHi5u
+vYmteMi/VJgTxya6TO3V221UCQ/mhkhpDGfyDHEO6XDek541HlMHFn/vOV0nuSAs
+Nyh3Y2ZvnNUFuHaL5ZXVHUpMIftZVV28jCRCTOtJFFbE8Pz1VcII79rekdPdGskL
+E92dVVnbyi/TQQw6Uu8kHzLo+w279+ay0VPooERSxgLBiM3pWo6I/M2idNUDe6eS
+RZo2JdZGKQ6pC3PN/vO0Xb5DKUwhFolJmF1E
+-----END CERTIFICATE-----";
+
+    fn temp_file(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new()
+            .unwrap();
+
+        write!(file, "{}", contents).unwrap();
+        file
+    }
+
+    fn options(ca_file: &tempfile::NamedTempFile) -> TlsOptions {
+        TlsOptions {
+            ca_file: Some(ca_file.path().to_str().unwrap().to_string()),
+            ..TlsOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_pem_certificates() {
+        let bundle = format!("# internal CA\n{}\n\n{}\n", CERTIFICATE, CERTIFICATE);
+
+        assert_eq!(pem_certificates(&bundle), vec![CERTIFICATE, CERTIFICATE]);
+        assert!(pem_certificates("not a certificate").is_empty());
+    }
+
+    #[test]
+    fn test_ca_bundle() {
+        let file = temp_file(&format!("{}\n{}\n", CERTIFICATE, CERTIFICATE));
+        let builder = options(&file)
+            .configure(reqwest::Client::builder(), "https://ingest:8443")
+            .unwrap();
+
+        assert!(builder.build().is_ok());
+    }
+
+    #[test]
+    fn test_connector() {
+        let file = temp_file(CERTIFICATE);
+
+        assert!(options(&file).connector("tls://siem:6514").is_ok());
+
+        let empty = temp_file("");
+
+        assert!(matches!(options(&empty).connector("tls://siem:6514"), Err(TlsError::NoCertificates(_))));
+    }
+
+    #[test]
+    fn test_invalid_ca_file() {
+        let empty = temp_file("");
+        let results = options(&empty).configure(reqwest::Client::builder(), "https://ingest:8443");
+
+        assert!(matches!(results, Err(TlsError::NoCertificates(_))));
+
+        let missing = TlsOptions {
+            ca_file: Some("/does/not/exist".to_string()),
+            ..TlsOptions::default()
+        };
+        let results = missing.configure(reqwest::Client::builder(), "https://ingest:8443");
+
+        assert!(matches!(results, Err(TlsError::ReadFile { .. })));
+    }
+
+    #[test]
+    fn test_invalid_identity() {
+        let cert = temp_file(CERTIFICATE);
+        let key = temp_file("not a key");
+        let options = TlsOptions {
+            cert_file: Some(cert.path().to_str().unwrap().to_string()),
+            key_file: Some(key.path().to_str().unwrap().to_string()),
+            ..TlsOptions::default()
+        };
+        let results = options.configure(reqwest::Client::builder(), "https://ingest:8443");
+
+        assert!(matches!(results, Err(TlsError::InvalidIdentity { .. })));
+    }
+
+    #[test]
+    fn test_server_name_url() {
+        let options = TlsOptions {
+            server_name: Some("ingest.internal".to_string()),
+            ..TlsOptions::default()
+        };
+
+        assert_eq!(options.url("https://10.0.0.5:8443"), "https://ingest.internal:8443");
+        assert_eq!(options.url("https://10.0.0.5:8443/v1/"), "https://ingest.internal:8443/v1/");
+        assert_eq!(TlsOptions::default().url("https://10.0.0.5:8443"), "https://10.0.0.5:8443");
+    }
+}